@@ -5,7 +5,7 @@ use std::sync::mpsc::TryRecvError;
 use reverie::util::{cast_slice, res};
 
 use reverie::engine::{
-    camera::{Camera, CameraController, Projection},
+    camera::{Camera, CameraController, CameraPose, Projection},
     renderer::{Renderer, Pass},
     context::Context,
     input::InputState,
@@ -13,7 +13,10 @@ use reverie::engine::{
     registry::Registry,
     scene::Scene,
     asset::texture::Texture,
+    components::{registry::ComponentRegistry, camera::CameraComponent, transform::TransformComponent},
 };
+use cg::SquareMatrix;
+use specs::WorldExt;
 
 pub struct App {
     pub context: Context,
@@ -23,6 +26,9 @@ pub struct App {
 
     camera: Camera,
     camera_controller: CameraController,
+    // Follows the scene camera selected in the viewport.
+    scene_camera: Camera,
+    use_scene_camera: bool,
 
     scene: Scene,
 
@@ -36,11 +42,12 @@ impl App {
         env_logger::init();
 
         let context = Context::new(&window.window).await;
-        let imgui = Imgui::new(&window.window, &context.device, &context.queue);
-
-        let renderer = Renderer::new(&context.device, &context.config, &imgui.viewport.texture.size()); 
+        let mut imgui = Imgui::new(&window.window, &context.device, &context.queue);
 
         let mut registry = Registry::new(context.device.clone(), context.queue.clone(), imgui.renderer.clone());
+        imgui.viewport.settings.post_processing.resolve(&mut registry);
+
+        let renderer = Renderer::new(&context.device, &registry.layouts, &context.config, &imgui.viewport.texture.size()); 
 
         Texture::load_defaults(&context.device, &context.queue);
 
@@ -53,17 +60,21 @@ impl App {
         registry.get_texture(7403896815389001851, false);
         registry.get_texture(17574920008220227172, false);
 
-        let camera = Camera::new(&context.device, &renderer.camera_bind_group_layout, (0.0, 5.0, 10.0), cg::Deg(-90.0), cg::Deg(-20.0), 
+        let camera = Camera::new(&context.device, &registry.layouts.camera, (0.0, 5.0, 10.0), cg::Deg(-90.0), cg::Deg(-20.0), 
+            Projection::new(context.config.width, context.config.height, cg::Deg(45.0), 0.1, 100.0));
+        let camera_controller = CameraController::new(imgui.viewport.settings.camera_speed, imgui.viewport.settings.camera_sensitivity);
+        let scene_camera = Camera::new(&context.device, &registry.layouts.camera, (0.0, 0.0, 0.0), cg::Deg(-90.0), cg::Deg(0.0), 
             Projection::new(context.config.width, context.config.height, cg::Deg(45.0), 0.1, 100.0));
-        let camera_controller = CameraController::new(4.0, 0.5);
 
-        let scene = Scene::new(res("scenes/first.revscene"), &mut registry, &context.device, &context.queue, &camera);
+        let scene = Scene::new(res("scenes/first.revscene"), Arc::new(ComponentRegistry::new()), &mut registry, &context.device, &context.queue, &camera).unwrap();
 
         Self {
             context,
             input,
             camera,
             camera_controller,
+            scene_camera,
+            use_scene_camera: false,
             scene,
             renderer,
             imgui,
@@ -91,25 +102,63 @@ impl App {
         }));
         self.renderer.resize(&self.context.device, &extent);
         self.camera.projection.resize(extent.width, extent.height);
+        self.scene_camera.projection.resize(extent.width, extent.height);
     }
 
     fn resize(&mut self, new_window_size: winit::dpi::PhysicalSize<u32>) {
         if new_window_size.width > 0 && new_window_size.height > 0 {
             self.context.config.width = new_window_size.width;
             self.context.config.height = new_window_size.height;
-            self.context.surface.configure(&self.context.device, &self.context.config);
+            self.context.surface.as_ref().unwrap().configure(&self.context.device, &self.context.config);
             
         }
     }
 
     fn update(&mut self, dt: instant::Duration) {
-        if self.imgui.viewport.active {
-            self.camera_controller.update_camera(&mut self.camera, dt, &self.input);
-        }
-        self.camera.update_uniform();
-        self.context.queue.write_buffer(&self.camera.buffer, 0, cast_slice(&[self.camera.uniform]));
+        self.scene.update_lights(&self.context.device, &self.context.queue);
+        self.scene.update(&self.context.queue);
+
+        let scene_camera = self.imgui.viewport.scene_camera(&self.scene);
+        self.use_scene_camera = scene_camera.is_some();
+        let camera = match scene_camera {
+            Some(entity) => {
+                let cameras = self.scene.world.read_storage::<CameraComponent>();
+                let transforms = self.scene.world.read_storage::<TransformComponent>();
+                let world = transforms.get(entity).map_or(cg::Matrix4::identity(), |transform| transform.get_matrix());
+                cameras.get(entity).unwrap().apply(&mut self.scene_camera, world);
+                &mut self.scene_camera
+            }
+            None => {
+                let viewport = &mut self.imgui.viewport;
+                self.camera_controller.speed = viewport.settings.camera_speed;
+                self.camera_controller.sensitivity = viewport.settings.camera_sensitivity;
+                self.camera.tone_mapping = viewport.settings.tone_mapping;
+                self.camera.post_processing = viewport.settings.post_processing.clone();
+                if let Some(preset) = viewport.preset.take() {
+                    // Apply the preset to find where it puts the camera, then ease there from the current pose.
+                    let from = CameraPose::from_camera(&self.camera);
+                    let distance = preset.apply(&mut self.camera);
+                    let to = CameraPose::from_camera(&self.camera);
+                    from.apply(&mut self.camera);
+                    // The projection kind switches at once, so its size does too.
+                    self.camera.projection.set_ortho_size(to.ortho_size);
+                    self.camera_controller.transition_to(to, distance);
+                }
+                if let Some((center, radius)) = viewport.frame.take() {
+                    self.camera_controller.frame(&self.camera, center, radius);
+                }
+                self.camera_controller.zoom(&mut self.camera, std::mem::take(&mut viewport.scroll));
+                self.camera_controller.update_camera(&mut self.camera, dt, &self.input, viewport.navigation);
+                &mut self.camera
+            }
+        };
+        camera.update_uniform();
+        self.context.queue.write_buffer(&camera.buffer, 0, cast_slice(&[camera.uniform]));
+        self.scene.light_manager.update_cascades(&self.context.queue, camera);
+        self.scene.light_manager.update_point_shadows(&self.context.queue, camera);
+        self.renderer.update_post_processing(&self.context.queue, camera, Some(dt.as_secs_f32()));
         
-        self.scene.skybox.as_ref().unwrap().update_projection(&self.camera, &self.context.queue);
+        self.scene.skybox.update_projection(camera, &self.context.queue);
 
         self.watcher.handle_events(&mut self.registry);
     }
@@ -133,7 +182,7 @@ impl App {
         } 
 
 
-        let output = self.context.surface.get_current_texture()?;
+        let output = self.context.surface.as_ref().unwrap().get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         
         let mut encoder = self.context.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -145,7 +194,8 @@ impl App {
         }
 
         let viewport_view = self.imgui.viewport.texture.create_view(&wgpu::TextureViewDescriptor::default());
-        self.renderer.draw(&viewport_view, &mut self.scene, &self.camera, &mut encoder)?;
+        let camera = if self.use_scene_camera { &self.scene_camera } else { &self.camera };
+        self.renderer.draw(&viewport_view, &mut self.scene, camera, &mut encoder);
         
         let texture = imgui_wgpu::Texture::from_raw_parts(
             &self.context.device, 
@@ -167,7 +217,7 @@ impl App {
         );
         
         self.imgui.renderer.lock().unwrap().textures.replace(imgui::TextureId::new(2), texture);
-        self.imgui.draw(&mut self.scene, &mut self.registry, &self.context.device, &self.context.queue, &view, &window, &mut encoder)?;
+        self.imgui.draw(&mut self.scene, &mut self.registry, &self.context.device, &self.context.queue, camera, &view, &window, &mut encoder)?;
 
         self.context.queue.submit([encoder.finish()]);
         output.present();
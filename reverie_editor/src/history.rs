@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Result};
+use reverie::engine::{
+    asset::material::Material,
+    components::material::update_entity_material,
+    registry::Registry,
+    scene::Scene,
+};
+use serde_yaml::Value;
+use uuid::Uuid;
+
+const MAX_COMMANDS: usize = 256;
+
+pub enum Command {
+    Component {
+        entity: Uuid,
+        name: String,
+        before: Option<Value>,
+        after: Option<Value>,
+    },
+    Material {
+        id: usize,
+        before: String,
+        after: String,
+    },
+    // Entities that were created (or deleted when `created` is false), stored as
+    // serialized scene yaml so they can be brought back with the same ids.
+    Entities {
+        roots: Vec<Uuid>,
+        parent: Option<Uuid>,
+        yaml: String,
+        created: bool,
+    },
+    RootOrder {
+        before: Vec<Uuid>,
+        after: Vec<Uuid>,
+    },
+    Batch(Vec<Command>),
+}
+
+impl Command {
+    fn merge(&mut self, other: &Command) -> bool {
+        match (self, other) {
+            (
+                Command::Component { entity, name, after, .. },
+                Command::Component { entity: other_entity, name: other_name, after: other_after, .. },
+            ) if entity == other_entity && name == other_name => {
+                *after = other_after.clone();
+                true
+            }
+            (
+                Command::Material { id, after, .. },
+                Command::Material { id: other_id, after: other_after, .. },
+            ) if id == other_id => {
+                *after = other_after.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn apply(&self, undo: bool, scene: &mut Scene, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        match self {
+            Command::Component { entity, name, before, after } => {
+                let entity = scene.find_entity(entity).ok_or_else(|| anyhow!("entity {} no longer exists", entity))?;
+                let value = if undo { before } else { after };
+                scene.set_component_value(name, entity, value.clone(), registry, device, queue)
+            }
+            Command::Material { id, before, after } => {
+                let yaml = if undo { before } else { after };
+                let material: Material = serde_yaml::from_str(yaml)?;
+                material.save(&registry.get_filepath(*id));
+                registry.load_material(*id, true);
+                update_entity_material(&scene.world, *id, registry);
+                Ok(())
+            }
+            Command::Entities { roots, parent, yaml, created } => {
+                if undo == *created {
+                    for root in roots {
+                        if let Some(entity) = scene.find_entity(root) {
+                            scene.delete_entity(entity, device);
+                        }
+                    }
+                } else {
+                    let parent = parent.and_then(|parent| scene.find_entity(&parent));
+                    scene.restore_entities(yaml, parent, registry, device, queue)?;
+                }
+                Ok(())
+            }
+            Command::RootOrder { before, after } => {
+                let order = if undo { before } else { after };
+                let roots = order.iter().filter_map(|id| scene.find_entity(id)).collect();
+                scene.set_root_order(roots);
+                Ok(())
+            }
+            Command::Batch(commands) => {
+                if undo {
+                    for command in commands.iter().rev() {
+                        command.apply(undo, scene, registry, device, queue)?;
+                    }
+                } else {
+                    for command in commands {
+                        command.apply(undo, scene, registry, device, queue)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+    merging: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            merging: false,
+        }
+    }
+
+    // Commands pushed with `merge` set are folded into the previous one while it
+    // touches the same data, so a whole drag becomes a single step.
+    pub fn push(&mut self, command: Command, merge: bool) {
+        self.redo.clear();
+
+        if self.merging && merge {
+            if let Some(last) = self.undo.last_mut() {
+                if last.merge(&command) {
+                    return;
+                }
+            }
+        }
+
+        self.undo.push(command);
+        if self.undo.len() > MAX_COMMANDS {
+            self.undo.remove(0);
+        }
+        self.merging = merge;
+    }
+
+    pub fn end_merge(&mut self) {
+        self.merging = false;
+    }
+
+    // A command that fails may have been applied only partly, so it's dropped along with everything
+    // that could be replayed on top of it.
+    pub fn undo(&mut self, scene: &mut Scene, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.merging = false;
+        if let Some(command) = self.undo.pop() {
+            match command.apply(true, scene, registry, device, queue) {
+                Ok(()) => self.redo.push(command),
+                Err(e) => {
+                    println!("{}", e);
+                    self.redo.clear();
+                }
+            }
+        }
+    }
+
+    pub fn redo(&mut self, scene: &mut Scene, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.merging = false;
+        if let Some(command) = self.redo.pop() {
+            match command.apply(false, scene, registry, device, queue) {
+                Ok(()) => self.undo.push(command),
+                Err(e) => {
+                    println!("{}", e);
+                    self.undo.clear();
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.merging = false;
+    }
+}
@@ -0,0 +1,377 @@
+use cg::{InnerSpace, SquareMatrix, Rotation3};
+use reverie::engine::{camera::{Camera, ProjectionKind}, components::transform::{TransformComponent, decompose}, scene::Scene};
+use serde_yaml::Value;
+use specs::{Entity, WorldExt};
+
+use crate::history::{History, Command};
+
+const AXIS_COLORS: [[f32; 4]; 3] = [[0.9, 0.2, 0.2, 1.0], [0.2, 0.8, 0.2, 1.0], [0.2, 0.4, 0.9, 1.0]];
+const ACTIVE_COLOR: [f32; 4] = [1.0, 0.85, 0.1, 1.0];
+const PICK_DISTANCE: f32 = 6.0;
+const SCREEN_SIZE: f32 = 0.15;
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GizmoSpace {
+    World,
+    Local,
+}
+
+struct Drag {
+    entity: Entity,
+    axis: usize,
+    mouse: [f32; 2],
+    position: cg::Vector3<f32>,
+    rotation: cg::Quaternion<f32>,
+    scale: cg::Vector3<f32>,
+    world: cg::Matrix4<f32>,
+    before: Option<Value>,
+}
+
+// Everything needed to map the selected entity between world and viewport space for one frame.
+struct Frame {
+    view_proj: cg::Matrix4<f32>,
+    min: [f32; 2],
+    size: [f32; 2],
+    origin: cg::Vector3<f32>,
+    length: f32,
+    toward_camera: cg::Vector3<f32>,
+}
+
+impl Frame {
+    fn project(&self, point: cg::Vector3<f32>) -> Option<[f32; 2]> {
+        world_to_screen(&self.view_proj, self.min, self.size, point)
+    }
+}
+
+// Maps a world space point onto the viewport image spanning `min`..`min + size`.
+pub fn world_to_screen(view_proj: &cg::Matrix4<f32>, min: [f32; 2], size: [f32; 2], point: cg::Vector3<f32>) -> Option<[f32; 2]> {
+    let clip = view_proj * point.extend(1.0);
+    if clip.w <= 0.0001 {
+        return None;
+    }
+
+    let ndc = clip.truncate() / clip.w;
+    Some([
+        min[0] + (ndc.x * 0.5 + 0.5) * size[0],
+        min[1] + (0.5 - ndc.y * 0.5) * size[1],
+    ])
+}
+
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub space: GizmoSpace,
+    pub snap: bool,
+    pub translate_snap: f32,
+    pub rotate_snap: f32,
+    pub scale_snap: f32,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn new() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            space: GizmoSpace::World,
+            snap: false,
+            translate_snap: 0.5,
+            rotate_snap: 15.0,
+            scale_snap: 0.1,
+            drag: None,
+        }
+    }
+
+    pub fn menu(&mut self, ui: &imgui::Ui) {
+        ui.menu("Gizmo", || {
+            if ui.menu_item_config("Translate").shortcut("W").selected(self.mode == GizmoMode::Translate).build() {
+                self.mode = GizmoMode::Translate;
+            }
+            if ui.menu_item_config("Rotate").shortcut("E").selected(self.mode == GizmoMode::Rotate).build() {
+                self.mode = GizmoMode::Rotate;
+            }
+            if ui.menu_item_config("Scale").shortcut("R").selected(self.mode == GizmoMode::Scale).build() {
+                self.mode = GizmoMode::Scale;
+            }
+            ui.separator();
+            if ui.menu_item_config("Local Space").shortcut("X").selected(self.space == GizmoSpace::Local).build() {
+                self.toggle_space();
+            }
+            ui.separator();
+            ui.checkbox("Snap", &mut self.snap);
+            imgui::Drag::new("Translate Snap").speed(0.01).range(0.001, 100.0).build(ui, &mut self.translate_snap);
+            imgui::Drag::new("Rotate Snap").speed(0.5).range(1.0, 180.0).build(ui, &mut self.rotate_snap);
+            imgui::Drag::new("Scale Snap").speed(0.01).range(0.001, 10.0).build(ui, &mut self.scale_snap);
+        });
+    }
+
+    pub fn shortcuts(&mut self, ui: &imgui::Ui) {
+        if self.drag.is_some() || ui.io().want_text_input || ui.is_mouse_down(imgui::MouseButton::Right) {
+            return;
+        }
+
+        if ui.is_key_pressed(imgui::Key::W) {
+            self.mode = GizmoMode::Translate;
+        } else if ui.is_key_pressed(imgui::Key::E) {
+            self.mode = GizmoMode::Rotate;
+        } else if ui.is_key_pressed(imgui::Key::R) {
+            self.mode = GizmoMode::Scale;
+        } else if ui.is_key_pressed(imgui::Key::X) {
+            self.toggle_space();
+        }
+    }
+
+    fn toggle_space(&mut self) {
+        self.space = match self.space {
+            GizmoSpace::World => GizmoSpace::Local,
+            GizmoSpace::Local => GizmoSpace::World,
+        };
+    }
+
+    // Draws the gizmo over the viewport image spanning `min`..`min + size` and applies any drag.
+    // Returns true while the mouse is over a handle or a drag is in progress.
+    pub fn ui(&mut self, ui: &imgui::Ui, scene: &mut Scene, entity: Entity, camera: &Camera, history: &mut History, queue: &wgpu::Queue, min: [f32; 2], size: [f32; 2], hovered: bool) -> bool {
+        let (world, parent_world) = {
+            let transforms = scene.world.read_component::<TransformComponent>();
+            let transform = match transforms.get(entity) {
+                Some(transform) => transform,
+                None => {
+                    self.drag = None;
+                    return false;
+                }
+            };
+            let parent_world = transform.data.parent
+                .and_then(|parent| transforms.get(parent))
+                .map(|parent| parent.get_matrix())
+                .unwrap_or(cg::Matrix4::identity());
+            (transform.get_matrix(), parent_world)
+        };
+
+        if self.drag.as_ref().map_or(false, |drag| drag.entity != entity) {
+            self.drag = None;
+        }
+
+        let origin = world.w.truncate();
+        let camera_position = cg::vec3(camera.position.x, camera.position.y, camera.position.z);
+        // Orthographic views keep the handles the size they would have at the matching perspective distance.
+        let (length, toward_camera) = match camera.projection.kind {
+            ProjectionKind::Perspective => ((camera_position - origin).magnitude() * SCREEN_SIZE, camera_position - origin),
+            ProjectionKind::Orthographic => (camera.projection.ortho_size() * SCREEN_SIZE / (camera.projection.fovy().0 * 0.5).tan(), -camera.forward()),
+        };
+        let frame = Frame {
+            view_proj: camera.projection.calc_matrix() * camera.calc_matrix(),
+            min,
+            size,
+            origin,
+            length,
+            toward_camera,
+        };
+
+        let center = match frame.project(origin) {
+            Some(center) => center,
+            None => return self.drag.is_some(),
+        };
+
+        let basis = match self.drag.as_ref() {
+            Some(drag) => drag.world,
+            None => world,
+        };
+        let axes = self.axes(basis);
+        let mouse = ui.io().mouse_pos;
+
+        let hovered_axis = match self.drag.as_ref() {
+            Some(drag) => Some(drag.axis),
+            None if hovered => self.pick(&frame, &axes, center, mouse),
+            None => None,
+        };
+
+        if self.drag.is_none() && hovered_axis.is_some() && ui.is_mouse_clicked(imgui::MouseButton::Left) {
+            let transforms = scene.world.read_component::<TransformComponent>();
+            let transform = transforms.get(entity).unwrap();
+            self.drag = Some(Drag {
+                entity,
+                axis: hovered_axis.unwrap(),
+                mouse,
+                position: transform.get_position(),
+                rotation: transform.get_rotation(),
+                scale: transform.get_scale(),
+                world,
+                before: None,
+            });
+            drop(transforms);
+            self.drag.as_mut().unwrap().before = scene.component_value("transform", entity).unwrap();
+        }
+
+        if let Some(drag) = self.drag.as_ref() {
+            if ui.is_mouse_down(imgui::MouseButton::Left) {
+                let (position, rotation, scale) = self.drag_transform(drag, &frame, &axes, center, mouse, parent_world);
+                scene.world.write_component::<TransformComponent>().get_mut(entity).unwrap().set_transform(position, rotation, scale);
+                scene.update_transform(entity, queue);
+            } else {
+                let drag = self.drag.take().unwrap();
+                let after = scene.component_value("transform", entity).unwrap();
+                if after != drag.before {
+                    history.push(Command::Component {
+                        entity: scene.entity_id(entity).unwrap(),
+                        name: "transform".to_string(),
+                        before: drag.before,
+                        after,
+                    }, false);
+                }
+            }
+        }
+
+        self.draw(ui, &frame, &axes, center, hovered_axis);
+
+        hovered_axis.is_some()
+    }
+
+    fn axes(&self, world: cg::Matrix4<f32>) -> [cg::Vector3<f32>; 3] {
+        if self.space == GizmoSpace::World && self.mode != GizmoMode::Scale {
+            return [cg::Vector3::unit_x(), cg::Vector3::unit_y(), cg::Vector3::unit_z()];
+        }
+
+        [world.x.truncate().normalize(), world.y.truncate().normalize(), world.z.truncate().normalize()]
+    }
+
+    fn pick(&self, frame: &Frame, axes: &[cg::Vector3<f32>; 3], center: [f32; 2], mouse: [f32; 2]) -> Option<usize> {
+        let mut closest = None;
+        let mut closest_distance = PICK_DISTANCE;
+
+        for (i, axis) in axes.iter().enumerate() {
+            let distance = match self.mode {
+                GizmoMode::Rotate => circle_points(frame, *axis).windows(2)
+                    .map(|segment| segment_distance(mouse, segment[0], segment[1]))
+                    .fold(f32::MAX, f32::min),
+                _ => match frame.project(frame.origin + axis * frame.length) {
+                    Some(end) => segment_distance(mouse, center, end),
+                    None => f32::MAX,
+                },
+            };
+
+            if distance < closest_distance {
+                closest_distance = distance;
+                closest = Some(i);
+            }
+        }
+
+        closest
+    }
+
+    fn drag_transform(&self, drag: &Drag, frame: &Frame, axes: &[cg::Vector3<f32>; 3], center: [f32; 2], mouse: [f32; 2], parent_world: cg::Matrix4<f32>) -> (cg::Vector3<f32>, cg::Quaternion<f32>, cg::Vector3<f32>) {
+        let axis = axes[drag.axis];
+        let delta = [mouse[0] - drag.mouse[0], mouse[1] - drag.mouse[1]];
+
+        // Movement along the axis as drawn on screen, in units of the axis handle length.
+        let along_axis = || {
+            let end = match frame.project(frame.origin + axis * frame.length) {
+                Some(end) => end,
+                None => return 0.0,
+            };
+            let screen_axis = [end[0] - center[0], end[1] - center[1]];
+            let length_squared = screen_axis[0] * screen_axis[0] + screen_axis[1] * screen_axis[1];
+            if length_squared < 1.0 {
+                return 0.0;
+            }
+            (delta[0] * screen_axis[0] + delta[1] * screen_axis[1]) / length_squared
+        };
+
+        match self.mode {
+            GizmoMode::Translate => {
+                let mut distance = along_axis() * frame.length;
+                if self.snap {
+                    distance = snap(distance, self.translate_snap);
+                }
+                let world_position = drag.world.w.truncate() + axis * distance;
+                let local_position = parent_world.invert().unwrap_or(cg::Matrix4::identity()) * world_position.extend(1.0);
+                (local_position.truncate(), drag.rotation, drag.scale)
+            }
+            GizmoMode::Rotate => {
+                let start = [drag.mouse[0] - center[0], drag.mouse[1] - center[1]];
+                let current = [mouse[0] - center[0], mouse[1] - center[1]];
+                let mut angle = (start[0] * current[1] - start[1] * current[0]).atan2(start[0] * current[0] + start[1] * current[1]).to_degrees();
+                // Screen y points down, so a clockwise drag is a positive angle; flip it when the axis faces the camera.
+                if axis.dot(frame.toward_camera) > 0.0 {
+                    angle = -angle;
+                }
+                if self.snap {
+                    angle = snap(angle, self.rotate_snap);
+                }
+
+                let world_rotation = decompose(drag.world).1;
+                let parent_rotation = decompose(parent_world).1;
+                let rotated = cg::Quaternion::from_axis_angle(axis, cg::Deg(angle)) * world_rotation;
+                (drag.position, parent_rotation.conjugate() * rotated, drag.scale)
+            }
+            GizmoMode::Scale => {
+                let mut factor = 1.0 + along_axis();
+                if self.snap {
+                    factor = 1.0 + snap(factor - 1.0, self.scale_snap);
+                }
+                let mut scale = drag.scale;
+                scale[drag.axis] = (scale[drag.axis] * factor).max(0.001);
+                (drag.position, drag.rotation, scale)
+            }
+        }
+    }
+
+    fn draw(&self, ui: &imgui::Ui, frame: &Frame, axes: &[cg::Vector3<f32>; 3], center: [f32; 2], active: Option<usize>) {
+        let draw_list = ui.get_window_draw_list();
+        draw_list.with_clip_rect_intersect(frame.min, [frame.min[0] + frame.size[0], frame.min[1] + frame.size[1]], || {
+            for (i, axis) in axes.iter().enumerate() {
+                let color = if active == Some(i) { ACTIVE_COLOR } else { AXIS_COLORS[i] };
+
+                match self.mode {
+                    GizmoMode::Rotate => {
+                        let points = circle_points(frame, *axis);
+                        draw_list.add_polyline(points, color).thickness(2.0).build();
+                    }
+                    GizmoMode::Translate | GizmoMode::Scale => {
+                        let end = match frame.project(frame.origin + axis * frame.length) {
+                            Some(end) => end,
+                            None => continue,
+                        };
+                        draw_list.add_line(center, end, color).thickness(2.5).build();
+                        if self.mode == GizmoMode::Translate {
+                            draw_list.add_circle(end, 5.0, color).filled(true).build();
+                        } else {
+                            draw_list.add_rect([end[0] - 4.0, end[1] - 4.0], [end[0] + 4.0, end[1] + 4.0], color).filled(true).build();
+                        }
+                    }
+                }
+            }
+            draw_list.add_circle(center, 3.0, [1.0, 1.0, 1.0, 1.0]).filled(true).build();
+        });
+    }
+}
+
+fn circle_points(frame: &Frame, axis: cg::Vector3<f32>) -> Vec<[f32; 2]> {
+    let helper = if axis.x.abs() < 0.9 { cg::Vector3::unit_x() } else { cg::Vector3::unit_y() };
+    let u = axis.cross(helper).normalize();
+    let v = axis.cross(u);
+
+    (0..=CIRCLE_SEGMENTS).filter_map(|i| {
+        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+        frame.project(frame.origin + (u * angle.cos() + v * angle.sin()) * frame.length)
+    }).collect()
+}
+
+fn segment_distance(point: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+    let ab = [b[0] - a[0], b[1] - a[1]];
+    let ap = [point[0] - a[0], point[1] - a[1]];
+    let length_squared = ab[0] * ab[0] + ab[1] * ab[1];
+    let t = if length_squared > 0.0 { ((ap[0] * ab[0] + ap[1] * ab[1]) / length_squared).clamp(0.0, 1.0) } else { 0.0 };
+    let closest = [a[0] + ab[0] * t - point[0], a[1] + ab[1] * t - point[1]];
+
+    (closest[0] * closest[0] + closest[1] * closest[1]).sqrt()
+}
+
+fn snap(value: f32, increment: f32) -> f32 {
+    (value / increment).round() * increment
+}
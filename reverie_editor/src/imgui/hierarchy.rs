@@ -1,47 +1,250 @@
-use reverie::engine::{scene::Scene, components::{transform::TransformComponent, name::Name, light::PointLight}};
-use specs::{Entity, WorldExt, Join, WriteStorage};
+use reverie::engine::{scene::Scene, components::{transform::TransformComponent, name::Name}, registry::Registry};
+use specs::{Entity, WorldExt, WriteStorage};
+use uuid::Uuid;
+
+use crate::history::{History, Command};
 
 use super::explorer::Explorer;
 
+const MARKER_COLOR: [f32; 4] = [0.3, 0.6, 1.0, 1.0];
+// Fraction of a node's height at its top and bottom that inserts next to it instead of parenting to it.
+const DROP_EDGE: f32 = 0.25;
+const ROOT_DROP_HEIGHT: f32 = 24.0;
+
+enum Action {
+    Create,
+    Move(Entity, Option<Entity>, usize),
+    Delete(Entity),
+    Duplicate(Entity),
+    Copy(Entity),
+    Paste,
+}
+
 pub struct Hierarchy {
     pub entity: Option<Entity>,
-    pub point_light_index: Option<usize>,
+    pub selection: Vec<Entity>,
+    clipboard: Option<String>,
+    action: Option<Action>,
 }
 
 impl Hierarchy {
     pub fn new() -> Self {
         Self {
             entity: None,
-            point_light_index: None,
+            selection: Vec::new(),
+            clipboard: None,
+            action: None,
+        }
+    }
+
+    // With `additive` the entity is toggled in the selection, otherwise it replaces it.
+    // `entity` always follows the most recently selected one.
+    pub fn select(&mut self, entity: Entity, additive: bool, explorer: &mut Explorer) {
+        explorer.selected_file = None;
+        explorer.material = None;
+
+        if !additive {
+            self.set_selection(Some(entity));
+            return;
+        }
+
+        match self.selection.iter().position(|selected| *selected == entity) {
+            Some(index) => {
+                self.selection.remove(index);
+                self.entity = self.selection.last().copied();
+            }
+            None => {
+                self.selection.push(entity);
+                self.entity = Some(entity);
+            }
         }
     }
 
-    pub fn ui<'a>(&mut self, ui: &'a imgui::Ui, scene: &mut Scene, explorer: &mut Explorer, device: &wgpu::Device) {
+    pub fn clear_selection(&mut self) {
+        self.set_selection(None);
+    }
+
+    pub fn is_selected(&self, entity: Entity) -> bool {
+        self.selection.contains(&entity)
+    }
+
+    pub fn retain_alive(&mut self, scene: &Scene) {
+        self.selection.retain(|entity| scene.world.is_alive(*entity));
+        if self.entity.map_or(false, |entity| !self.selection.contains(&entity)) {
+            self.entity = self.selection.last().copied();
+        }
+    }
+
+    fn set_selection(&mut self, entity: Option<Entity>) {
+        self.entity = entity;
+        self.selection = entity.into_iter().collect();
+    }
+
+    pub fn ui<'a>(&mut self, ui: &'a imgui::Ui, scene: &mut Scene, explorer: &mut Explorer, history: &mut History, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) {
         ui.window("Hierarchy").build(|| {
-            let mut point_light_index = 0;
+            let roots = scene.roots();
             let mut transforms = scene.world.write_component::<TransformComponent>();
-            for entity in scene.world.entities().join() {
-                if transforms.get(entity).unwrap().data.parent.is_none() {
-                    self.create_node(ui, scene, explorer, entity, &mut point_light_index, &mut transforms);
-                }
+            for (index, entity) in roots.into_iter().enumerate() {
+                self.create_node(ui, scene, explorer, entity, None, index, &mut transforms);
             }
             drop(transforms);
 
+            self.root_drop_zone(ui);
+
             ui.popup("create_object", || {
                 if ui.button("Create Object") {
-                    scene.create_entity(device);
+                    self.action = Some(Action::Create);
+                }
+                if self.clipboard.is_some() && ui.button("Paste") {
+                    self.action = Some(Action::Paste);
                 }
             });
 
-            if ui.is_window_hovered() && ui.is_mouse_clicked(imgui::MouseButton::Right) {
+            if ui.is_item_clicked_with_button(imgui::MouseButton::Right) || (ui.is_window_hovered() && !ui.is_any_item_hovered() && ui.is_mouse_clicked(imgui::MouseButton::Right)) {
                 ui.open_popup("create_object")
             }
+
+            if ui.is_window_focused() {
+                let ctrl = ui.io().key_ctrl;
+                if let Some(entity) = self.entity {
+                    if ui.is_key_pressed(imgui::Key::Delete) {
+                        self.action = Some(Action::Delete(entity));
+                    } else if ctrl && ui.is_key_pressed(imgui::Key::D) {
+                        self.action = Some(Action::Duplicate(entity));
+                    } else if ctrl && ui.is_key_pressed(imgui::Key::C) {
+                        self.action = Some(Action::Copy(entity));
+                    }
+                }
+                if ctrl && ui.is_key_pressed(imgui::Key::V) {
+                    self.action = Some(Action::Paste);
+                }
+            }
+        });
+
+        if let Some(action) = self.action.take() {
+            self.apply(action, scene, history, registry, device, queue);
+        }
+    }
+
+    fn apply(&mut self, action: Action, scene: &mut Scene, history: &mut History, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) {
+        match action {
+            Action::Create => {
+                let entity = scene.create_entity(device, registry);
+                record_created(scene, history, &[entity], None);
+                self.set_selection(Some(entity));
+            }
+            Action::Move(entity, parent, index) => {
+                let old_parent = scene.world.read_component::<TransformComponent>().get(entity).and_then(|transform| transform.data.parent);
+                let mut affected = vec![entity];
+                for other in [old_parent, parent].into_iter().flatten() {
+                    if !affected.contains(&other) {
+                        affected.push(other);
+                    }
+                }
+                let before: Vec<_> = affected.iter().map(|entity| scene.component_value("transform", *entity).unwrap()).collect();
+                let roots_before = root_ids(scene);
+
+                if scene.move_entity(entity, parent, index, queue) {
+                    let mut commands: Vec<Command> = affected.iter().zip(before).filter_map(|(entity, before)| {
+                        let after = scene.component_value("transform", *entity).unwrap();
+                        (after != before).then(|| Command::Component {
+                            entity: scene.entity_id(*entity).unwrap(),
+                            name: "transform".to_string(),
+                            before,
+                            after,
+                        })
+                    }).collect();
+                    let roots_after = root_ids(scene);
+                    if roots_after != roots_before {
+                        commands.push(Command::RootOrder { before: roots_before, after: roots_after });
+                    }
+                    if !commands.is_empty() {
+                        history.push(Command::Batch(commands), false);
+                    }
+                } else {
+                    println!("Cannot parent an entity to itself or one of its descendants");
+                }
+            }
+            Action::Delete(entity) => {
+                let deleted = scene.hierarchy(entity);
+                self.selection.retain(|selected| !deleted.contains(selected));
+                if self.entity.map_or(false, |selected| deleted.contains(&selected)) {
+                    self.entity = self.selection.last().copied();
+                }
+                let roots_before = root_ids(scene);
+                let command = scene.copy_entity(entity).map(|yaml| {
+                    let parent = scene.world.read_component::<TransformComponent>().get(entity).and_then(|transform| transform.data.parent);
+                    Command::Entities {
+                        roots: vec![scene.entity_id(entity).unwrap()],
+                        parent: parent.and_then(|parent| scene.entity_id(parent)),
+                        yaml,
+                        created: false,
+                    }
+                });
+                scene.delete_entity(entity, device);
+                match command {
+                    // The order goes first so undoing restores the entity before putting it back in place.
+                    Ok(command) => history.push(Command::Batch(vec![
+                        Command::RootOrder { before: roots_before, after: root_ids(scene) },
+                        command,
+                    ]), false),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            Action::Duplicate(entity) => {
+                let parent = scene.world.read_component::<TransformComponent>().get(entity).and_then(|transform| transform.data.parent);
+                match scene.duplicate_entity(entity, registry, device, queue) {
+                    Ok(entities) => {
+                        record_created(scene, history, &entities, parent);
+                        self.set_selection(entities.first().copied());
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            Action::Copy(entity) => {
+                match scene.copy_entity(entity) {
+                    Ok(yaml) => self.clipboard = Some(yaml),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            Action::Paste => {
+                if let Some(yaml) = &self.clipboard {
+                    match scene.paste_entities(yaml, None, registry, device, queue) {
+                        Ok(entities) => {
+                            record_created(scene, history, &entities, None);
+                            self.set_selection(entities.first().copied());
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    fn context_menu(&mut self, ui: &imgui::Ui, entity: Entity) {
+        let id = format!("entity_menu_{}", entity.id());
+        if ui.is_item_clicked_with_button(imgui::MouseButton::Right) {
+            ui.open_popup(&id);
+        }
+        ui.popup(&id, || {
+            if ui.menu_item_config("Duplicate").shortcut("Ctrl+D").build() {
+                self.action = Some(Action::Duplicate(entity));
+            }
+            if ui.menu_item_config("Copy").shortcut("Ctrl+C").build() {
+                self.action = Some(Action::Copy(entity));
+            }
+            if ui.menu_item_config("Paste").shortcut("Ctrl+V").enabled(self.clipboard.is_some()).build() {
+                self.action = Some(Action::Paste);
+            }
+            ui.separator();
+            if ui.menu_item_config("Delete").shortcut("Delete").build() {
+                self.action = Some(Action::Delete(entity));
+            }
         });
     }
 
-    fn create_node<'a>(&mut self, ui: &'a imgui::Ui, scene: &Scene, explorer: &mut Explorer, entity: Entity, point_light_index: &mut usize, transforms: &mut WriteStorage<TransformComponent>) {
+    fn create_node<'a>(&mut self, ui: &'a imgui::Ui, scene: &Scene, explorer: &mut Explorer, entity: Entity, parent: Option<Entity>, index: usize, transforms: &mut WriteStorage<TransformComponent>) {
         let names = scene.world.read_component::<Name>();
-        let point_light_component = scene.world.read_component::<PointLight>();
         let name = names.get(entity).unwrap();
 
         let mut flags = imgui::TreeNodeFlags::DEFAULT_OPEN | 
@@ -49,7 +252,7 @@ impl Hierarchy {
             imgui::TreeNodeFlags::OPEN_ON_ARROW | 
             imgui::TreeNodeFlags::SPAN_AVAIL_WIDTH;
 
-        if self.entity == Some(entity) {
+        if self.is_selected(entity) {
             flags |= imgui::TreeNodeFlags::SELECTED
         }
 
@@ -58,95 +261,113 @@ impl Hierarchy {
             .flags(flags)
             .build(|| {
                 opened = true;
-                if let Some(payload) = ui.drag_drop_source_config("Object").begin_payload(entity) {
-                    ui.text(name.0.as_str());
-                    payload.end();
-                }
-                match ui.drag_drop_target() {
-                    Some(target) => {
-                        match target.accept_payload::<Entity, _>("Object", imgui::DragDropFlags::empty()) {
-                            Some(Ok(payload_data)) => {
-                                let child_transform = transforms.get_mut(payload_data.data);
-                                let parent_option = child_transform.unwrap().data.parent;
-                                if parent_option.is_some() {                  
-                                    let old_parent = scene.world.entities().entity(parent_option.unwrap()).clone();
-                                    let old_parent_transform = transforms.get_mut(old_parent).unwrap();
-                                    old_parent_transform.data.children.retain(|&x| x != payload_data.data.id());
-                                }
-                                let parent_transform = transforms.get_mut(entity).unwrap();
-                                let parent_matrix = parent_transform.get_matrix();
-                                let child_transform = transforms.get_mut(payload_data.data).unwrap();
-                                child_transform.data.parent = Some(entity.id());
-                                child_transform.update_local_transformation(parent_matrix);
-                                
-                                let parent_transform = transforms.get_mut(entity);
-                                parent_transform.unwrap().data.children.push(payload_data.data.id());
-                            },
-                            Some(Err(e)) => {
-                                println!("{}", e);
-                            },
-                            _ => {},
-                        }
-                    }
-                    _ => {},
-                }
-                if ui.is_item_clicked() && !ui.is_item_toggled_open() {
-                    self.entity = Some(entity);
-                    explorer.selected_file = None;
-                    explorer.material = None;
-                    match point_light_component.get(entity) {
-                        Some(_) => self.point_light_index = Some(*point_light_index),
-                        None => self.point_light_index = None,
-                    }
-                }
+                self.node_item(ui, explorer, entity, name, parent, index);
 
-                for child in &transforms.get(entity).unwrap().data.children.clone() {
-                    self.create_node(ui, scene, explorer, scene.world.entities().entity(*child), point_light_index, transforms);
+                let children = transforms.get(entity).map(|transform| transform.data.children.clone()).unwrap_or_default();
+                for (child_index, child) in children.into_iter().enumerate() {
+                    self.create_node(ui, scene, explorer, child, Some(entity), child_index, transforms);
                 }
             });
         
         if !opened {
-            if let Some(payload) = ui.drag_drop_source_config("Object").begin_payload(entity) {
-                ui.text(name.0.as_str());
-                payload.end();
-            }
-            match ui.drag_drop_target() {
-                Some(target) => {
-                    match target.accept_payload::<Entity, _>("Object", imgui::DragDropFlags::empty()) {
-                        Some(Ok(payload_data)) => {
-                            let parent_transform = transforms.get_mut(entity).unwrap();
-                            let parent_matrix = parent_transform.get_matrix();
-                            let child_transform = transforms.get_mut(payload_data.data).unwrap();
-                            child_transform.data.parent = Some(entity.id());
-                            child_transform.update_local_transformation(parent_matrix);
-                            
-                            let parent_transform = transforms.get_mut(entity);
-                            parent_transform.unwrap().data.children.push(payload_data.data.id());
-
-                        },
-                        Some(Err(e)) => {
-                            println!("{}", e);
-                        },
-                        _ => {},
-                    }
-                }
-                _ => {},
-            }
-        
+            self.node_item(ui, explorer, entity, name, parent, index);
+        }
+    }
 
-            if ui.is_item_clicked() && !ui.is_item_toggled_open() {
-                self.entity = Some(entity);
-                explorer.selected_file = None;
-                explorer.material = None;
-                match point_light_component.get(entity) {
-                    Some(_) => self.point_light_index = Some(*point_light_index),
-                    None => self.point_light_index = None,
+    // Interaction for the tree node that was just submitted.
+    fn node_item(&mut self, ui: &imgui::Ui, explorer: &mut Explorer, entity: Entity, name: &Name, parent: Option<Entity>, index: usize) {
+        self.context_menu(ui, entity);
+        if let Some(payload) = ui.drag_drop_source_config("Object").begin_payload(entity) {
+            ui.text(name.0.as_str());
+            payload.end();
+        }
+        self.drop_target(ui, entity, parent, index);
+
+        if ui.is_item_clicked() && !ui.is_item_toggled_open() {
+            self.select(entity, ui.io().key_shift, explorer);
+        }
+    }
+
+    // Dropping on the top or bottom edge of a node inserts next to it, anywhere else parents to it.
+    fn drop_target(&mut self, ui: &imgui::Ui, entity: Entity, parent: Option<Entity>, index: usize) {
+        let target = match ui.drag_drop_target() {
+            Some(target) => target,
+            None => return,
+        };
+
+        let flags = imgui::DragDropFlags::ACCEPT_BEFORE_DELIVERY | imgui::DragDropFlags::ACCEPT_NO_DRAW_DEFAULT_RECT;
+        match target.accept_payload::<Entity, _>("Object", flags) {
+            Some(Ok(payload_data)) if payload_data.data != entity => {
+                let min = ui.item_rect_min();
+                let max = ui.item_rect_max();
+                let edge = (max[1] - min[1]) * DROP_EDGE;
+                let mouse = ui.io().mouse_pos[1];
+
+                let draw_list = ui.get_window_draw_list();
+                let action = if mouse < min[1] + edge {
+                    draw_list.add_line([min[0], min[1]], [max[0], min[1]], MARKER_COLOR).thickness(2.0).build();
+                    Action::Move(payload_data.data, parent, index)
+                } else if mouse > max[1] - edge {
+                    draw_list.add_line([min[0], max[1]], [max[0], max[1]], MARKER_COLOR).thickness(2.0).build();
+                    Action::Move(payload_data.data, parent, index + 1)
+                } else {
+                    draw_list.add_rect(min, max, MARKER_COLOR).build();
+                    Action::Move(payload_data.data, Some(entity), usize::MAX)
+                };
+
+                if payload_data.delivery {
+                    self.action = Some(action);
                 }
-            }
+            },
+            Some(Err(e)) => {
+                println!("{}", e);
+            },
+            _ => {},
         }
+    }
+
+    // The empty space below the tree; dropping an entity there moves it to the end of the top level.
+    fn root_drop_zone(&mut self, ui: &imgui::Ui) {
+        let available = ui.content_region_avail();
+        ui.invisible_button("root_drop_zone", [available[0].max(1.0), available[1].max(ROOT_DROP_HEIGHT)]);
 
-        if point_light_component.get(entity).is_some() {
-            *point_light_index += 1;
+        let target = match ui.drag_drop_target() {
+            Some(target) => target,
+            None => return,
+        };
+
+        let flags = imgui::DragDropFlags::ACCEPT_BEFORE_DELIVERY | imgui::DragDropFlags::ACCEPT_NO_DRAW_DEFAULT_RECT;
+        match target.accept_payload::<Entity, _>("Object", flags) {
+            Some(Ok(payload_data)) => {
+                let min = ui.item_rect_min();
+                let draw_list = ui.get_window_draw_list();
+                draw_list.add_line(min, [ui.item_rect_max()[0], min[1]], MARKER_COLOR).thickness(2.0).build();
+                draw_list.add_text([min[0] + 4.0, min[1] + 4.0], MARKER_COLOR, "Make root");
+
+                if payload_data.delivery {
+                    self.action = Some(Action::Move(payload_data.data, None, usize::MAX));
+                }
+            },
+            Some(Err(e)) => {
+                println!("{}", e);
+            },
+            _ => {},
         }
     }
-}
\ No newline at end of file
+}
+
+fn root_ids(scene: &Scene) -> Vec<Uuid> {
+    scene.roots().into_iter().filter_map(|entity| scene.entity_id(entity)).collect()
+}
+
+fn record_created(scene: &mut Scene, history: &mut History, entities: &[Entity], parent: Option<Entity>) {
+    match scene.copy_entities(entities) {
+        Ok(yaml) => history.push(Command::Entities {
+            roots: entities.iter().filter_map(|entity| scene.entity_id(*entity)).collect(),
+            parent: parent.and_then(|parent| scene.entity_id(parent)),
+            yaml,
+            created: true,
+        }, false),
+        Err(e) => println!("{}", e),
+    }
+}
@@ -1,22 +1,21 @@
 mod explorer;
 mod viewport;
 mod hierarchy;
+mod gizmo;
 
 use std::{sync::{Arc, Mutex}, path::PathBuf};
 
 use reverie::engine::{
-    components::{
-        transform::TransformComponent, 
-        name::Name,
-        light::{PointLight, DirectionalLight}, material::MaterialComponent, mesh::Mesh, ComponentDefault, TypeName
-    }, registry::AssetType, asset::texture::Texture, scene::Scene,
+    components::{material::{MaterialComponent, update_entity_material}, registry::EditContext},
+    registry::AssetType, asset::texture::Texture, scene::Scene, camera::Camera,
 };
-use specs::{*, WorldExt};
 
 use reverie::engine::registry::Registry;
+use specs::{Entity, WorldExt};
 
 use imgui_inspector::ImguiInspect;
 use crate::cursor::set_cursor;
+use crate::history::{History, Command};
 
 use explorer::Explorer;
 
@@ -30,8 +29,7 @@ pub struct Imgui {
     pub viewport: Viewport,
     pub explorer: Explorer,
     pub hierarchy: Hierarchy,
-
-    directional_light_index: Option<usize>,
+    pub history: History,
 }
 
 impl Imgui {
@@ -57,20 +55,69 @@ impl Imgui {
             viewport: Viewport::new(device),
             explorer: Explorer::new(),
             hierarchy: Hierarchy::new(),
-
-            directional_light_index: None,
+            history: History::new(),
         }
     }
 
-    fn ui(&mut self, device: &wgpu::Device, scene: &mut Scene, registry: &mut Registry, queue: &wgpu::Queue, window: &winit::window::Window) {
+    fn ui(&mut self, device: &wgpu::Device, scene: &mut Scene, registry: &mut Registry, queue: &wgpu::Queue, camera: &Camera, window: &winit::window::Window) {
         let ui = self.context.frame();
 
         ui.dockspace_over_main_viewport();
+
+        if !ui.is_any_item_active() {
+            self.history.end_merge();
+        }
+
+        if ui.io().key_ctrl && !ui.io().want_text_input && ui.is_key_pressed(imgui::Key::Z) {
+            if ui.io().key_shift {
+                self.history.redo(scene, registry, device, queue);
+            } else {
+                self.history.undo(scene, registry, device, queue);
+            }
+        }
+
+        self.hierarchy.retain_alive(scene);
         
         ui.window("Performance").build(|| {
             ui.text(format!("{} FPS ({:.3}ms)", (ui.io().framerate as u32), (ui.io().delta_time * 1000.0)));
         });
 
+        ui.window("Scene Settings").build(|| {
+            let skybox_name = match scene.skybox_asset() {
+                Some(id) => registry.get_filepath(id).file_name().unwrap().to_str().unwrap().to_string(),
+                None => String::from("Default"),
+            };
+            ui.button(format!("{}##skybox", skybox_name));
+
+            let mut skybox = None;
+            match ui.drag_drop_target() {
+                Some(target) => {
+                    match target.accept_payload::<Option<usize>, _>(AssetType::Texture.to_string(), imgui::DragDropFlags::empty()) {
+                        Some(Ok(payload_data)) => {
+                            skybox = Some(payload_data.data);
+                        },
+                        Some(Err(e)) => {
+                            println!("{}", e);
+                        },
+                        _ => {},
+                    }
+                },
+                _ => {},
+            }
+            ui.same_line();
+            ui.text("Skybox");
+
+            if scene.skybox_asset().is_some() && ui.button("Use Default Skybox") {
+                skybox = Some(None);
+            }
+
+            if let Some(asset) = skybox {
+                if let Err(e) = scene.set_skybox(asset, registry, device, queue, camera) {
+                    println!("{}", e);
+                }
+            }
+        });
+
         ui.window("Inspector")
             .build(|| {
                 if let Some(material_path) = &self.explorer.selected_file.clone() {
@@ -80,12 +127,15 @@ impl Imgui {
                             ui.text(material_path.file_name().unwrap().to_str().unwrap());
                             ui.separator();
                             let mut material_asset = self.explorer.material.as_ref().unwrap().asset.lock().unwrap();
+                            let before = serde_yaml::to_string(&*material_asset).unwrap();
                             if material_asset.imgui_inspect(ui).iter().any(|&value| value == true) {
+                                let after = serde_yaml::to_string(&*material_asset).unwrap();
                                 material_asset.save(material_path);
                                 drop(material_asset);
                                 registry.load_material(material_id, true);
                                 update_entity_material(&scene.world, material_id, registry);
                                 self.explorer.material = Some(registry.get_material(material_id));
+                                self.history.push(Command::Material { id: material_id, before, after }, ui.is_any_item_active());
                             }
                         }
                         _ => {}
@@ -93,136 +143,44 @@ impl Imgui {
                 }
 
                 if let Some(entity) = self.hierarchy.entity {
-                    let mut names = scene.world.write_component::<Name>();
-                    ui.input_text("##entity name", &mut names.get_mut(entity).unwrap().0).build();
-                    ui.separator();
-                    {
-                        let mut transforms = scene.world.write_component::<TransformComponent>();
-
-                        let mut parent_id = None;
-                        let mut parent_matrix: Option<cg::Matrix4<f32>> = None;
-                        if let Some(transform) = transforms.get_mut(entity) {
-                            if let Some(id) = transform.data.parent {
-                                parent_id = Some(id);
-                            }
-                        }
-                        if let Some(id) = parent_id {
-                            if let Some(transform) = transforms.get(scene.world.entities().entity(id as u32)) {
-                                parent_matrix = Some(transform.get_matrix());
-                            }
-                        }
-
-                        if let Some(transform) = transforms.get_mut(entity) {
-                            if ui.collapsing_header("Transform", imgui::TreeNodeFlags::DEFAULT_OPEN) {
-                                if transform.data.imgui_inspect(ui).iter().any(|&value| value == true) {
-                                    transform.data.update_matrix(parent_matrix);
-                                    transform.update_buffers(queue);
-                                    if self.hierarchy.point_light_index.is_some() {
-                                        scene.light_manager.update_light_position(queue, self.hierarchy.point_light_index.unwrap(), transform.get_position());
-                                    }
-                                    let matrix = transform.get_matrix().clone();
-                                    let children = transform.data.children.clone();
-                                    drop(transform);
-                                    for child in children {
-                                        let child_transform = transforms.get_mut(scene.world.entities().entity(child)).unwrap();
-                                        if child_transform.data.parent.unwrap() == entity.id() {
-                                            child_transform.data.update_matrix(Some(matrix));
-                                            child_transform.update_buffers(queue);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        let mut lights = scene.world.write_component::<PointLight>();
-                        if let Some(light) = lights.get_mut(entity) {
-                            if ui.collapsing_header("Point Light", imgui::TreeNodeFlags::DEFAULT_OPEN) {
-                                if light.imgui_inspect(ui).iter().any(|&value| value == true) {
-                                    scene.light_manager.update_light_color(queue, self.hierarchy.point_light_index.unwrap(), light.get_color());
-                                }
-                            }
-                        }
-
-                        let mut directional_lights = scene.world.write_component::<DirectionalLight>();
-                        if let Some(light) = directional_lights.get_mut(entity) {
-                            if ui.collapsing_header("Directional Light", imgui::TreeNodeFlags::DEFAULT_OPEN) {
-                                if light.imgui_inspect(ui).iter().any(|&value| value == true) {
-                                    scene.light_manager.update_directional_data(queue, self.directional_light_index.unwrap(), light.direction, light.color)
-                                }
-                            }
-                        }
-
-                        let mut materials = scene.world.write_component::<MaterialComponent>();
-                        if let Some(material) = materials.get_mut(entity) {
-                            let material_id = material.id.clone();
-                            if ui.collapsing_header("Material", imgui::TreeNodeFlags::DEFAULT_OPEN) {
-                                ui.button("material");
-                                match ui.drag_drop_target() {
-                                    Some(target) => {
-                                        match target.accept_payload::<Option<usize>, _>(AssetType::Material.to_string(), imgui::DragDropFlags::empty()) {
-                                            Some(Ok(payload_data)) => {
-                                                material.id = payload_data.data.unwrap();
-                                                material.material = registry.get_material(material.id);
-                                            },
-                                            Some(Err(e)) => {
-                                                println!("{}", e);
-                                            },
-                                            _ => {},
-                                        }
-                                    },
-                                    _ => {},
-                                }
-
-                                let material_path = registry.get_filepath(material.id);
-                                ui.text(material_path.file_name().unwrap().to_str().unwrap());
-                                ui.separator();
-                                let mut material_asset = material.material.asset.lock().unwrap();
-                                if material_asset.floats.imgui_inspect(ui).iter().any(|&value| value == true) {
-                                    material.material.update_floats(material_asset.floats);
-                                }
-                                if material_asset.imgui_inspect(ui).iter().any(|&value| value == true) {
-                                    material_asset.save(&material_path);
-                                    drop(material_asset);
-                                    registry.load_material(material_id, true);
-                                    drop(materials);
-                                    update_entity_material(&scene.world, material_id, registry);
-                                    self.explorer.material = Some(registry.get_material(material_id));
-                                }
+                    let components = scene.components.clone();
+                    let id = scene.entity_id(entity).unwrap();
+                    let material_before = material_snapshot(scene, entity);
+                    let mut context = EditContext {
+                        device,
+                        queue,
+                        registry,
+                    };
+                    for component in components.iter() {
+                        if component.has(&scene.world, entity) {
+                            let before = scene.component_value(component.name, entity).unwrap();
+                            if component.inspect(ui, scene, entity, &mut context) {
+                                let after = scene.component_value(component.name, entity).unwrap();
+                                self.history.push(Command::Component { entity: id, name: component.name.to_string(), before, after }, ui.is_any_item_active());
                             }
                         }
+                    }
 
-                        let mut meshes = scene.world.write_component::<Mesh>();
-                        if let Some(mesh) = meshes.get_mut(entity) {
-                            if ui.collapsing_header("Mesh", imgui::TreeNodeFlags::DEFAULT_OPEN) {
-                                ui.button("mesh");
-                                match ui.drag_drop_target() {
-                                    Some(target) => {
-                                        match target.accept_payload::<Option<usize>, _>(AssetType::Mesh.to_string(), imgui::DragDropFlags::empty()) {
-                                            Some(Ok(payload_data)) => {
-                                                mesh.id = payload_data.data.unwrap();
-                                                mesh.mesh = registry.get_mesh(mesh.id).unwrap();
-                                            },
-                                            Some(Err(e)) => {
-                                                println!("{}", e);
-                                            },
-                                            _ => {},
-                                        }
-                                    }
-                                    _ => {},
-                                }
-                            }
+                    if let Some((material_id, before)) = material_before {
+                        if let Some((_, after)) = material_snapshot(scene, entity).filter(|(after_id, after)| *after_id == material_id && *after != before) {
+                            self.history.push(Command::Material { id: material_id, before, after }, ui.is_any_item_active());
                         }
                     }
-                    drop(names);
 
                     ui.popup("components", || {
                         ui.text("Add Component");
-                        add_component::<Name>(ui, scene, entity, device, registry, None);
-                        add_component::<TransformComponent>(ui, scene, entity, device, registry, None);
-                        add_component::<MaterialComponent>(ui, scene, entity, device, registry, None);
-                        add_component::<Mesh>(ui, scene, entity, device, registry, None);
-                        add_component::<PointLight>(ui, scene, entity, device, registry, self.hierarchy.point_light_index);
-                        add_component::<DirectionalLight>(ui, scene, entity, device, registry, self.directional_light_index);
+                        for component in components.iter() {
+                            if ui.button(component.name) {
+                                let before = scene.component_value(component.name, entity).unwrap();
+                                if before.is_some() {
+                                    component.remove(scene, entity, device);
+                                } else {
+                                    component.add(scene, entity, device, context.registry);
+                                }
+                                let after = scene.component_value(component.name, entity).unwrap();
+                                self.history.push(Command::Component { entity: id, name: component.name.to_string(), before, after }, false);
+                            }
+                        }
                     });
 
                     if ui.is_window_hovered() && ui.is_mouse_clicked(imgui::MouseButton::Right) {
@@ -231,22 +189,30 @@ impl Imgui {
                 }
             });
 
-        self.hierarchy.ui(ui, scene, &mut self.explorer, device);
+        self.hierarchy.ui(ui, scene, &mut self.explorer, &mut self.history, registry, device, queue);
 
-        self.viewport.ui(ui, scene, registry, device);
+        let path = scene.path.clone();
+        self.viewport.ui(ui, scene, &mut self.hierarchy, &mut self.explorer, camera, &mut self.history, registry, device, queue);
+        if scene.path != path {
+            if let viewport::ViewCamera::Entity(_) = self.viewport.camera {
+                self.viewport.camera = viewport::ViewCamera::Editor;
+            }
+            self.hierarchy.clear_selection();
+            self.history.clear();
+        }
         self.explorer.ui(ui, registry);
         
         if self.explorer.selected_file.is_some() {
-            self.hierarchy.entity = None;
+            self.hierarchy.clear_selection();
         }
 
         set_cursor(window, ui);
     }
 
-    pub fn draw(&mut self, scene: &mut Scene, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue, view: &wgpu::TextureView, window: &winit::window::Window, encoder: &mut wgpu::CommandEncoder) -> Result<(), wgpu::SurfaceError> {
+    pub fn draw(&mut self, scene: &mut Scene, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera, view: &wgpu::TextureView, window: &winit::window::Window, encoder: &mut wgpu::CommandEncoder) -> Result<(), wgpu::SurfaceError> {
         self.platform.prepare_frame(self.context.io_mut(), window).expect("Failed to prepare frame");
 
-        self.ui(device, scene, registry, queue, window);
+        self.ui(device, scene, registry, queue, camera, window);
 
         let mut renderer_lock = self.renderer.lock().unwrap();
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -294,40 +260,7 @@ impl Imgui {
     }
 }
 
-fn update_entity_material(world: &World, id: usize, registry: &mut Registry) {
-    for entity in world.entities().join() {
-        let mut materials = world.write_component::<MaterialComponent>();
-        if let Some(material) = materials.get_mut(entity) {
-            if material.id == id {
-                material.material = registry.get_material(id);
-            }
-        }
-    }
+fn material_snapshot(scene: &Scene, entity: Entity) -> Option<(usize, String)> {
+    let materials = scene.world.read_storage::<MaterialComponent>();
+    materials.get(entity).map(|material| (material.id, serde_yaml::to_string(&*material.material.asset.lock().unwrap()).unwrap()))
 }
-
-use std::string::ToString;
-
-fn add_component<'a, T: ComponentDefault + specs::Component>(ui: &'a imgui::Ui, scene: &mut Scene, entity: Entity, device: &wgpu::Device, registry: &mut Registry, index: Option<usize>) where T: TypeName {
-    if ui.button(T::type_name()) {
-        let mut components = scene.world.write_storage::<T>();
-        if let Some(_) = components.get(entity) {
-            components.remove(entity);
-            if T::type_name() == "point_light" {
-                scene.light_manager.remove_point_light(device, index.unwrap())
-            } else if T::type_name() == "directional_light"  {
-                scene.light_manager.remove_directional_light(device, index.unwrap())
-            }
-        } else {
-            components.insert(entity, T::default(device, registry)).expect(&format!("Failed to add component: {}", T::type_name()));
-            drop(components);
-            if T::type_name() == "point_light" {
-                let transforms = scene.world.read_component::<TransformComponent>();
-                let lights = scene.world.read_component::<PointLight>();
-                scene.light_manager.add_point_light(device, transforms.get(entity).unwrap(), lights.get(entity).unwrap());
-            } else if T::type_name() == "directional_light" {
-                let lights = scene.world.read_component::<DirectionalLight>();
-                scene.light_manager.add_directional_light(device, lights.get(entity).unwrap());
-            }
-        }
-    }
-}
\ No newline at end of file
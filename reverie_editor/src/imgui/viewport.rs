@@ -1,12 +1,82 @@
 use std::sync::Arc;
 
-use reverie::engine::{registry::{AssetType, Registry}, scene::Scene};
+use reverie::engine::{registry::{AssetType, Registry}, scene::Scene, camera::{Camera, Navigation, ProjectionKind, SAFE_FRAC_PI_2}, components::{mesh::Mesh, transform::TransformComponent, camera::CameraComponent, name::Name}};
+use cg::InnerSpace;
+use specs::{Entity, Join, WorldExt};
+
+use crate::{history::History, settings::Settings};
+
+use super::{gizmo::{Gizmo, world_to_screen}, hierarchy::Hierarchy, explorer::Explorer};
+
+const OUTLINE_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 1.0];
+const OUTLINE_SECONDARY_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 0.5];
+// How far in front of the camera a preset keeps looking at, and how far back an orthographic preset sits from it.
+const PRESET_FOCUS_DISTANCE: f32 = 10.0;
+const PRESET_ORTHO_DISTANCE: f32 = 50.0;
+const BOX_EDGES: [(usize, usize); 12] = [(0, 1), (1, 2), (2, 3), (3, 0), (4, 5), (5, 6), (6, 7), (7, 4), (0, 4), (1, 5), (2, 6), (3, 7)];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ViewCamera {
+    Editor,
+    Main,
+    Entity(Entity),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ViewPreset {
+    Perspective,
+    Top,
+    Front,
+    Side,
+}
+
+impl ViewPreset {
+    const ALL: [(ViewPreset, &'static str); 4] = [
+        (ViewPreset::Perspective, "Perspective"),
+        (ViewPreset::Top, "Top"),
+        (ViewPreset::Front, "Front"),
+        (ViewPreset::Side, "Side"),
+    ];
+
+    // Keeps the point the camera was looking at in view; orthographic presets frame it with the
+    // same size a perspective camera would see at that distance. Returns how far that point is.
+    pub fn apply(self, camera: &mut Camera) -> f32 {
+        let focus = camera.position + camera.forward() * PRESET_FOCUS_DISTANCE;
+        let (yaw, pitch) = match self {
+            ViewPreset::Perspective => {
+                camera.projection.kind = ProjectionKind::Perspective;
+                return PRESET_FOCUS_DISTANCE;
+            }
+            ViewPreset::Top => (cg::Rad::from(cg::Deg(-90.0)), cg::Rad(-SAFE_FRAC_PI_2)),
+            ViewPreset::Front => (cg::Rad::from(cg::Deg(-90.0)), cg::Rad(0.0)),
+            ViewPreset::Side => (cg::Rad::from(cg::Deg(180.0)), cg::Rad(0.0)),
+        };
+
+        if camera.projection.kind == ProjectionKind::Perspective {
+            let size = PRESET_FOCUS_DISTANCE * (camera.projection.fovy().0 * 0.5).tan();
+            camera.projection.set_ortho_size(size);
+        }
+        camera.projection.kind = ProjectionKind::Orthographic;
+        camera.yaw = yaw;
+        camera.pitch = pitch;
+        camera.position = focus - camera.forward() * PRESET_ORTHO_DISTANCE;
+        PRESET_ORTHO_DISTANCE
+    }
+}
 
 pub struct Viewport {
     pub texture: Arc<wgpu::Texture>,
     pub size: [u32; 2],
-    pub active: bool,
+    pub navigation: Option<Navigation>,
     pub gizmos: bool,
+    pub gizmo: Gizmo,
+    pub camera: ViewCamera,
+    pub preset: Option<ViewPreset>,
+    pub scroll: f32,
+    // World space center and radius of the selection to frame.
+    pub frame: Option<(cg::Vector3<f32>, f32)>,
+    pub settings: Settings,
+    settings_dirty: bool,
 }
 
 impl Viewport {
@@ -29,27 +99,122 @@ impl Viewport {
         Self {
             size: [texture.width(), texture.height()],
             texture,
-            active: false,
+            navigation: None,
             gizmos: true,
+            gizmo: Gizmo::new(),
+            camera: ViewCamera::Editor,
+            preset: None,
+            scroll: 0.0,
+            frame: None,
+            settings: Settings::load(),
+            settings_dirty: false,
         }
     }
 
-    pub fn ui<'a>(&mut self, ui: &'a imgui::Ui, scene: &mut Scene, registry: &mut Registry, device: &wgpu::Device) {
+    // The scene camera the viewport looks through, or None for the editor camera.
+    pub fn scene_camera(&self, scene: &Scene) -> Option<Entity> {
+        match self.camera {
+            ViewCamera::Editor => None,
+            ViewCamera::Main => scene.main_camera(),
+            ViewCamera::Entity(entity) => Some(entity).filter(|entity| scene.world.read_storage::<CameraComponent>().contains(*entity)),
+        }
+    }
+
+    fn frame_selection(&mut self, scene: &Scene, hierarchy: &Hierarchy) {
+        if let Some(bounds) = scene.world_bounds(&hierarchy.selection) {
+            self.camera = ViewCamera::Editor;
+            self.frame = Some(((bounds.min + bounds.max) * 0.5, (bounds.max - bounds.min).magnitude() * 0.5));
+        }
+    }
+
+    fn view_menu(&mut self, ui: &imgui::Ui, scene: &Scene, hierarchy: &Hierarchy, camera: &Camera, registry: &mut Registry) {
+        ui.menu("View", || {
+            for (preset, label) in ViewPreset::ALL {
+                let selected = self.camera == ViewCamera::Editor && match preset {
+                    ViewPreset::Perspective => camera.projection.kind == ProjectionKind::Perspective,
+                    _ => false,
+                };
+                if ui.menu_item_config(label).selected(selected).build() {
+                    self.camera = ViewCamera::Editor;
+                    self.preset = Some(preset);
+                }
+            }
+            ui.separator();
+            if ui.menu_item_config("Frame Selected").shortcut("F").enabled(!hierarchy.selection.is_empty()).build() {
+                self.frame_selection(scene, hierarchy);
+            }
+            ui.separator();
+            imgui::Drag::new("Speed").speed(0.1).range(0.1, 1000.0).build(ui, &mut self.settings.camera_speed);
+            let mut edited = ui.is_item_deactivated_after_edit();
+            imgui::Drag::new("Sensitivity").speed(0.01).range(0.01, 10.0).build(ui, &mut self.settings.camera_sensitivity);
+            edited |= ui.is_item_deactivated_after_edit();
+            ui.menu("Tone Mapping", || {
+                self.settings_dirty |= self.settings.tone_mapping.inspect(ui);
+            });
+            ui.menu("Post Processing", || {
+                self.settings_dirty |= self.settings.post_processing.inspect(ui, registry);
+            });
+            // Saved once a drag is released rather than on every frame it moves.
+            if self.settings_dirty && !ui.is_any_item_active() {
+                self.settings_dirty = false;
+                edited = true;
+            }
+            if edited {
+                if let Err(e) = self.settings.save() {
+                    println!("{}", e);
+                }
+            }
+        });
+    }
+
+    fn camera_menu(&mut self, ui: &imgui::Ui, scene: &Scene) {
+        ui.menu("Camera", || {
+            if ui.menu_item_config("Editor").selected(self.camera == ViewCamera::Editor).build() {
+                self.camera = ViewCamera::Editor;
+            }
+            if ui.menu_item_config("Main Camera").selected(self.camera == ViewCamera::Main).build() {
+                self.camera = ViewCamera::Main;
+            }
+            ui.separator();
+
+            let cameras = scene.world.read_storage::<CameraComponent>();
+            let names = scene.world.read_storage::<Name>();
+            for (entity, _) in (&scene.world.entities(), &cameras).join() {
+                let label = format!("{}##camera_{}", names.get(entity).map_or("Camera", |name| name.0.as_str()), entity.id());
+                if ui.menu_item_config(label).selected(self.camera == ViewCamera::Entity(entity)).build() {
+                    self.camera = ViewCamera::Entity(entity);
+                }
+            }
+        });
+    }
+
+    pub fn ui<'a>(&mut self, ui: &'a imgui::Ui, scene: &mut Scene, hierarchy: &mut Hierarchy, explorer: &mut Explorer, camera: &Camera, history: &mut History, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) {
         let padding = ui.push_style_var(imgui::StyleVar::WindowPadding([0.0, 0.0]));
         ui.window("Viewport").menu_bar(true).build(|| {
             let bar = ui.begin_menu_bar();
             if ui.menu_item("Save") {
-                scene.save_scene();
+                if let Err(e) = scene.save_scene() {
+                    println!("{}", e);
+                }
             }
+            ui.menu_item_config("Gizmos").selected(self.gizmos).build_with_ref(&mut self.gizmos);
+            self.gizmo.menu(ui);
+            self.view_menu(ui, scene, hierarchy, camera, registry);
+            self.camera_menu(ui, scene);
             
             bar.unwrap().end();
             self.size = [ui.content_region_avail()[0] as u32, ui.content_region_avail()[1] as u32];
-            imgui::Image::new(imgui::TextureId::new(2), ui.content_region_avail()).build(ui);
+            let min = ui.cursor_screen_pos();
+            let size = ui.content_region_avail();
+            imgui::Image::new(imgui::TextureId::new(2), size).build(ui);
+            let hovered = ui.is_item_hovered();
             match ui.drag_drop_target() {
                 Some(target) => {
                     match target.accept_payload::<Option<usize>, _>(AssetType::Scene.to_string(), imgui::DragDropFlags::empty()) {
                         Some(Ok(payload_data)) => {
-                            scene.load_scene(&registry.get_filepath(payload_data.data.unwrap()), registry, device);
+                            if let Err(e) = scene.load_scene(&registry.get_filepath(payload_data.data.unwrap()), registry, device, queue, camera) {
+                                println!("{}", e);
+                            }
                         },
                         Some(Err(e)) => {
                             println!("{}", e);
@@ -59,10 +224,93 @@ impl Viewport {
                 },
                 _ => {},
             }
-            if ui.is_item_hovered() {
-                self.active = ui.is_mouse_down(imgui::MouseButton::Right);
+            self.navigate(ui, hovered);
+            self.scroll = if hovered { ui.io().mouse_wheel } else { 0.0 };
+
+            if ui.is_window_focused() {
+                self.gizmo.shortcuts(ui);
+                if !ui.io().want_text_input && self.navigation.is_none() && ui.is_key_pressed(imgui::Key::F) {
+                    self.frame_selection(scene, hierarchy);
+                }
+            }
+            if size[0] <= 0.0 || size[1] <= 0.0 {
+                return;
+            }
+
+            self.draw_outline(ui, scene, hierarchy, camera, min, size);
+
+            let gizmo_hovered = match hierarchy.entity.filter(|_| self.gizmos) {
+                Some(entity) => self.gizmo.ui(ui, scene, entity, camera, history, queue, min, size, hovered && !ui.io().key_alt),
+                None => false,
+            };
+
+            if hovered && !gizmo_hovered && !ui.io().key_alt && ui.is_mouse_clicked(imgui::MouseButton::Left) {
+                let mouse = ui.io().mouse_pos;
+                let ndc = cg::vec2(
+                    (mouse[0] - min[0]) / size[0] * 2.0 - 1.0,
+                    1.0 - (mouse[1] - min[1]) / size[1] * 2.0,
+                );
+                let (origin, direction) = camera.ray(ndc);
+                let additive = ui.io().key_shift;
+                match scene.pick(origin, direction) {
+                    Some(entity) => hierarchy.select(entity, additive, explorer),
+                    None if !additive => hierarchy.clear_selection(),
+                    None => {}
+                }
             }
         });
         padding.pop();
     }
-}
\ No newline at end of file
+
+    // RMB flies, Alt+LMB orbits and MMB pans the editor camera for as long as the button that started it is held.
+    fn navigate(&mut self, ui: &imgui::Ui, hovered: bool) {
+        if let Some(navigation) = self.navigation {
+            let button = match navigation {
+                Navigation::Fly => imgui::MouseButton::Right,
+                Navigation::Orbit => imgui::MouseButton::Left,
+                Navigation::Pan => imgui::MouseButton::Middle,
+            };
+            if !ui.is_mouse_down(button) || self.camera != ViewCamera::Editor {
+                self.navigation = None;
+            }
+        } else if hovered && self.camera == ViewCamera::Editor {
+            if ui.is_mouse_clicked(imgui::MouseButton::Right) {
+                self.navigation = Some(Navigation::Fly);
+            } else if ui.io().key_alt && ui.is_mouse_clicked(imgui::MouseButton::Left) {
+                self.navigation = Some(Navigation::Orbit);
+            } else if ui.is_mouse_clicked(imgui::MouseButton::Middle) {
+                self.navigation = Some(Navigation::Pan);
+            }
+        }
+    }
+
+    // Draws the mesh bounds of every selected entity, the active one brighter than the rest.
+    fn draw_outline(&self, ui: &imgui::Ui, scene: &Scene, hierarchy: &Hierarchy, camera: &Camera, min: [f32; 2], size: [f32; 2]) {
+        let view_proj = camera.projection.calc_matrix() * camera.calc_matrix();
+        let meshes = scene.world.read_storage::<Mesh>();
+        let transforms = scene.world.read_storage::<TransformComponent>();
+
+        let draw_list = ui.get_window_draw_list();
+        draw_list.with_clip_rect_intersect(min, [min[0] + size[0], min[1] + size[1]], || {
+            for entity in &hierarchy.selection {
+                let (mesh, transform) = match (meshes.get(*entity), transforms.get(*entity)) {
+                    (Some(mesh), Some(transform)) => (mesh, transform),
+                    _ => continue,
+                };
+                let world = transform.get_matrix();
+                let color = if hierarchy.entity == Some(*entity) { OUTLINE_COLOR } else { OUTLINE_SECONDARY_COLOR };
+
+                for submesh in mesh.mesh.iter() {
+                    let corners: Vec<Option<[f32; 2]>> = submesh.bounds.corners().iter()
+                        .map(|corner| world_to_screen(&view_proj, min, size, (world * corner.extend(1.0)).truncate()))
+                        .collect();
+                    for (a, b) in BOX_EDGES {
+                        if let (Some(a), Some(b)) = (corners[a], corners[b]) {
+                            draw_list.add_line(a, b, color).thickness(1.5).build();
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
@@ -2,6 +2,8 @@ mod cursor;
 mod app;
 mod imgui;
 mod watcher;
+mod history;
+mod settings;
 
 use app::run;
 
@@ -0,0 +1,49 @@
+use std::error::Error;
+
+use reverie::engine::{post::PostProcessing, tonemap::ToneMapping};
+use serde::{Serialize, Deserialize};
+
+const SETTINGS_FILE: &str = "editor.yaml";
+
+// Editor preferences stored next to the project's registry.yaml.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub camera_speed: f32,
+    pub camera_sensitivity: f32,
+    // Used by the editor camera, scene cameras bring their own.
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    #[serde(default)]
+    pub post_processing: PostProcessing,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            camera_speed: 4.0,
+            camera_sensitivity: 0.5,
+            tone_mapping: ToneMapping::default(),
+            post_processing: PostProcessing::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        match read_settings() {
+            Ok(settings) => settings,
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let yaml = serde_yaml::to_string(self)?;
+        std::fs::write(std::env::current_dir()?.join(SETTINGS_FILE), yaml)?;
+        Ok(())
+    }
+}
+
+fn read_settings() -> Result<Settings, Box<dyn Error>> {
+    let yaml = std::fs::read_to_string(std::env::current_dir()?.join(SETTINGS_FILE))?;
+    Ok(serde_yaml::from_str(&yaml)?)
+}
@@ -0,0 +1,40 @@
+struct Params {
+    // Set when the output format doesn't encode sRGB on write.
+    encode_srgb: u32,
+    _padding: u32,
+    _padding2: u32,
+    _padding3: u32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+// A single triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = clamp(textureLoad(t_source, vec2<i32>(in.position.xy), 0).rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    if (params.encode_srgb != 0u) {
+        return vec4<f32>(linear_to_srgb(color), 1.0);
+    }
+    return vec4<f32>(color, 1.0);
+}
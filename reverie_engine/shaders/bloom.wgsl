@@ -0,0 +1,81 @@
+struct Params {
+    threshold: f32,
+    knee: f32,
+    // Upsampling tent radius in uv units.
+    radius: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn sample(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+}
+
+// 13 tap box filter from Call of Duty: Advanced Warfare's bloom, weighted towards the centre.
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = sample(uv + texel * vec2<f32>(-2.0, -2.0));
+    let b = sample(uv + texel * vec2<f32>(0.0, -2.0));
+    let c = sample(uv + texel * vec2<f32>(2.0, -2.0));
+    let d = sample(uv + texel * vec2<f32>(-2.0, 0.0));
+    let e = sample(uv);
+    let f = sample(uv + texel * vec2<f32>(2.0, 0.0));
+    let g = sample(uv + texel * vec2<f32>(-2.0, 2.0));
+    let h = sample(uv + texel * vec2<f32>(0.0, 2.0));
+    let i = sample(uv + texel * vec2<f32>(2.0, 2.0));
+    let j = sample(uv + texel * vec2<f32>(-1.0, -1.0));
+    let k = sample(uv + texel * vec2<f32>(1.0, -1.0));
+    let l = sample(uv + texel * vec2<f32>(-1.0, 1.0));
+    let m = sample(uv + texel * vec2<f32>(1.0, 1.0));
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+// Keeps what is brighter than the threshold, with a quadratic knee instead of a hard cut.
+fn prefilter(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let soft = clamp(brightness - params.threshold + params.knee, 0.0, 2.0 * params.knee);
+    let curve = soft * soft / (4.0 * params.knee + 0.00001);
+    let contribution = max(curve, brightness - params.threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(prefilter(downsample(in.uv)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter, added onto the next larger mip by the blend state.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let r = params.radius;
+    var color = sample(in.uv) * 4.0;
+    color += (sample(in.uv + vec2<f32>(0.0, -r)) + sample(in.uv + vec2<f32>(-r, 0.0)) + sample(in.uv + vec2<f32>(r, 0.0)) + sample(in.uv + vec2<f32>(0.0, r))) * 2.0;
+    color += sample(in.uv + vec2<f32>(-r, -r)) + sample(in.uv + vec2<f32>(r, -r)) + sample(in.uv + vec2<f32>(-r, r)) + sample(in.uv + vec2<f32>(r, r));
+    return vec4<f32>(color / 16.0, 1.0);
+}
@@ -0,0 +1,53 @@
+struct Params {
+    // Blend between the input and the graded color.
+    contribution: f32,
+    _padding: f32,
+    _padding2: f32,
+    _padding3: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+@group(0) @binding(3)
+var t_lut: texture_3d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+// LUTs are authored on display colors, so the lookup happens in sRGB and texel centres.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(t_source, s_source, in.uv, 0.0).rgb;
+    let size = f32(textureDimensions(t_lut).x);
+    let coords = linear_to_srgb(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0))) * (size - 1.0) / size + 0.5 / size;
+    let graded = srgb_to_linear(textureSampleLevel(t_lut, s_source, coords, 0.0).rgb);
+    return vec4<f32>(mix(color, graded, params.contribution), 1.0);
+}
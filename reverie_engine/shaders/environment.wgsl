@@ -0,0 +1,163 @@
+@group(0) @binding(0)
+var t_source: texture_cube<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var t_output: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(3)
+var<uniform> roughness: f32;
+@group(0) @binding(4)
+var t_brdf_lut: texture_storage_2d<rgba16float, write>;
+
+const PI = 3.14159265359;
+
+// Direction through the centre of a texel on one face of a cube map, faces ordered +x, -x, +y, -y, +z, -z.
+fn cube_direction(face: u32, texel: vec2<u32>, size: vec2<u32>) -> vec3<f32> {
+    let p = (vec2<f32>(texel) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -p.y, -p.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -p.y, p.x)); }
+        case 2u: { return normalize(vec3<f32>(p.x, 1.0, p.y)); }
+        case 3u: { return normalize(vec3<f32>(p.x, -1.0, -p.y)); }
+        case 4u: { return normalize(vec3<f32>(p.x, -p.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-p.x, -p.y, -1.0)); }
+    }
+}
+
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, n));
+    return mat3x3<f32>(right, cross(n, right), n);
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+fn distribution_ggx(nh: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = nh * nh * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return normalize(tangent_frame(n) * h);
+}
+
+// Fills one mip of the source cube from the view bound as t_source, either the skybox or the mip
+// above. Each texel centre falls between four texels of the mip above, so one bilinear tap averages them.
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(t_output));
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    let direction = cube_direction(id.z, id.xy, size);
+    textureStore(t_output, vec2<i32>(id.xy), i32(id.z), textureSampleLevel(t_source, s_source, direction, 0.0));
+}
+
+// Cosine weighted average of the hemisphere around each direction.
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(t_output));
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    let n = cube_direction(id.z, id.xy, size);
+    let frame = tangent_frame(n);
+    let delta = 0.05;
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let direction = frame * vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            sum += textureSampleLevel(t_source, s_source, direction, 0.0).rgb * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    textureStore(t_output, vec2<i32>(id.xy), i32(id.z), vec4<f32>(PI * sum / count, 1.0));
+}
+
+// Radiance convolved with the GGX lobe for one roughness, assuming the view is along the normal.
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(t_output));
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    let n = cube_direction(id.z, id.xy, size);
+    let samples = 512u;
+    let source_size = f32(textureDimensions(t_source).x);
+    let texel_angle = 4.0 * PI / (6.0 * source_size * source_size);
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < samples; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, samples), n, roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let nl = dot(n, l);
+        if (nl > 0.0) {
+            // Filtered importance sampling: with the view along n the pdf of l is D / 4, and a sample
+            // reads the mip whose texels span the solid angle it stands for, so a few samples of a
+            // wide lobe average the sky around them instead of landing on single bright texels.
+            let pdf = distribution_ggx(max(dot(n, h), 0.0), roughness) / 4.0;
+            let sample_angle = 1.0 / (f32(samples) * pdf + 0.0001);
+            var lod = 0.0;
+            if (roughness > 0.0) {
+                lod = max(0.5 * log2(sample_angle / texel_angle) + 1.0, 0.0);
+            }
+            sum += textureSampleLevel(t_source, s_source, l, lod).rgb * nl;
+            weight += nl;
+        }
+    }
+
+    textureStore(t_output, vec2<i32>(id.xy), i32(id.z), vec4<f32>(sum / max(weight, 0.0001), 1.0));
+}
+
+// Scale and bias applied to f0 by the split sum approximation, by n.v across and roughness down.
+@compute @workgroup_size(8, 8, 1)
+fn brdf(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(t_brdf_lut));
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let nv = uv.x;
+    let lut_roughness = uv.y;
+    let v = vec3<f32>(sqrt(1.0 - nv * nv), 0.0, nv);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+    let k = lut_roughness * lut_roughness / 2.0;
+
+    let samples = 1024u;
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < samples; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, samples), n, lut_roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let nl = max(l.z, 0.0);
+        let nh = max(h.z, 0.0);
+        let vh = max(dot(v, h), 0.0);
+        if (nl > 0.0) {
+            let g = (nv / (nv * (1.0 - k) + k)) * (nl / (nl * (1.0 - k) + k));
+            let g_vis = g * vh / (nh * nv);
+            let fc = pow(1.0 - vh, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+
+    textureStore(t_brdf_lut, vec2<i32>(id.xy), vec4<f32>(scale / f32(samples), bias / f32(samples), 0.0, 1.0));
+}
@@ -0,0 +1,47 @@
+@group(0) @binding(0)
+var t_equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var t_output: texture_storage_2d_array<rgba16float, write>;
+
+const PI = 3.14159265359;
+
+// Direction through the centre of a texel on one face of a cube map, faces ordered +x, -x, +y, -y, +z, -z.
+fn cube_direction(face: u32, texel: vec2<u32>, size: vec2<u32>) -> vec3<f32> {
+    let p = (vec2<f32>(texel) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -p.y, -p.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -p.y, p.x)); }
+        case 2u: { return normalize(vec3<f32>(p.x, 1.0, p.y)); }
+        case 3u: { return normalize(vec3<f32>(p.x, -1.0, -p.y)); }
+        case 4u: { return normalize(vec3<f32>(p.x, -p.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-p.x, -p.y, -1.0)); }
+    }
+}
+
+// Float textures can't be filtered without a device feature, so the panorama is read texel by
+// texel, wrapping around horizontally and clamping at the poles.
+fn load(texel: vec2<i32>, size: vec2<i32>) -> vec3<f32> {
+    let x = (texel.x % size.x + size.x) % size.x;
+    let y = clamp(texel.y, 0, size.y - 1);
+    return textureLoad(t_equirect, vec2<i32>(x, y), 0).rgb;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(t_output));
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    let direction = cube_direction(id.z, id.xy, size);
+    let uv = vec2<f32>(atan2(direction.z, direction.x) / (2.0 * PI) + 0.5, acos(clamp(direction.y, -1.0, 1.0)) / PI);
+
+    let source_size = vec2<i32>(textureDimensions(t_equirect));
+    let position = uv * vec2<f32>(source_size) - 0.5;
+    let base = vec2<i32>(floor(position));
+    let f = fract(position);
+    let top = mix(load(base, source_size), load(base + vec2<i32>(1, 0), source_size), f.x);
+    let bottom = mix(load(base + vec2<i32>(0, 1), source_size), load(base + vec2<i32>(1, 1), source_size), f.x);
+
+    textureStore(t_output, vec2<i32>(id.xy), i32(id.z), vec4<f32>(mix(top, bottom, f.y), 1.0));
+}
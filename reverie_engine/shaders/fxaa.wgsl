@@ -0,0 +1,65 @@
+struct Params {
+    // Longest distance in texels the filter blurs along an edge.
+    span_max: f32,
+    // How much the blur direction is shortened on dark and low contrast edges.
+    reduce_mul: f32,
+    reduce_min: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn sample(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+}
+
+// Edges are found in roughly perceptual space, the square root standing in for gamma.
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(sqrt(max(color, vec3<f32>(0.0))), vec3<f32>(0.299, 0.587, 0.114));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let center = sample(in.uv);
+    let luma_nw = luma(sample(in.uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample(in.uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample(in.uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample(in.uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = luma(center);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var direction = vec2<f32>(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * params.reduce_mul, params.reduce_min);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-params.span_max), vec2<f32>(params.span_max)) * texel;
+
+    let a = 0.5 * (sample(in.uv + direction * (1.0 / 3.0 - 0.5)) + sample(in.uv + direction * (2.0 / 3.0 - 0.5)));
+    let b = a * 0.5 + 0.25 * (sample(in.uv - direction * 0.5) + sample(in.uv + direction * 0.5));
+    let luma_b = luma(b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(a, 1.0);
+    }
+    return vec4<f32>(b, 1.0);
+}
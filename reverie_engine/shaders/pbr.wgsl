@@ -14,8 +14,15 @@ var<uniform> camera: Camera;
 
 struct PointLight {
     projection: array<mat4x4<f32>, 6>,
+    // Offset and scale of each face in the shadow atlas.
+    tiles: array<vec4<f32>, 6>,
     position: vec3<f32>,
+    range: f32,
     color: vec3<f32>,
+    // Candela.
+    intensity: f32,
+    shadow_index: i32,
+    bias: f32,
 };
 @group(3) @binding(0)
 var<storage, read> point_lights: array<PointLight>;
@@ -24,6 +31,11 @@ var<uniform> point_light_count: i32;
 struct DirectionalLight {
     direction: vec3<f32>,
     color: vec3<f32>,
+    cascades: array<mat4x4<f32>, 4>,
+    cascade_count: i32,
+    pcf_radius: i32,
+    shadow_index: i32,
+    debug: i32,
 };
 @group(3) @binding(2)
 var<storage, read> directional_lights: array<DirectionalLight>;
@@ -82,9 +94,38 @@ var t_ao: texture_2d<f32>;
 var s_ao: sampler;
 
 @group(3) @binding(4)
-var t_depth_cube: texture_depth_cube_array;
-@group(3) @binding(5)
-var s_depth_cube: sampler;
+var t_point_shadows: texture_depth_2d;
+@group(3) @binding(11)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(12)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(13)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(14)
+var s_environment: sampler;
+@group(3) @binding(6)
+var t_cascades: binding_array<texture_depth_2d_array, 4>;
+@group(3) @binding(7)
+var s_shadow: sampler_comparison;
+@group(3) @binding(8)
+var t_spot_shadows: texture_depth_2d_array;
+
+struct SpotLight {
+    projection: mat4x4<f32>,
+    position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>,
+    inner_cos: f32,
+    color: vec3<f32>,
+    outer_cos: f32,
+    shadow_index: i32,
+    // Candela.
+    intensity: f32,
+};
+@group(3) @binding(9)
+var<storage, read> spot_lights: array<SpotLight>;
+@group(3) @binding(10)
+var<uniform> spot_light_count: i32;
 
 struct PBR {
     albedo: vec3<f32>,
@@ -138,7 +179,8 @@ fn fs_main(
     }
 
     let n = get_normal_from_map(in.normal, in.world_position, in.tex_coords);
-    let v = normalize(camera.view_pos.xyz - in.world_position);
+    // w is 0 for orthographic cameras, where view_pos holds the direction towards the camera.
+    let v = normalize(camera.view_pos.xyz - in.world_position * camera.view_pos.w);
 
     var f0 = vec3<f32>(0.04);
     f0 = mix(f0, albedo, metallic);
@@ -149,15 +191,20 @@ fn fs_main(
         let h = normalize(v + l);
         
         let distance = length(point_lights[i].position - in.world_position);
-        let attenuation = 1.0 / (distance * distance);
-        let radiance = (point_lights[i].color * 255.0) * attenuation;
+        if distance >= point_lights[i].range {
+            continue;
+        }
+        // Inverse square falloff, windowed so it reaches zero at the light's range.
+        let window = saturate(1.0 - pow(distance / point_lights[i].range, 4.0));
+        let attenuation = window * window / max(distance * distance, 0.0001);
+        let radiance = point_lights[i].color * point_lights[i].intensity * attenuation;
 
         let ndf = distributionggx(n, h, roughness);
         let g = geometrysmith(n, v, l, roughness);
         let f = fresnelschlick(max(dot(h, v), 0.0), f0);
 
         let numerator = ndf * g * f;
-        let denominator = 4.0 / max(dot(n, v), 0.0) * max(dot(n, l), 0.0) + 0.0001;
+        let denominator = 4.0 * max(dot(n, v), 0.0) * max(dot(n, l), 0.0) + 0.0001;
         let specular = numerator / denominator;
 
         let ks = f;
@@ -165,52 +212,103 @@ fn fs_main(
         kd = kd * (1.0 - metallic);
         let nl = max(dot(n, l), 0.0);
 
-        //let shadow_factor = textureSampleCompare(t_depth_cube, s_depth_cube, l, distance / 100.0);
+        var shadow = 1.0;
+        if point_lights[i].shadow_index >= 0 {
+            shadow = calculate_shadow(i, in.world_position, l);
+        }
 
-        var face = get_cube_face(l);
+        lo = lo + ((kd * albedo / PI + specular) * radiance * (nl * shadow));
+    }
 
-        let fragment_pos_light_space = point_lights[i].projection[face] * vec4<f32>(in.world_position, 1.0);
-        let depth = fragment_pos_light_space.z / fragment_pos_light_space.w;
-        
-        let shadow = calculate_shadow(distance, depth, l, i);
-        // var shadow = 0.0;
-        // var closestDepth = textureSample(t_depth_cube, s_depth_cube, l, i);
-        // if (depth < closestDepth) {
-        //     shadow = shadow + 1.0;
-        // }
+    var tint = vec3<f32>(1.0);
+    for (var i = 0; i < directional_light_count; i += 1) {
+        let light = directional_lights[i];
+        // Free slots are zeroed.
+        if (light.cascade_count == 0) {
+            continue;
+        }
+        let l = normalize(-light.direction);
+        let h = normalize(v + l);
+        let radiance = light.color;
+
+        let ndf = distributionggx(n, h, roughness);
+        let g = geometrysmith(n, v, l, roughness);
+        let f = fresnelschlick(max(dot(h, v), 0.0), f0);
+
+        let numerator = ndf * g * f;
+        let denominator = 4.0 * max(dot(n, v), 0.0) * max(dot(n, l), 0.0) + 0.0001;
+        let specular = numerator / denominator;
+
+        let ks = f;
+        var kd = vec3<f32>(1.0) - ks;
+        kd = kd * (1.0 - metallic);
+        let nl = max(dot(n, l), 0.0);
+
+        var shadow = 1.0;
+        if (light.shadow_index >= 0) {
+            let cascade = get_cascade(i, in.world_position);
+            if (cascade >= 0) {
+                shadow = calculate_cascade_shadow(i, cascade, in.world_position, in.normal, l);
+                if (light.debug != 0) {
+                    tint = tint * get_cascade_color(cascade);
+                }
+            }
+        }
 
         lo = lo + ((kd * albedo / PI + specular) * radiance * (nl * shadow));
     }
 
-    // for (var i = 1; i < directional_light_count; i = i + 1) {
-    //     let l = normalize(directional_lights[i].direction);
-    //     let h = normalize(v + l);
-    //     let radiance = directional_lights[i].color;
+    for (var i = 0; i < spot_light_count; i += 1) {
+        let light = spot_lights[i];
+        let distance = length(light.position - in.world_position);
+        if distance >= light.range {
+            continue;
+        }
+        let l = normalize(light.position - in.world_position);
+        let h = normalize(v + l);
 
-    //     let ndf = distributionggx(n, h, roughness);
-    //     let g = geometrysmith(n, v, l, roughness);
-    //     let f = fresnelschlick(max(dot(h, v), 0.0), f0);
+        let cone = clamp((dot(l, -light.direction) - light.outer_cos) / max(light.inner_cos - light.outer_cos, 0.0001), 0.0, 1.0);
+        // Inverse square, windowed so the light reaches exactly zero at its range.
+        let window = clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+        let attenuation = window * window / max(distance * distance, 0.0001);
+        let radiance = light.color * light.intensity * attenuation * cone;
 
-    //     let numerator = ndf * g * f;
-    //     let denominator = 4.0 / max(dot(n, v), 0.0) * max(dot(n, l), 0.0) + 0.0001;
-    //     let specular = numerator / denominator;
+        let ndf = distributionggx(n, h, roughness);
+        let g = geometrysmith(n, v, l, roughness);
+        let f = fresnelschlick(max(dot(h, v), 0.0), f0);
 
-    //     let ks = f;
-    //     var kd = vec3<f32>(1.0) - ks;
-    //     kd = kd * (1.0 - metallic);
-    //     let nl = max(dot(n, l), 0.0);
-    //     lo = lo + ((kd * albedo / PI + specular) * radiance * nl);
-    // }
+        let numerator = ndf * g * f;
+        let denominator = 4.0 * max(dot(n, v), 0.0) * max(dot(n, l), 0.0) + 0.0001;
+        let specular = numerator / denominator;
 
-    let ambient = vec3<f32>(0.03) * albedo * ao;
-    var color = ambient + lo;
-    color = color / (color + vec3<f32>(1.0));
-    color = pow(color, vec3<f32>(1.0/2.2));
+        let ks = f;
+        var kd = vec3<f32>(1.0) - ks;
+        kd = kd * (1.0 - metallic);
+        let nl = max(dot(n, l), 0.0);
 
-    return vec4<f32>(color, 1.0);
+        var shadow = 1.0;
+        if (light.shadow_index >= 0 && cone > 0.0) {
+            shadow = calculate_spot_shadow(i, in.world_position, in.normal, l);
+        }
+
+        lo = lo + ((kd * albedo / PI + specular) * radiance * (nl * shadow));
+    }
+
+    // Image based lighting: irradiance for the diffuse part, the split sum approximation for specular.
+    let nv = max(dot(n, v), 0.0);
+    let f_ambient = fresnelschlickroughness(nv, f0, roughness);
+    let kd_ambient = (vec3<f32>(1.0) - f_ambient) * (1.0 - metallic);
+    let irradiance = textureSample(t_irradiance, s_environment, n).rgb;
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflect(-v, n), roughness * MAX_REFLECTION_LOD).rgb;
+    let brdf = textureSample(t_brdf_lut, s_environment, vec2<f32>(nv, roughness)).rg;
+    let ambient = (kd_ambient * irradiance * albedo + prefiltered * (f_ambient * brdf.x + brdf.y)) * ao;
+    // Left in linear HDR, exposure and tone mapping happen in a later pass.
+    return vec4<f32>((ambient + lo) * tint, 1.0);
 }
 
 const PI = 3.14159265359;
+// Mip count of the prefiltered environment map minus one.
+const MAX_REFLECTION_LOD = 4.0;
 
 fn get_normal_from_map(normal: vec3<f32>, world_position: vec3<f32>, tex_coords: vec2<f32>) -> vec3<f32> {
     let tangent_normal: vec3<f32> = textureSample(t_normal, s_normal, tex_coords).xyz * 2.0 - 1.0;
@@ -262,73 +360,30 @@ fn fresnelschlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
     return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
 }
 
-fn get_sample_offset_directions() -> array<vec3<f32>, 20> {
-    return array<vec3<f32>, 20>(
-        vec3<f32>( 1.0,  1.0,  1.0), vec3<f32>( 1.0, -1.0,  1.0), vec3<f32>(-1.0, -1.0,  1.0), vec3<f32>(-1.0,  1.0,  1.0), 
-        vec3<f32>( 1.0,  1.0, -1.0), vec3<f32>( 1.0, -1.0, -1.0), vec3<f32>(-1.0, -1.0, -1.0), vec3<f32>(-1.0,  1.0, -1.0),
-        vec3<f32>( 1.0,  1.0,  0.0), vec3<f32>( 1.0, -1.0,  0.0), vec3<f32>(-1.0, -1.0,  0.0), vec3<f32>(-1.0,  1.0,  0.0),
-        vec3<f32>( 1.0,  0.0,  1.0), vec3<f32>(-1.0,  0.0,  1.0), vec3<f32>( 1.0,  0.0, -1.0), vec3<f32>(-1.0,  0.0, -1.0),
-        vec3<f32>( 0.0,  1.0,  1.0), vec3<f32>( 0.0, -1.0,  1.0), vec3<f32>( 0.0, -1.0, -1.0), vec3<f32>( 0.0,  1.0, -1.0)
-    );
+fn fresnelschlickroughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
 }
 
-fn calculate_shadow(distance: f32, depth: f32, l: vec3<f32>, i: i32) -> f32 {
-    var shadow = 0.0;
-    let samples = 20;        
-    let sample_directions = get_sample_offset_directions();
-    let disk_radius = (1.0 + (distance / 100.0)) / 200.0;
-    let bias = -0.0;
-
-    var closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[0] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[1] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[2] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[3] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[4] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[5] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[6] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[7] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[8] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[9] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[10] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[11] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[12] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[13] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[14] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[15] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[16] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[17] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[18] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-    closest_depth = textureSample(t_depth_cube, s_depth_cube, l + sample_directions[19] * disk_radius, i);
-    shadow += compare_depth(depth, closest_depth, bias);
-
-    return shadow / f32(samples);
-}
+fn calculate_shadow(i: i32, world_position: vec3<f32>, l: vec3<f32>) -> f32 {
+    let face = get_cube_face(l);
+    let clip = point_lights[i].projection[face] * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let tile = point_lights[i].tiles[face];
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_point_shadows));
+    let uv = tile.xy + vec2<f32>(ndc.x * 0.5 + 0.5, -ndc.y * 0.5 + 0.5) * tile.zw;
+    let depth = ndc.z - point_lights[i].bias;
 
-fn compare_depth(depth: f32, closest_depth: f32, bias: f32) -> f32 {
-    if (depth - bias< closest_depth) {
-        return 1.0;
+    // Samples are kept inside the face's tile so filtering never reads its neighbours in the atlas.
+    var shadow = 0.0;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let offset = uv + vec2<f32>(f32(x), f32(y)) * texel;
+            let coords = clamp(offset, tile.xy + texel, tile.xy + tile.zw - texel);
+            shadow += textureSampleCompareLevel(t_point_shadows, s_shadow, coords, depth);
+        }
     }
-    
-    return 0.0;
+
+    return shadow / 9.0;
 }
 
 fn get_cube_face(l: vec3<f32>) -> i32 {
@@ -355,4 +410,73 @@ fn get_cube_face(l: vec3<f32>) -> i32 {
     }
 
     return face;
+}
+
+// The first cascade whose light space box contains the position; cascades are ordered near to far.
+fn get_cascade(i: i32, world_position: vec3<f32>) -> i32 {
+    for (var c = 0; c < directional_lights[i].cascade_count; c += 1) {
+        let clip = directional_lights[i].cascades[c] * vec4<f32>(world_position, 1.0);
+        let ndc = clip.xyz / clip.w;
+        if (all(abs(ndc.xy) <= vec2<f32>(1.0)) && ndc.z >= 0.0 && ndc.z <= 1.0) {
+            return c;
+        }
+    }
+
+    return -1;
+}
+
+fn calculate_cascade_shadow(i: i32, cascade: i32, world_position: vec3<f32>, normal: vec3<f32>, l: vec3<f32>) -> f32 {
+    let light = directional_lights[i];
+    let matrix = directional_lights[i].cascades[cascade];
+    let size = vec2<f32>(textureDimensions(t_cascades[light.shadow_index]));
+    let texel = 1.0 / size;
+
+    // Pushing the lookup out along the normal by about a texel hides most acne on surfaces facing away from the light.
+    let world_texel = 2.0 / (length(vec3<f32>(matrix[0].x, matrix[1].x, matrix[2].x)) * size.x);
+    let position = world_position + normalize(normal) * world_texel * (1.0 - dot(normalize(normal), l));
+
+    let clip = matrix * vec4<f32>(position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, -ndc.y * 0.5 + 0.5);
+    let depth = ndc.z - 0.0005;
+
+    var shadow = 0.0;
+    let radius = light.pcf_radius;
+    for (var x = -radius; x <= radius; x += 1) {
+        for (var y = -radius; y <= radius; y += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            shadow += textureSampleCompareLevel(t_cascades[light.shadow_index], s_shadow, uv + offset, cascade, depth);
+        }
+    }
+
+    let samples = f32((2 * radius + 1) * (2 * radius + 1));
+    return shadow / samples;
+}
+
+fn get_cascade_color(cascade: i32) -> vec3<f32> {
+    switch cascade {
+        case 0: { return vec3<f32>(1.0, 0.4, 0.4); }
+        case 1: { return vec3<f32>(0.4, 1.0, 0.4); }
+        case 2: { return vec3<f32>(0.4, 0.4, 1.0); }
+        default: { return vec3<f32>(1.0, 1.0, 0.4); }
+    }
+}
+
+fn calculate_spot_shadow(i: i32, world_position: vec3<f32>, normal: vec3<f32>, l: vec3<f32>) -> f32 {
+    let light = spot_lights[i];
+    let position = world_position + normalize(normal) * 0.02 * (1.0 - dot(normalize(normal), l));
+    let clip = light.projection * vec4<f32>(position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, -ndc.y * 0.5 + 0.5);
+    let depth = ndc.z - 0.0002;
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_spot_shadows));
+    var shadow = 0.0;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            shadow += textureSampleCompareLevel(t_spot_shadows, s_shadow, uv + vec2<f32>(f32(x), f32(y)) * texel, light.shadow_index, depth);
+        }
+    }
+
+    return shadow / 9.0;
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+struct Params {
+    exposure: f32,
+    tonemapper: u32,
+    automatic: u32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Fraction of the way to the measured luminance covered this frame, 1 to jump straight there.
+    adaptation: f32,
+    pixel_count: u32,
+}
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: Params;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(3)
+var<storage, read_write> average_luminance: f32;
+// The same buffer as `average_luminance`, bound read only for the tone mapping itself.
+@group(0) @binding(4)
+var<storage, read> metered_luminance: f32;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Bin 0 collects pixels too dark to count, the rest spread log luminance evenly over the range.
+fn luminance_bin(l: f32) -> u32 {
+    if (l < 0.0001) {
+        return 0u;
+    }
+    let t = clamp((log2(l) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+@compute @workgroup_size(16, 16, 1)
+fn build_histogram(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) index: u32) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    let size = vec2<u32>(textureDimensions(t_hdr));
+    if (id.x < size.x && id.y < size.y) {
+        let color = textureLoad(t_hdr, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(luminance(color))], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
+
+var<workgroup> weights: array<f32, 256>;
+
+// Averages the histogram in log space, eases the stored luminance towards it and clears the
+// histogram for the next frame.
+@compute @workgroup_size(256, 1, 1)
+fn average_histogram(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weights[index] = f32(count) * f32(index);
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride = stride / 2u) {
+        if (index < stride) {
+            weights[index] += weights[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (index == 0u) {
+        let lit = max(f32(params.pixel_count) - f32(count), 1.0);
+        let log_average = (weights[0] / lit - 1.0) / 254.0 * params.log_luminance_range + params.min_log_luminance;
+        let target_luminance = exp2(log_average);
+        average_luminance = average_luminance + (target_luminance - average_luminance) * params.adaptation;
+    }
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+// A single triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms.
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Scales by luminance so bright colors keep their hue.
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + luminance(color));
+}
+
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+// AgX base look with the polynomial fit of its contrast curve, converted back to linear.
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = inset * color;
+    v = clamp(log2(max(v, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = agx_contrast((v - min_ev) / (max_ev - min_ev));
+    v = outset * v;
+    return pow(max(v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(t_hdr, vec2<i32>(in.position.xy), 0).rgb;
+
+    var exposure = exp2(params.exposure);
+    if (params.automatic != 0u) {
+        // Middle grey for the measured luminance, as a camera meter calibrated with K = 12.5 would set it.
+        exposure = exposure / (9.6 * max(metered_luminance, 0.0001));
+    }
+    let color = hdr * exposure;
+
+    switch params.tonemapper {
+        case 0u: { return vec4<f32>(aces(color), 1.0); }
+        case 1u: { return vec4<f32>(reinhard(color), 1.0); }
+        default: { return vec4<f32>(agx(color), 1.0); }
+    }
+}
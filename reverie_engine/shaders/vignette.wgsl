@@ -0,0 +1,39 @@
+struct Params {
+    color: vec3<f32>,
+    intensity: f32,
+    // Distance from the centre where darkening starts, 1 being the middle of an edge.
+    radius: f32,
+    smoothness: f32,
+    aspect: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(t_source, s_source, in.uv, 0.0).rgb;
+    let offset = (in.uv - 0.5) * 2.0 * vec2<f32>(params.aspect, 1.0);
+    let falloff = smoothstep(params.radius - params.smoothness, params.radius, length(offset));
+    return vec4<f32>(mix(color, params.color, falloff * params.intensity), 1.0);
+}
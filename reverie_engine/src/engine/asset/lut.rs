@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+// A color grading lookup table, read from the common strip layout: `size` square slices of
+// increasing blue side by side, red across each slice and green down it.
+pub struct Lut {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub size: u32,
+}
+
+impl Lut {
+    pub fn load(device: &wgpu::Device, queue: &wgpu::Queue, path: &PathBuf) -> Result<Self> {
+        let img = image::open(path)?.to_rgba8();
+        let size = img.height();
+        if img.width() != size * size {
+            bail!("{} is not a LUT strip, expected {}x{} for a {} LUT", path.display(), size * size, size, size);
+        }
+
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: path.to_str(),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for slice in 0..size {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: slice },
+                },
+                &img,
+                wgpu::ImageDataLayout {
+                    offset: (slice * size * 4) as wgpu::BufferAddress,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * img.width()),
+                    rows_per_image: std::num::NonZeroU32::new(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Ok(Self {
+            texture,
+            view,
+            size,
+        })
+    }
+}
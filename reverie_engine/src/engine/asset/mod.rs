@@ -1,3 +1,4 @@
+pub mod lut;
 pub mod material;
 pub mod model;
 pub mod texture;
\ No newline at end of file
@@ -66,6 +66,69 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub element_count: u32,
     pub material: usize,
+    pub bounds: Bounds,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub min: cg::Vector3<f32>,
+    pub max: cg::Vector3<f32>,
+}
+
+impl Bounds {
+    pub fn from_vertices(vertices: &[ModelVertex]) -> Self {
+        let mut bounds = Self {
+            min: cg::Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: cg::Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+        };
+        for vertex in vertices {
+            for i in 0..3 {
+                bounds.min[i] = bounds.min[i].min(vertex.position[i]);
+                bounds.max[i] = bounds.max[i].max(vertex.position[i]);
+            }
+        }
+        if vertices.is_empty() {
+            bounds.min = cg::Vector3::new(0.0, 0.0, 0.0);
+            bounds.max = cg::Vector3::new(0.0, 0.0, 0.0);
+        }
+        bounds
+    }
+
+    pub fn corners(&self) -> [cg::Vector3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            cg::Vector3::new(min.x, min.y, min.z),
+            cg::Vector3::new(max.x, min.y, min.z),
+            cg::Vector3::new(max.x, max.y, min.z),
+            cg::Vector3::new(min.x, max.y, min.z),
+            cg::Vector3::new(min.x, min.y, max.z),
+            cg::Vector3::new(max.x, min.y, max.z),
+            cg::Vector3::new(max.x, max.y, max.z),
+            cg::Vector3::new(min.x, max.y, max.z),
+        ]
+    }
+
+    // Slab test; returns the ray parameter of the nearest hit in front of the origin.
+    pub fn intersect(&self, origin: cg::Vector3<f32>, direction: cg::Vector3<f32>) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::MAX;
+        for i in 0..3 {
+            if direction[i].abs() < f32::EPSILON {
+                if origin[i] < self.min[i] || origin[i] > self.max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (self.min[i] - origin[i]) / direction[i];
+            let t1 = (self.max[i] - origin[i]) / direction[i];
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
 }
 
 pub trait DrawModel<'a> {
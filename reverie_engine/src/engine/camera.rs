@@ -1,13 +1,14 @@
 use wgpu::util::DeviceExt;
 
-use std::f32::consts::FRAC_PI_2;
+use std::f32::consts::{FRAC_PI_2, PI};
 
-use cg::{InnerSpace, SquareMatrix};
+use cg::{EuclideanSpace, InnerSpace, SquareMatrix};
 use instant::Duration;
+use serde::{Serialize, Deserialize};
 
 use crate::util::cast_slice;
 
-use super::input::{InputState, Key};
+use super::{input::{InputState, Key}, tonemap::ToneMapping, post::PostProcessing};
 
 pub const OPENGL_TO_WGPU_MATRIX: cg::Matrix4<f32> = cg::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -16,7 +17,18 @@ pub const OPENGL_TO_WGPU_MATRIX: cg::Matrix4<f32> = cg::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
-const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+pub const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+const ORTHO_ZOOM_STEP: f32 = 0.1;
+const MIN_ORTHO_SIZE: f32 = 0.01;
+const MAX_ORTHO_SIZE: f32 = 10000.0;
+const DEFAULT_DISTANCE: f32 = 10.0;
+const MIN_DISTANCE: f32 = 0.1;
+const DOLLY_STEP: f32 = 0.05;
+const PAN_SCALE: f32 = 0.002;
+const FRAME_MARGIN: f32 = 1.2;
+const MIN_FRAME_RADIUS: f32 = 0.5;
+// Fraction of the remaining way covered per second is 1 - e^-rate.
+const TRANSITION_RATE: f32 = 12.0;
 
 
 pub struct Camera {
@@ -24,6 +36,9 @@ pub struct Camera {
     pub yaw: cg::Rad<f32>,
     pub pitch: cg::Rad<f32>,
     pub projection: Projection,
+    pub clear_color: wgpu::Color,
+    pub tone_mapping: ToneMapping,
+    pub post_processing: PostProcessing,
     pub uniform: CameraUniform,
     pub buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
@@ -61,6 +76,9 @@ impl Camera {
             yaw: yaw.into(),
             pitch: pitch.into(),
             projection,
+            clear_color: wgpu::Color::BLACK,
+            tone_mapping: ToneMapping::default(),
+            post_processing: PostProcessing::default(),
             uniform: camera_uniform,
             buffer: camera_buffer,
             bind_group: camera_bind_group,
@@ -69,28 +87,52 @@ impl Camera {
 
     pub fn update_uniform(&mut self) {
         let view_proj = (self.projection.calc_matrix() * self.calc_matrix()).into();
-        self.uniform.update(&self.position, view_proj);
+        // An orthographic camera has a constant view direction, passed with w = 0 instead of a position.
+        let view_position = match self.projection.kind {
+            ProjectionKind::Perspective => self.position.to_homogeneous(),
+            ProjectionKind::Orthographic => (-self.forward()).extend(0.0),
+        };
+        self.uniform.update(view_position, view_proj);
     }
 
-    pub fn calc_matrix(&self) -> cg::Matrix4<f32> {
+    pub fn forward(&self) -> cg::Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
 
+        cg::Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+
+    // Ray through a point given in normalized device coordinates, as origin and direction.
+    pub fn ray(&self, ndc: cg::Vector2<f32>) -> (cg::Vector3<f32>, cg::Vector3<f32>) {
+        let inverse = (self.projection.calc_matrix() * self.calc_matrix()).invert().unwrap_or(cg::Matrix4::identity());
+        let near = inverse * cg::vec4(ndc.x, ndc.y, -1.0, 1.0);
+        let far = inverse * cg::vec4(ndc.x, ndc.y, 1.0, 1.0);
+        let near = near.truncate() / near.w;
+        let far = far.truncate() / far.w;
+        (near, (far - near).normalize())
+    }
+
+    pub fn calc_matrix(&self) -> cg::Matrix4<f32> {
         cg::Matrix4::look_to_rh(
             self.position,
-            cg::Vector3::new(
-                cos_pitch * cos_yaw,
-                sin_pitch,
-                cos_pitch * sin_yaw
-            ).normalize(),
+            self.forward(),
             cg::Vector3::unit_y(),
         )
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ProjectionKind {
+    Perspective,
+    Orthographic,
+}
+
 pub struct Projection {
     pub aspect: f32,
+    pub kind: ProjectionKind,
     fovy: cg::Rad<f32>,
+    // Half of the visible height in orthographic mode.
+    ortho_size: f32,
     znear: f32,
     zfar: f32,
 }
@@ -105,19 +147,61 @@ impl Projection {
     ) -> Self {
         Self {
             aspect: width as f32 / height as f32,
+            kind: ProjectionKind::Perspective,
             fovy: fovy.into(),
+            ortho_size: 5.0,
             znear,
             zfar,
         }
     }
 
+    pub fn set<F: Into<cg::Rad<f32>>>(&mut self, kind: ProjectionKind, fovy: F, ortho_size: f32, znear: f32, zfar: f32) {
+        self.kind = kind;
+        self.fovy = fovy.into();
+        self.ortho_size = ortho_size;
+        self.znear = znear;
+        self.zfar = zfar;
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
 
-    pub fn calc_matrix(&self) -> cg::Matrix4<f32> {
+    pub fn fovy(&self) -> cg::Rad<f32> {
+        self.fovy
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
+    pub fn ortho_size(&self) -> f32 {
+        self.ortho_size
+    }
+
+    pub fn set_ortho_size(&mut self, ortho_size: f32) {
+        self.ortho_size = ortho_size;
+    }
+
+    // The perspective matrix even in orthographic mode, for things that need a field of view like the skybox.
+    pub fn perspective_matrix(&self) -> cg::Matrix4<f32> {
         cg::perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
+
+    pub fn calc_matrix(&self) -> cg::Matrix4<f32> {
+        match self.kind {
+            ProjectionKind::Perspective => cg::perspective(self.fovy, self.aspect, self.znear, self.zfar),
+            ProjectionKind::Orthographic => {
+                let height = self.ortho_size;
+                let width = height * self.aspect;
+                cg::ortho(-width, width, -height, height, self.znear, self.zfar)
+            }
+        }
+    }
 }
 
 
@@ -136,12 +220,65 @@ impl CameraUniform {
         }
     }
 
-    pub fn update(&mut self, eye: &cg::Point3<f32>, view_proj: cg::Matrix4<f32>) {
-        self.view_position = eye.to_homogeneous();
+    pub fn update(&mut self, view_position: cg::Vector4<f32>, view_proj: cg::Matrix4<f32>) {
+        self.view_position = view_position;
         self.view_proj = view_proj;
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Navigation {
+    Fly,
+    Orbit,
+    Pan,
+}
+
+// Where a camera sits and looks, so the controller can ease between two of them.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraPose {
+    pub position: cg::Point3<f32>,
+    pub yaw: cg::Rad<f32>,
+    pub pitch: cg::Rad<f32>,
+    pub ortho_size: f32,
+}
+
+impl CameraPose {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            position: camera.position,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            ortho_size: camera.projection.ortho_size(),
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+        camera.projection.set_ortho_size(self.ortho_size);
+    }
+
+    // Yaw takes the short way around.
+    fn lerp(&self, other: &CameraPose, t: f32) -> Self {
+        let yaw = (other.yaw.0 - self.yaw.0 + PI).rem_euclid(2.0 * PI) - PI;
+        Self {
+            position: self.position + (other.position - self.position) * t,
+            yaw: cg::Rad(self.yaw.0 + yaw * t),
+            pitch: cg::Rad(self.pitch.0 + (other.pitch.0 - self.pitch.0) * t),
+            ortho_size: self.ortho_size + (other.ortho_size - self.ortho_size) * t,
+        }
+    }
+
+    fn close_to(&self, other: &CameraPose) -> bool {
+        let yaw = ((other.yaw.0 - self.yaw.0 + PI).rem_euclid(2.0 * PI) - PI).abs();
+        (other.position - self.position).magnitude() < 0.001
+            && yaw < 0.0001
+            && (other.pitch.0 - self.pitch.0).abs() < 0.0001
+            && (other.ortho_size - self.ortho_size).abs() < 0.001
+    }
+}
+
 #[derive(Debug)]
 pub struct CameraController {
     amount_left: f32,
@@ -152,9 +289,11 @@ pub struct CameraController {
     amount_down: f32,
     rotate_horizontal: f32,
     rotate_vertical: f32,
-    scroll: f32,
-    speed: f32,
-    sensitivity: f32,
+    pub speed: f32,
+    pub sensitivity: f32,
+    // Distance in front of the camera to the point it orbits around and dollies towards.
+    distance: f32,
+    transition: Option<CameraPose>,
 }
 
 impl CameraController {
@@ -168,9 +307,10 @@ impl CameraController {
             amount_down: 0.0,
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
-            scroll: 0.0,
             speed,
             sensitivity,
+            distance: DEFAULT_DISTANCE,
+            transition: None,
         }
     }
 
@@ -188,13 +328,33 @@ impl CameraController {
         self.rotate_vertical = input.cursor_delta.y as f32;
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration, input: &InputState) {
-        self.movement(input);
-        if input.right_button_down() {
-            self.process_mouse(input);
-        }
-        
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration, input: &InputState, navigation: Option<Navigation>) {
         let dt = dt.as_secs_f32();
+        if navigation.is_some() {
+            self.transition = None;
+        }
+
+        match navigation {
+            Some(Navigation::Fly) => self.fly(camera, dt, input),
+            Some(Navigation::Orbit) => self.orbit(camera, dt, input),
+            Some(Navigation::Pan) => self.pan(camera, input),
+            None => {}
+        }
+
+        if let Some(target) = self.transition {
+            let pose = CameraPose::from_camera(camera).lerp(&target, 1.0 - (-TRANSITION_RATE * dt).exp());
+            if pose.close_to(&target) {
+                target.apply(camera);
+                self.transition = None;
+            } else {
+                pose.apply(camera);
+            }
+        }
+    }
+
+    fn fly(&mut self, camera: &mut Camera, dt: f32, input: &InputState) {
+        self.movement(input);
+        self.process_mouse(input);
 
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
         let forward = cg::Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
@@ -202,13 +362,32 @@ impl CameraController {
         camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
         camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
-        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
-        let scrollward = cg::Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-        self.scroll = 0.0;
-
         camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
 
+        self.rotate(camera, dt);
+    }
+
+    fn orbit(&mut self, camera: &mut Camera, dt: f32, input: &InputState) {
+        self.process_mouse(input);
+        let pivot = camera.position + camera.forward() * self.distance;
+        self.rotate(camera, dt);
+        camera.position = pivot - camera.forward() * self.distance;
+    }
+
+    // Moves the camera and its pivot in the view plane, scaled so the scene roughly follows the cursor.
+    fn pan(&mut self, camera: &mut Camera, input: &InputState) {
+        let half_height = match camera.projection.kind {
+            ProjectionKind::Perspective => self.distance * (camera.projection.fovy().0 * 0.5).tan(),
+            ProjectionKind::Orthographic => camera.projection.ortho_size(),
+        };
+        let forward = camera.forward();
+        let right = forward.cross(cg::Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+        let delta = input.cursor_delta;
+        camera.position += (up * delta.y - right * delta.x) * half_height * PAN_SCALE;
+    }
+
+    fn rotate(&mut self, camera: &mut Camera, dt: f32) {
         camera.yaw += cg::Rad(self.rotate_horizontal) * self.sensitivity * dt;
         camera.pitch += cg::Rad(-self.rotate_vertical) * self.sensitivity * dt;
 
@@ -221,4 +400,43 @@ impl CameraController {
             camera.pitch = cg::Rad(SAFE_FRAC_PI_2);
         }
     }
-}
\ No newline at end of file
+
+    // Mouse wheel: dollies a perspective camera towards its pivot, scales the view of an orthographic one.
+    pub fn zoom(&mut self, camera: &mut Camera, amount: f32) {
+        if amount == 0.0 {
+            return;
+        }
+        self.transition = None;
+
+        match camera.projection.kind {
+            ProjectionKind::Perspective => {
+                let step = (self.distance * DOLLY_STEP * amount * self.speed * self.sensitivity).min(self.distance - MIN_DISTANCE);
+                camera.position += camera.forward() * step;
+                self.distance -= step;
+            }
+            ProjectionKind::Orthographic => {
+                let size = camera.projection.ortho_size() * (1.0 - amount * ORTHO_ZOOM_STEP).clamp(0.5, 1.5);
+                camera.projection.set_ortho_size(size.clamp(MIN_ORTHO_SIZE, MAX_ORTHO_SIZE));
+            }
+        }
+    }
+
+    // Eases the camera to `target`, which then orbits around the point `distance` in front of it.
+    pub fn transition_to(&mut self, target: CameraPose, distance: f32) {
+        self.distance = distance.max(MIN_DISTANCE);
+        self.transition = Some(target);
+    }
+
+    // Eases the camera back along its view direction until a sphere around `center` fills the view.
+    pub fn frame(&mut self, camera: &Camera, center: cg::Vector3<f32>, radius: f32) {
+        let radius = radius.max(MIN_FRAME_RADIUS) * FRAME_MARGIN / camera.projection.aspect.min(1.0);
+        let distance = radius / (camera.projection.fovy().0 * 0.5).sin();
+
+        let mut target = CameraPose::from_camera(camera);
+        target.position = cg::Point3::from_vec(center) - camera.forward() * distance;
+        if camera.projection.kind == ProjectionKind::Orthographic {
+            target.ortho_size = radius.clamp(MIN_ORTHO_SIZE, MAX_ORTHO_SIZE);
+        }
+        self.transition_to(target, distance);
+    }
+}
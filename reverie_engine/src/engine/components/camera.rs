@@ -0,0 +1,111 @@
+use anyhow::Result;
+use cg::{EuclideanSpace, Rotation};
+use serde::{Serialize, Deserialize};
+use serde_yaml::Value;
+use specs::{Component, HashMapStorage, Entity, WorldExt};
+
+use crate::engine::{camera::{Camera, ProjectionKind}, registry::Registry, scene::Scene, tonemap::ToneMapping, post::PostProcessing};
+
+use super::{ComponentDefault, TypeName, transform::decompose, registry::{SceneComponent, SaveContext, LoadContext, EditContext}};
+
+const PROJECTIONS: [&str; 2] = ["Perspective", "Orthographic"];
+
+#[derive(Clone, Component, Serialize, Deserialize)]
+#[storage(HashMapStorage)]
+pub struct CameraComponent {
+    pub projection: ProjectionKind,
+    pub fov: f32,
+    pub ortho_size: f32,
+    pub near: f32,
+    pub far: f32,
+    pub clear_color: [f32; 4],
+    #[serde(default)]
+    pub main: bool,
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    #[serde(default)]
+    pub post_processing: PostProcessing,
+}
+
+impl CameraComponent {
+    // Points `camera` along the entity's -z axis from its world position. The fly camera has no
+    // roll, so any roll in the entity's rotation is dropped.
+    pub fn apply(&self, camera: &mut Camera, world: cg::Matrix4<f32>) {
+        let (position, rotation, _) = decompose(world);
+        let forward = rotation.rotate_vector(-cg::Vector3::unit_z());
+
+        camera.position = cg::Point3::from_vec(position);
+        camera.pitch = cg::Rad(forward.y.clamp(-0.9999, 0.9999).asin());
+        camera.yaw = cg::Rad(forward.z.atan2(forward.x));
+        camera.projection.set(self.projection, cg::Deg(self.fov), self.ortho_size, self.near, self.far);
+        camera.clear_color = wgpu::Color {
+            r: self.clear_color[0] as f64,
+            g: self.clear_color[1] as f64,
+            b: self.clear_color[2] as f64,
+            a: self.clear_color[3] as f64,
+        };
+        camera.tone_mapping = self.tone_mapping;
+        camera.post_processing = self.post_processing.clone();
+    }
+}
+
+impl ComponentDefault for CameraComponent {
+    fn default(_device: &wgpu::Device, _registry: &mut Registry) -> Self {
+        Self {
+            projection: ProjectionKind::Perspective,
+            fov: 45.0,
+            ortho_size: 5.0,
+            near: 0.1,
+            far: 100.0,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            main: false,
+            tone_mapping: ToneMapping::default(),
+            post_processing: PostProcessing::default(),
+        }
+    }
+}
+
+impl TypeName for CameraComponent {
+    fn type_name() -> &'static str {
+        "camera"
+    }
+}
+
+impl SceneComponent for CameraComponent {
+    fn save(&self, _context: &SaveContext) -> Result<Value> {
+        Ok(serde_yaml::to_value(self)?)
+    }
+
+    fn load(value: Value, context: &mut LoadContext) -> Result<Self> {
+        let mut camera: Self = serde_yaml::from_value(value)?;
+        camera.post_processing.resolve(context.registry);
+        Ok(camera)
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, context: &mut EditContext) -> bool {
+        let mut cameras = scene.world.write_storage::<CameraComponent>();
+        let camera = cameras.get_mut(entity).unwrap();
+
+        let mut changed = false;
+        if ui.collapsing_header("Camera", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+            let mut projection = camera.projection as usize;
+            if ui.combo_simple_string("Projection", &mut projection, &PROJECTIONS) {
+                camera.projection = if projection == 0 { ProjectionKind::Perspective } else { ProjectionKind::Orthographic };
+                changed = true;
+            }
+            changed |= match camera.projection {
+                ProjectionKind::Perspective => imgui::Drag::new("Field of View").speed(0.5).range(1.0, 179.0).build(ui, &mut camera.fov),
+                ProjectionKind::Orthographic => imgui::Drag::new("Size").speed(0.05).range(0.01, 10000.0).build(ui, &mut camera.ortho_size),
+            };
+            changed |= imgui::Drag::new("Near").speed(0.01).range(0.001, camera.far).build(ui, &mut camera.near);
+            changed |= imgui::Drag::new("Far").speed(0.5).range(camera.near, 100000.0).build(ui, &mut camera.far);
+            changed |= ui.color_edit4("Clear Color", &mut camera.clear_color);
+            changed |= ui.checkbox("Main Camera", &mut camera.main);
+            ui.separator();
+            changed |= camera.tone_mapping.inspect(ui);
+            ui.separator();
+            changed |= camera.post_processing.inspect(ui, context.registry);
+        }
+        changed
+    }
+}
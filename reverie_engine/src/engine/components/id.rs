@@ -0,0 +1,13 @@
+use serde::{Serialize, Deserialize};
+use specs::{Component, VecStorage};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Id(pub Uuid);
+
+impl Id {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
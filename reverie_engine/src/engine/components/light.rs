@@ -1,37 +1,102 @@
+use anyhow::Result;
 use serde::{Serialize, Deserialize};
+use serde_yaml::Value;
 use specs::{prelude::*, Component};
 
 use imgui_inspector_derive::ImguiInspect;
 use imgui_inspector::*;
 
-use crate::engine::registry::Registry;
+use crate::engine::{registry::Registry, scene::{Scene, light_manager::MAX_CASCADES}};
 
-use super::{ComponentDefault, TypeName};
+use super::{ComponentDefault, TypeName, registry::{SceneComponent, SaveContext, LoadContext, EditContext}};
 
-#[derive(Clone, Component, ImguiInspect, Serialize, Deserialize)]
-#[storage(VecStorage)]
+const RESOLUTIONS: [u32; 4] = [512, 1024, 2048, 4096];
+
+// Edits go through a copy so only real changes are flagged for the light system.
+fn edit_flagged<T>(storage: &mut WriteStorage<T>, entity: Entity, edit: impl FnOnce(&mut T)) -> bool
+where
+    T: Component<Storage = FlaggedStorage<T, VecStorage<T>>> + Clone + PartialEq + Send + Sync,
+{
+    let before = storage.get(entity).unwrap();
+    let mut after = before.clone();
+    edit(&mut after);
+
+    let changed = after != *before;
+    if changed {
+        *storage.get_mut(entity).unwrap() = after;
+    }
+    changed
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PointShadowSettings {
+    pub enabled: bool,
+    // Depth offset against acne, in depth buffer units.
+    pub bias: f32,
+    pub resolution: u32,
+}
+
+impl Default for PointShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bias: 0.0001,
+            resolution: 2048,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct PointLight {
-    #[inspect(widget = "color")]
     diffuse_color: [f32; 3],
+    // Luminous power in lumens.
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    // The light fades to nothing at this distance, which is also the far plane of its shadow.
+    #[serde(default = "default_range")]
+    pub range: f32,
+    #[serde(default)]
+    pub shadows: PointShadowSettings,
+}
+
+// Matches the brightness of lights saved before intensity existed.
+fn default_intensity() -> f32 {
+    3200.0
+}
+
+fn default_range() -> f32 {
+    100.0
 }
 
 impl PointLight {
     pub fn new(diffuse_color: [f32; 3]) -> Self {
         Self {
             diffuse_color,
+            intensity: default_intensity(),
+            range: default_range(),
+            shadows: PointShadowSettings::default(),
         }
     }
 
     pub fn get_color(&self) -> cg::Vector3<f32> {
         cg::vec3(self.diffuse_color[0], self.diffuse_color[1], self.diffuse_color[2])
     }
+
+    // Luminous intensity of an isotropic source.
+    pub fn candela(&self) -> f32 {
+        self.intensity / (4.0 * std::f32::consts::PI)
+    }
+}
+
+// Flagged so the light system can pick up insertions, edits and removals.
+impl Component for PointLight {
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
 }
 
 impl ComponentDefault for PointLight {
     fn default(_device: &wgpu::Device, _registry: &mut Registry) -> Self {
-        Self {
-            diffuse_color: [1.0, 1.0, 1.0],
-        }
+        Self::new([1.0, 1.0, 1.0])
     }
 }
 
@@ -41,12 +106,75 @@ impl TypeName for PointLight {
     }
 }
 
-#[derive(Clone, Component, ImguiInspect, Serialize, Deserialize)]
+impl SceneComponent for PointLight {
+    fn save(&self, _context: &SaveContext) -> Result<Value> {
+        Ok(serde_yaml::to_value(self)?)
+    }
+
+    fn load(value: Value, _context: &mut LoadContext) -> Result<Self> {
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, _context: &mut EditContext) -> bool {
+        let mut lights = scene.world.write_storage::<PointLight>();
+        edit_flagged(&mut lights, entity, |light| {
+            if ui.collapsing_header("Point Light", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                ui.color_edit3("Color", &mut light.diffuse_color);
+                imgui::Drag::new("Intensity (lm)").speed(10.0).range(0.0, 1000000.0).build(ui, &mut light.intensity);
+                imgui::Drag::new("Range").speed(0.1).range(0.2, 10000.0).build(ui, &mut light.range);
+
+                let shadows = &mut light.shadows;
+                ui.checkbox("Cast Shadows", &mut shadows.enabled);
+                imgui::Drag::new("Shadow Bias").speed(0.00001).range(0.0, 0.01).display_format("%.5f").build(ui, &mut shadows.bias);
+                let mut resolution = RESOLUTIONS.iter().position(|&resolution| resolution == shadows.resolution).unwrap_or(0);
+                if ui.combo("Resolution", &mut resolution, &RESOLUTIONS, |resolution| resolution.to_string().into()) {
+                    shadows.resolution = RESOLUTIONS[resolution];
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CascadeSettings {
+    pub enabled: bool,
+    pub cascades: u32,
+    // 0 splits the shadow distance evenly, 1 logarithmically.
+    pub split_lambda: f32,
+    pub resolution: u32,
+    // Texels sampled on each side of the shadow lookup; 0 is a single hardware filtered tap.
+    pub pcf_radius: u32,
+    // How far from the camera shadows reach, capped by its far plane.
+    pub distance: f32,
+}
+
+impl Default for CascadeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cascades: 4,
+            split_lambda: 0.75,
+            resolution: 2048,
+            pcf_radius: 1,
+            distance: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, ImguiInspect, Serialize, Deserialize)]
 pub struct DirectionalLight {
     #[inspect(widget = "custom", speed = 0.05)]
     pub direction: cg::Vector3<f32>,
     #[inspect(widget = "color") ]
     pub color: [f32; 3],
+    #[inspect(hide = true)]
+    #[serde(default)]
+    pub shadows: CascadeSettings,
+    // Tints each cascade so its coverage can be checked in the viewport.
+    #[inspect(hide = true)]
+    #[serde(skip)]
+    pub debug_cascades: bool,
 }
 
 impl DirectionalLight {
@@ -54,15 +182,23 @@ impl DirectionalLight {
         Self {
             direction,
             color,
+            shadows: CascadeSettings::default(),
+            debug_cascades: false,
         }
     }
 }
 
+impl Component for DirectionalLight {
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
+}
+
 impl ComponentDefault for DirectionalLight {
     fn default(_device: &wgpu::Device, _registry: &mut Registry) -> Self {
         Self {
             direction: cg::vec3(-0.2, -1.0, -0.3),
             color: [1.0, 1.0, 1.0],
+            shadows: CascadeSettings::default(),
+            debug_cascades: false,
         }
     }
 }
@@ -71,4 +207,117 @@ impl TypeName for DirectionalLight {
     fn type_name() -> &'static str {
         "directional_light"
     }
-}
\ No newline at end of file
+}
+
+impl SceneComponent for DirectionalLight {
+    fn save(&self, _context: &SaveContext) -> Result<Value> {
+        Ok(serde_yaml::to_value(self)?)
+    }
+
+    fn load(value: Value, _context: &mut LoadContext) -> Result<Self> {
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, _context: &mut EditContext) -> bool {
+        let mut lights = scene.world.write_storage::<DirectionalLight>();
+        let before = lights.get(entity).unwrap().clone();
+        let edited = edit_flagged(&mut lights, entity, |light| {
+            if ui.collapsing_header("Directional Light", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                light.imgui_inspect(ui);
+
+                let shadows = &mut light.shadows;
+                ui.checkbox("Cast Shadows", &mut shadows.enabled);
+                imgui::Drag::new("Cascades").range(1, MAX_CASCADES as u32).build(ui, &mut shadows.cascades);
+                imgui::Drag::new("Split Lambda").speed(0.01).range(0.0, 1.0).build(ui, &mut shadows.split_lambda);
+                let mut resolution = RESOLUTIONS.iter().position(|&resolution| resolution == shadows.resolution).unwrap_or(0);
+                if ui.combo("Resolution", &mut resolution, &RESOLUTIONS, |resolution| resolution.to_string().into()) {
+                    shadows.resolution = RESOLUTIONS[resolution];
+                }
+                imgui::Drag::new("PCF Radius").range(0, 4).build(ui, &mut shadows.pcf_radius);
+                imgui::Drag::new("Shadow Distance").speed(0.5).range(1.0, 10000.0).build(ui, &mut shadows.distance);
+                ui.checkbox("Debug Cascades", &mut light.debug_cascades);
+            }
+        });
+
+        // The debug toggle isn't saved, so flipping only that isn't a change to the scene.
+        let after = lights.get(entity).unwrap();
+        edited && DirectionalLight { debug_cascades: before.debug_cascades, ..after.clone() } != before
+    }
+}
+
+// Shines along the entity's -z axis.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpotLight {
+    pub color: [f32; 3],
+    // Luminous power in lumens, as for point lights. The cone only masks the light, so a spot and
+    // a point light of the same power are equally bright inside the cone.
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    // Full intensity inside the inner cone, fading out to nothing at the outer one. Half angles in degrees.
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    pub range: f32,
+    #[serde(default = "default_true")]
+    pub shadows: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl SpotLight {
+    pub fn get_color(&self) -> cg::Vector3<f32> {
+        cg::vec3(self.color[0], self.color[1], self.color[2])
+    }
+
+    pub fn candela(&self) -> f32 {
+        self.intensity / (4.0 * std::f32::consts::PI)
+    }
+}
+
+impl Component for SpotLight {
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
+}
+
+impl ComponentDefault for SpotLight {
+    fn default(_device: &wgpu::Device, _registry: &mut Registry) -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0],
+            intensity: default_intensity(),
+            inner_angle: 20.0,
+            outer_angle: 30.0,
+            range: 20.0,
+            shadows: true,
+        }
+    }
+}
+
+impl TypeName for SpotLight {
+    fn type_name() -> &'static str {
+        "spot_light"
+    }
+}
+
+impl SceneComponent for SpotLight {
+    fn save(&self, _context: &SaveContext) -> Result<Value> {
+        Ok(serde_yaml::to_value(self)?)
+    }
+
+    fn load(value: Value, _context: &mut LoadContext) -> Result<Self> {
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, _context: &mut EditContext) -> bool {
+        let mut lights = scene.world.write_storage::<SpotLight>();
+        edit_flagged(&mut lights, entity, |light| {
+            if ui.collapsing_header("Spot Light", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                ui.color_edit3("Color", &mut light.color);
+                imgui::Drag::new("Intensity (lm)").speed(10.0).range(0.0, 1000000.0).build(ui, &mut light.intensity);
+                imgui::Drag::new("Inner Angle").speed(0.1).range(0.0, light.outer_angle).build(ui, &mut light.inner_angle);
+                imgui::Drag::new("Outer Angle").speed(0.1).range(light.inner_angle.max(0.1), 89.0).build(ui, &mut light.outer_angle);
+                imgui::Drag::new("Range").speed(0.1).range(0.1, 10000.0).build(ui, &mut light.range);
+                ui.checkbox("Cast Shadows", &mut light.shadows);
+            }
+        })
+    }
+}
@@ -1,11 +1,15 @@
 use std::sync::Arc;
 
+use anyhow::Result;
 use serde::Serialize;
-use specs::{Component, VecStorage};
+use serde_yaml::Value;
+use specs::{Component, VecStorage, Entity, World, WorldExt, Join};
 
-use crate::engine::{gpu::Gpu, registry::Registry, asset::material::Material};
+use imgui_inspector::ImguiInspect;
 
-use super::{ComponentDefault, TypeName};
+use crate::engine::{gpu::Gpu, registry::{Registry, AssetType}, asset::material::Material, scene::Scene};
+
+use super::{ComponentDefault, TypeName, DeserializedId, registry::{SceneComponent, SaveContext, LoadContext, EditContext}};
 
 #[derive(Clone, Component, Serialize)]
 #[storage(VecStorage)]
@@ -45,4 +49,67 @@ impl TypeName for MaterialComponent {
     fn type_name() -> &'static str {
         "material"
     }
+}
+
+impl SceneComponent for MaterialComponent {
+    fn save(&self, _context: &SaveContext) -> Result<Value> {
+        Ok(serde_yaml::to_value(self)?)
+    }
+
+    fn load(value: Value, context: &mut LoadContext) -> Result<Self> {
+        let material: DeserializedId = serde_yaml::from_value(value)?;
+        Ok(MaterialComponent::new(material.id, context.registry))
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, context: &mut EditContext) -> bool {
+        let mut materials = scene.world.write_storage::<MaterialComponent>();
+        let material = materials.get_mut(entity).unwrap();
+        let material_id = material.id.clone();
+
+        let mut changed = false;
+        if ui.collapsing_header("Material", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+            ui.button("material");
+            match ui.drag_drop_target() {
+                Some(target) => {
+                    match target.accept_payload::<Option<usize>, _>(AssetType::Material.to_string(), imgui::DragDropFlags::empty()) {
+                        Some(Ok(payload_data)) => {
+                            material.id = payload_data.data.unwrap();
+                            material.material = context.registry.get_material(material.id);
+                            changed = true;
+                        },
+                        Some(Err(e)) => {
+                            println!("{}", e);
+                        },
+                        _ => {},
+                    }
+                },
+                _ => {},
+            }
+
+            let material_path = context.registry.get_filepath(material.id);
+            ui.text(material_path.file_name().unwrap().to_str().unwrap());
+            ui.separator();
+            let mut material_asset = material.material.asset.lock().unwrap();
+            if material_asset.floats.imgui_inspect(ui).iter().any(|&value| value == true) {
+                material.material.update_floats(material_asset.floats);
+            }
+            if material_asset.imgui_inspect(ui).iter().any(|&value| value == true) {
+                material_asset.save(&material_path);
+                drop(material_asset);
+                context.registry.load_material(material_id, true);
+                drop(materials);
+                update_entity_material(&scene.world, material_id, context.registry);
+            }
+        }
+        changed
+    }
+}
+
+pub fn update_entity_material(world: &World, id: usize, registry: &mut Registry) {
+    let mut materials = world.write_storage::<MaterialComponent>();
+    for material in (&mut materials).join() {
+        if material.id == id {
+            material.material = registry.get_material(id);
+        }
+    }
 }
\ No newline at end of file
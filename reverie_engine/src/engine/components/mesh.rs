@@ -1,11 +1,13 @@
 use std::sync::Arc;
 
+use anyhow::Result;
 use serde::Serialize;
-use specs::{Component, VecStorage};
+use serde_yaml::Value;
+use specs::{Component, VecStorage, Entity, WorldExt};
 
-use crate::engine::{asset::model, registry::Registry};
+use crate::engine::{asset::model, registry::{Registry, AssetType}, scene::Scene};
 
-use super::{ComponentDefault, TypeName};
+use super::{ComponentDefault, TypeName, DeserializedId, registry::{SceneComponent, SaveContext, LoadContext, EditContext}};
 
 #[derive(Clone, Component, Serialize)]
 #[storage(VecStorage)]
@@ -41,4 +43,42 @@ impl TypeName for Mesh {
     fn type_name() -> &'static str {
         "mesh"
     }
+}
+
+impl SceneComponent for Mesh {
+    fn save(&self, _context: &SaveContext) -> Result<Value> {
+        Ok(serde_yaml::to_value(self)?)
+    }
+
+    fn load(value: Value, context: &mut LoadContext) -> Result<Self> {
+        let mesh: DeserializedId = serde_yaml::from_value(value)?;
+        Ok(Mesh::new(mesh.id, context.registry))
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, context: &mut EditContext) -> bool {
+        let mut meshes = scene.world.write_storage::<Mesh>();
+        let mesh = meshes.get_mut(entity).unwrap();
+
+        let mut changed = false;
+        if ui.collapsing_header("Mesh", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+            ui.button("mesh");
+            match ui.drag_drop_target() {
+                Some(target) => {
+                    match target.accept_payload::<Option<usize>, _>(AssetType::Mesh.to_string(), imgui::DragDropFlags::empty()) {
+                        Some(Ok(payload_data)) => {
+                            mesh.id = payload_data.data.unwrap();
+                            mesh.mesh = context.registry.get_mesh(mesh.id).unwrap();
+                            changed = true;
+                        },
+                        Some(Err(e)) => {
+                            println!("{}", e);
+                        },
+                        _ => {},
+                    }
+                }
+                _ => {},
+            }
+        }
+        changed
+    }
 }
\ No newline at end of file
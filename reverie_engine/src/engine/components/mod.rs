@@ -1,10 +1,15 @@
+use serde::Deserialize;
+
 use super::registry::Registry;
 
+pub mod registry;
+pub mod id;
 pub mod transform;
 pub mod mesh;
 pub mod material;
 pub mod name;
 pub mod light;
+pub mod camera;
 
 pub trait ComponentDefault {
     fn default(device: &wgpu::Device, registry: &mut Registry) -> Self;
@@ -12,4 +17,9 @@ pub trait ComponentDefault {
 
 pub trait TypeName {
     fn type_name() -> &'static str;
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeserializedId {
+    pub id: usize,
+}
@@ -1,9 +1,11 @@
+use anyhow::Result;
 use serde::{Serialize, Deserialize};
-use specs::{Component, DefaultVecStorage};
+use serde_yaml::Value;
+use specs::{Component, DefaultVecStorage, Entity, WorldExt};
 
-use crate::engine::registry::Registry;
+use crate::engine::{registry::Registry, scene::Scene};
 
-use super::{ComponentDefault, TypeName};
+use super::{ComponentDefault, TypeName, registry::{SceneComponent, SaveContext, LoadContext, EditContext}};
 
 #[derive(Clone, Default, Component, Serialize, Deserialize)]
 #[storage(DefaultVecStorage)]
@@ -27,4 +29,21 @@ impl TypeName for Name {
     fn type_name() -> &'static str {
         "name"
     }
+}
+
+impl SceneComponent for Name {
+    fn save(&self, _context: &SaveContext) -> Result<Value> {
+        Ok(serde_yaml::to_value(self)?)
+    }
+
+    fn load(value: Value, _context: &mut LoadContext) -> Result<Self> {
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, _context: &mut EditContext) -> bool {
+        let mut names = scene.world.write_storage::<Name>();
+        let changed = ui.input_text("##entity name", &mut names.get_mut(entity).unwrap().0).build();
+        ui.separator();
+        changed
+    }
 }
\ No newline at end of file
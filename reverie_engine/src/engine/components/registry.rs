@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde_yaml::Value;
+use specs::{Component, Entity, Join, ReadStorage, World, WorldExt};
+use uuid::Uuid;
+
+use crate::engine::{registry::Registry, scene::Scene};
+
+use super::{
+    ComponentDefault,
+    TypeName,
+    id::Id,
+    name::Name,
+    transform::TransformComponent,
+    light::{PointLight, DirectionalLight, SpotLight},
+    material::MaterialComponent,
+    mesh::Mesh,
+    camera::CameraComponent,
+};
+
+pub struct SaveContext<'a> {
+    ids: ReadStorage<'a, Id>,
+}
+
+impl<'a> SaveContext<'a> {
+    pub fn new(world: &'a World) -> Self {
+        Self {
+            ids: world.read_storage::<Id>(),
+        }
+    }
+
+    pub fn id(&self, entity: Entity) -> Option<Uuid> {
+        self.ids.get(entity).map(|id| id.0)
+    }
+}
+
+pub struct LoadContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub registry: &'a mut Registry,
+    entities: &'a HashMap<Uuid, Entity>,
+}
+
+impl<'a> LoadContext<'a> {
+    pub fn new(device: &'a wgpu::Device, registry: &'a mut Registry, entities: &'a HashMap<Uuid, Entity>) -> Self {
+        Self {
+            device,
+            registry,
+            entities,
+        }
+    }
+
+    pub fn entity(&self, id: &Uuid) -> Option<Entity> {
+        self.entities.get(id).copied()
+    }
+}
+
+pub struct EditContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub registry: &'a mut Registry,
+}
+
+pub trait SceneComponent: Component + ComponentDefault + TypeName + Send + Sync + Sized {
+    fn save(&self, context: &SaveContext) -> Result<Value>;
+    fn load(value: Value, context: &mut LoadContext) -> Result<Self>;
+
+    fn on_add(_scene: &mut Scene, _entity: Entity, _device: &wgpu::Device) {}
+    fn on_remove(_scene: &mut Scene, _entity: Entity, _device: &wgpu::Device) {}
+    fn on_change(_scene: &mut Scene, _entity: Entity, _device: &wgpu::Device, _queue: &wgpu::Queue) {}
+
+    fn inspect(_ui: &imgui::Ui, _scene: &mut Scene, _entity: Entity, _context: &mut EditContext) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ComponentEntry {
+    pub name: &'static str,
+    register: fn(&mut World),
+    has: fn(&World, Entity) -> bool,
+    save: fn(&World, Entity, &SaveContext) -> Result<Option<Value>>,
+    load: fn(&mut World, Entity, Value, &mut LoadContext) -> Result<()>,
+    add: fn(&mut Scene, Entity, &wgpu::Device, &mut Registry),
+    on_add: fn(&mut Scene, Entity, &wgpu::Device),
+    set: fn(&mut Scene, Entity, Value, &mut Registry, &wgpu::Device, &wgpu::Queue) -> Result<()>,
+    remove: fn(&mut Scene, Entity, &wgpu::Device),
+    inspect: fn(&imgui::Ui, &mut Scene, Entity, &mut EditContext) -> bool,
+}
+
+impl ComponentEntry {
+    fn new<T: SceneComponent>() -> Self where T::Storage: Default {
+        Self {
+            name: T::type_name(),
+            register: |world| {
+                world.register::<T>();
+            },
+            has: |world, entity| world.read_storage::<T>().contains(entity),
+            save: |world, entity, context| {
+                world.read_storage::<T>().get(entity).map(|component| component.save(context)).transpose()
+            },
+            load: |world, entity, value, context| {
+                let component = T::load(value, context)?;
+                world.write_storage::<T>().insert(entity, component)?;
+                Ok(())
+            },
+            add: |scene, entity, device, registry| {
+                let component = T::default(device, registry);
+                scene.world.write_storage::<T>().insert(entity, component).expect(&format!("Failed to add component: {}", T::type_name()));
+                T::on_add(scene, entity, device);
+            },
+            on_add: T::on_add,
+            set: |scene, entity, value, registry, device, queue| {
+                let entities: HashMap<Uuid, Entity> = {
+                    let ids = scene.world.read_storage::<Id>();
+                    (&scene.world.entities(), &ids).join().map(|(entity, id)| (id.0, entity)).collect()
+                };
+                let component = T::load(value, &mut LoadContext::new(device, registry, &entities))?;
+                let replaced = scene.world.write_storage::<T>().insert(entity, component)?.is_some();
+                if replaced {
+                    T::on_change(scene, entity, device, queue);
+                } else {
+                    T::on_add(scene, entity, device);
+                }
+                Ok(())
+            },
+            remove: |scene, entity, device| {
+                if scene.world.write_storage::<T>().remove(entity).is_some() {
+                    T::on_remove(scene, entity, device);
+                }
+            },
+            inspect: T::inspect,
+        }
+    }
+
+    pub fn register(&self, world: &mut World) {
+        (self.register)(world)
+    }
+
+    pub fn has(&self, world: &World, entity: Entity) -> bool {
+        (self.has)(world, entity)
+    }
+
+    pub fn save(&self, world: &World, entity: Entity, context: &SaveContext) -> Result<Option<Value>> {
+        (self.save)(world, entity, context)
+    }
+
+    pub fn load(&self, world: &mut World, entity: Entity, value: Value, context: &mut LoadContext) -> Result<()> {
+        (self.load)(world, entity, value, context)
+    }
+
+    pub fn add(&self, scene: &mut Scene, entity: Entity, device: &wgpu::Device, registry: &mut Registry) {
+        (self.add)(scene, entity, device, registry)
+    }
+
+    pub fn on_add(&self, scene: &mut Scene, entity: Entity, device: &wgpu::Device) {
+        (self.on_add)(scene, entity, device)
+    }
+
+    // Replaces the component with one loaded from `value`, running the same hooks as an edit.
+    pub fn set(&self, scene: &mut Scene, entity: Entity, value: Value, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        (self.set)(scene, entity, value, registry, device, queue)
+    }
+
+    pub fn remove(&self, scene: &mut Scene, entity: Entity, device: &wgpu::Device) {
+        (self.remove)(scene, entity, device)
+    }
+
+    pub fn inspect(&self, ui: &imgui::Ui, scene: &mut Scene, entity: Entity, context: &mut EditContext) -> bool {
+        (self.inspect)(ui, scene, entity, context)
+    }
+}
+
+pub struct ComponentRegistry {
+    entries: Vec<ComponentEntry>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            entries: Vec::new(),
+        };
+
+        registry.register::<Name>();
+        registry.register::<TransformComponent>();
+        registry.register::<PointLight>();
+        registry.register::<DirectionalLight>();
+        registry.register::<SpotLight>();
+        registry.register::<MaterialComponent>();
+        registry.register::<Mesh>();
+        registry.register::<CameraComponent>();
+
+        registry
+    }
+
+    pub fn register<T: SceneComponent>(&mut self) where T::Storage: Default {
+        let entry = ComponentEntry::new::<T>();
+        match self.entries.iter_mut().find(|existing| existing.name == entry.name) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ComponentEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ComponentEntry> {
+        self.entries.iter()
+    }
+}
@@ -3,15 +3,20 @@ use std::collections::HashMap;
 
 use cg::InnerSpace;
 use cg::Matrix;
+use cg::Rotation3;
 use cg::SquareMatrix;
 use serde::Deserialize;
 use serde::Serialize;
+use anyhow::Result;
+use serde_yaml::Value;
 use specs::{prelude::*, Component};
+use uuid::Uuid;
 
 use wgpu::util::DeviceExt;
 
 use crate::engine::registry::Registry;
-use crate::engine::renderer::Renderer;
+use crate::engine::layouts::Layouts;
+use crate::engine::scene::Scene;
 use crate::util::align::Align16;
 use crate::util::cast_slice;
 
@@ -20,37 +25,46 @@ use imgui_inspector::*;
 
 use super::ComponentDefault;
 use super::TypeName;
+use super::registry::{SceneComponent, SaveContext, LoadContext, EditContext};
 
-#[derive(Deserialize)]
-pub struct DeserializedTransform {
+#[derive(Serialize, Deserialize)]
+pub struct SerializedTransform {
     pub position: cg::Vector3<f32>,
-    pub rotation: cg::Vector3<f32>,
+    pub rotation: cg::Quaternion<f32>,
     pub scale: cg::Vector3<f32>,
-    pub parent: Option<u32>,
-    pub children: Vec<u32>,
+    #[serde(default)]
+    pub parent: Option<Uuid>,
+    #[serde(default)]
+    pub children: Vec<Uuid>,
 }
 
-#[derive(Clone, ImguiInspect, Serialize)]
+#[derive(Clone, ImguiInspect)]
 pub struct Transform {
     #[inspect(widget = "custom", speed = 0.01)]
     position: cg::Vector3<f32>,
+    // Euler degrees kept alongside the quaternion so editing in the inspector doesn't jump between equivalent angles.
     #[inspect(widget = "custom")]
-    rotation: cg::Vector3<f32>,
+    euler: cg::Vector3<f32>,
     #[inspect(widget = "custom", min = 0.001, max = 100.0, speed = 0.01)]
     scale: cg::Vector3<f32>,
 
     #[inspect(hide = true)]
-    pub parent: Option<u32>,
+    rotation: cg::Quaternion<f32>,
 
     #[inspect(hide = true)]
-    pub children: Vec<u32>,
+    pub parent: Option<Entity>,
+
+    #[inspect(hide = true)]
+    pub children: Vec<Entity>,
 
     #[inspect(hide = true)]
-    #[serde(skip)]
     matrix: cg::Matrix4<f32>,
     #[inspect(hide = true)]
-    #[serde(skip)]
     normal_matrix: cg::Matrix4<f32>,
+
+    // Set whenever the local transform changes; cleared once `TransformSystem` has propagated it.
+    #[inspect(hide = true)]
+    pub dirty: bool,
 }
 
 #[derive(Component)]
@@ -63,7 +77,7 @@ pub struct TransformComponent {
 }
 
 impl TransformComponent {
-    pub fn new(transform: Transform, device: &wgpu::Device) -> Self {
+    pub fn new(transform: Transform, device: &wgpu::Device, layouts: &Layouts) -> Self {
         let matrix_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("matrix_buffer"),
             contents: cast_slice(&[transform.matrix, transform.normal_matrix]),
@@ -77,7 +91,7 @@ impl TransformComponent {
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_transform_layout(),
+            layout: &layouts.transform,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -96,6 +110,7 @@ impl TransformComponent {
 
     pub fn set_position(&mut self, position: cg::Vector3<f32>, queue: &wgpu::Queue, parent_matrix: Option<cg::Matrix4<f32>>) {
         self.data.position = position;
+        self.data.dirty = true;
         self.data.update_matrix(parent_matrix);
         self.update_buffers(queue);
     }
@@ -104,11 +119,21 @@ impl TransformComponent {
         self.data.position
     }
 
-    
-    pub fn get_rotation(&self) -> cg::Vector3<f32> {
+    pub fn set_transform(&mut self, position: cg::Vector3<f32>, rotation: cg::Quaternion<f32>, scale: cg::Vector3<f32>) {
+        self.data.position = position;
+        self.data.set_rotation(rotation);
+        self.data.scale = scale;
+        self.data.dirty = true;
+    }
+
+    pub fn get_rotation(&self) -> cg::Quaternion<f32> {
         self.data.rotation
     }
 
+    pub fn get_euler(&self) -> cg::Vector3<f32> {
+        self.data.euler
+    }
+
     pub fn get_scale(&self) -> cg::Vector3<f32> {
         self.data.scale
     }
@@ -117,53 +142,91 @@ impl TransformComponent {
         self.data.matrix
     }
 
+    pub fn get_world_position(&self) -> cg::Vector3<f32> {
+        self.data.matrix.w.truncate()
+    }
+
+    pub fn get_world_rotation(&self) -> cg::Quaternion<f32> {
+        decompose(self.data.matrix).1
+    }
+
+    pub fn get_world_scale(&self) -> cg::Vector3<f32> {
+        decompose(self.data.matrix).2
+    }
+
+    // The world setters take the parent's world matrix, like `set_position`. Children follow
+    // the next time the transform system runs, or immediately through `Scene::update_transform`.
+    pub fn set_world_position(&mut self, position: cg::Vector3<f32>, parent_matrix: Option<cg::Matrix4<f32>>) {
+        let mut matrix = self.data.matrix;
+        matrix.w = position.extend(1.0);
+        self.set_world_matrix(matrix, parent_matrix);
+    }
+
+    pub fn set_world_rotation(&mut self, rotation: cg::Quaternion<f32>, parent_matrix: Option<cg::Matrix4<f32>>) {
+        let (position, _, scale) = decompose(self.data.matrix);
+        self.set_world_matrix(compose(position, rotation, scale), parent_matrix);
+    }
+
+    pub fn set_world_scale(&mut self, scale: cg::Vector3<f32>, parent_matrix: Option<cg::Matrix4<f32>>) {
+        let (position, rotation, _) = decompose(self.data.matrix);
+        self.set_world_matrix(compose(position, rotation, scale), parent_matrix);
+    }
+
+    pub fn set_world_matrix(&mut self, matrix: cg::Matrix4<f32>, parent_matrix: Option<cg::Matrix4<f32>>) {
+        let local = match parent_matrix.and_then(|parent_matrix| parent_matrix.invert()) {
+            Some(inverse_parent) => inverse_parent * matrix,
+            None => matrix,
+        };
+
+        let (position, rotation, scale) = decompose(local);
+        self.set_transform(position, rotation, scale);
+        self.data.update_matrix(parent_matrix);
+    }
+
     pub fn update_buffers(&self, queue: &wgpu::Queue) {
         queue.write_buffer(&self.buffers.get("matrix").unwrap(), 0, cast_slice(&[self.data.matrix, self.data.normal_matrix]));
         queue.write_buffer(&self.buffers.get("position").unwrap(), 0, cast_slice(&[self.data.position]));
     }
 
+    // Keeps the current world transform while re-expressing it relative to a new parent.
     pub fn update_local_transformation(&mut self, parent_matrix: cg::Matrix4<f32>) {
-        let inverse_parent = parent_matrix.invert().unwrap();
-        let new_matrix: cg::Matrix4<f32> = inverse_parent * self.data.matrix;
-
-        let position = new_matrix.w.truncate();
-
-        let scale = cg::vec3(
-            new_matrix.x.truncate().magnitude(),
-            new_matrix.y.truncate().magnitude(),
-            new_matrix.z.truncate().magnitude(),
-        );
-        
-        self.data.position = position;
-        self.data.scale = scale;
-
-        self.data.update_matrix(Some(parent_matrix));
+        self.set_world_matrix(self.data.matrix, Some(parent_matrix));
     }
 }
 
 
 impl Transform {
-    pub fn new(position: cg::Vector3<f32>, rotation: cg::Vector3<f32>, scale: cg::Vector3<f32>, parent: Option<u32>) -> Self {
+    pub fn new(position: cg::Vector3<f32>, rotation: cg::Quaternion<f32>, scale: cg::Vector3<f32>, parent: Option<Entity>) -> Self {
         
-        let matrix = calculate_transform_matrix(position, rotation, scale);
+        let matrix = compose(position, rotation, scale);
 
         Self {
             position,
-            rotation,
+            euler: euler_from_quaternion(rotation),
             scale,
+            rotation,
             parent,
             children: Vec::new(),
             matrix,
             normal_matrix: matrix.invert().unwrap().transpose(),
+            dirty: true,
         }
     }
 
+    pub fn set_rotation(&mut self, rotation: cg::Quaternion<f32>) {
+        self.rotation = rotation.normalize();
+        self.euler = euler_from_quaternion(self.rotation);
+        self.dirty = true;
+    }
+
+    pub fn set_euler(&mut self, euler: cg::Vector3<f32>) {
+        self.euler = euler;
+        self.rotation = quaternion_from_euler(euler);
+        self.dirty = true;
+    }
+
     pub fn update_matrix(&mut self, parent_matrix: Option<cg::Matrix4<f32>>) {
-        let rotation = cg::Matrix4::from_angle_x(cg::Deg(self.rotation.x))
-            * cg::Matrix4::from_angle_y(cg::Deg(self.rotation.y))
-            * cg::Matrix4::from_angle_z(cg::Deg(self.rotation.z));
-        
-        self.matrix = cg::Matrix4::from_translation(self.position) * rotation * cg::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
+        self.matrix = compose(self.position, self.rotation, self.scale);
 
         if let Some(parent_matrix) = parent_matrix {
             self.matrix = parent_matrix * self.matrix;
@@ -179,56 +242,74 @@ impl Default for Transform {
 
         Self { 
             position: cg::Vector3 { x: 0.0, y: 0.0, z: 0.0 },
-            rotation: cg::Vector3 { x: 0.0, y: 0.0, z: 0.0 },
+            euler: cg::Vector3 { x: 0.0, y: 0.0, z: 0.0 },
             scale: cg::Vector3 { x: 1.0, y: 1.0, z: 1.0 },
+            rotation: cg::Quaternion::new(1.0, 0.0, 0.0, 0.0),
             parent: None,
             children: Vec::new(),
             matrix: cg::SquareMatrix::identity(),
             normal_matrix: matrix.invert().unwrap().transpose(),
+            dirty: true,
         }
     }
 }
 
-fn calculate_transform_matrix(position: cg::Vector3<f32>, rotation: cg::Vector3<f32>, scale: cg::Vector3<f32>) -> cg::Matrix4<f32> {
-    let rotation = cg::Matrix4::from_angle_x(cg::Deg(rotation.x))
-        * cg::Matrix4::from_angle_y(cg::Deg(rotation.y))
-        * cg::Matrix4::from_angle_z(cg::Deg(rotation.z));
-    
-    cg::Matrix4::from_translation(position) * rotation * cg::Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
+pub fn compose(position: cg::Vector3<f32>, rotation: cg::Quaternion<f32>, scale: cg::Vector3<f32>) -> cg::Matrix4<f32> {
+    cg::Matrix4::from_translation(position) * cg::Matrix4::from(rotation) * cg::Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
 }
 
-impl ComponentDefault for TransformComponent {
-    fn default(device: &wgpu::Device, _registry: &mut Registry) -> Self {
-        let transform = Transform::default();
+// Splits a matrix into translation, rotation and scale. Any shear left over from non-uniform
+// scaling in a parent is dropped by re-orthogonalizing the rotation axes, and a mirrored matrix
+// is represented with a negative x scale.
+pub fn decompose(matrix: cg::Matrix4<f32>) -> (cg::Vector3<f32>, cg::Quaternion<f32>, cg::Vector3<f32>) {
+    let position = matrix.w.truncate();
 
-        let matrix_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("matrix_buffer"),
-            contents: cast_slice(&[transform.matrix, transform.normal_matrix]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    let mut x = matrix.x.truncate();
+    let mut y = matrix.y.truncate();
+    let z = matrix.z.truncate();
+    let mut scale = cg::vec3(x.magnitude(), y.magnitude(), z.magnitude());
 
-        let position_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("position_buffer"),
-            contents: cast_slice(&[Align16(transform.position)]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    if x.cross(y).dot(z) < 0.0 {
+        scale.x = -scale.x;
+        x = -x;
+    }
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_transform_layout(),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: matrix_buffer.as_entire_binding()
-                }
-            ],
-            label: None,
-        });
+    x = if scale.x.abs() > f32::EPSILON { x.normalize() } else { cg::Vector3::unit_x() };
+    y = y - x * x.dot(y);
+    y = if y.magnitude2() > f32::EPSILON { y.normalize() } else { orthogonal(x) };
+    let z = x.cross(y);
 
-        Self {
-            data: Transform::default(),
-            buffers: HashMap::from([("matrix", matrix_buffer), ("position", position_buffer)]),
-            bind_group,
-        }
+    let rotation = cg::Quaternion::from(cg::Matrix3::from_cols(x, y, z)).normalize();
+    (position, rotation, scale)
+}
+
+fn orthogonal(axis: cg::Vector3<f32>) -> cg::Vector3<f32> {
+    let helper = if axis.x.abs() < 0.9 { cg::Vector3::unit_x() } else { cg::Vector3::unit_y() };
+    axis.cross(helper).normalize()
+}
+
+// Euler angles are in degrees and applied as x * y * z.
+pub fn quaternion_from_euler(euler: cg::Vector3<f32>) -> cg::Quaternion<f32> {
+    cg::Quaternion::from_angle_x(cg::Deg(euler.x))
+        * cg::Quaternion::from_angle_y(cg::Deg(euler.y))
+        * cg::Quaternion::from_angle_z(cg::Deg(euler.z))
+}
+
+pub fn euler_from_quaternion(rotation: cg::Quaternion<f32>) -> cg::Vector3<f32> {
+    let matrix = cg::Matrix3::from(rotation);
+    let sin_y = matrix.z.x.clamp(-1.0, 1.0);
+    let (x, y, z) = if sin_y.abs() < 0.9999 {
+        ((-matrix.z.y).atan2(matrix.z.z), sin_y.asin(), (-matrix.y.x).atan2(matrix.x.x))
+    } else {
+        (matrix.y.z.atan2(matrix.y.y), sin_y.asin(), 0.0)
+    };
+
+    cg::vec3(x.to_degrees(), y.to_degrees(), z.to_degrees())
+}
+
+impl ComponentDefault for TransformComponent {
+    fn default(device: &wgpu::Device, registry: &mut Registry) -> Self {
+        Self::new(Transform::default(), device, &registry.layouts)
     }
 }
 
@@ -236,4 +317,123 @@ impl TypeName for TransformComponent {
     fn type_name() -> &'static str {
         "transform"
     }
-}
\ No newline at end of file
+}
+
+impl SceneComponent for TransformComponent {
+    fn save(&self, context: &SaveContext) -> Result<Value> {
+        let transform = SerializedTransform {
+            position: self.data.position,
+            rotation: self.data.rotation,
+            scale: self.data.scale,
+            parent: self.data.parent.and_then(|parent| context.id(parent)),
+            children: self.data.children.iter().filter_map(|child| context.id(*child)).collect(),
+        };
+
+        Ok(serde_yaml::to_value(transform)?)
+    }
+
+    fn load(value: Value, context: &mut LoadContext) -> Result<Self> {
+        let transform: SerializedTransform = serde_yaml::from_value(value)?;
+        let parent = transform.parent.and_then(|parent| context.entity(&parent));
+        let mut data = Transform::new(transform.position, transform.rotation, transform.scale, parent);
+        data.children = transform.children.iter().filter_map(|child| context.entity(child)).collect();
+
+        Ok(TransformComponent::new(data, context.device, &context.registry.layouts))
+    }
+
+    fn on_change(scene: &mut Scene, entity: Entity, _device: &wgpu::Device, queue: &wgpu::Queue) {
+        scene.update_transform(entity, queue);
+    }
+
+    fn inspect(ui: &imgui::Ui, scene: &mut Scene, entity: Entity, context: &mut EditContext) -> bool {
+        let mut transforms = scene.world.write_storage::<TransformComponent>();
+        let transform = transforms.get_mut(entity).unwrap();
+
+        let mut changed = false;
+        if ui.collapsing_header("Transform", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+            let euler = transform.data.euler;
+            changed = transform.data.imgui_inspect(ui).iter().any(|&value| value == true);
+            if transform.data.euler != euler {
+                let euler = transform.data.euler;
+                transform.data.set_euler(euler);
+            }
+            transform.data.dirty |= changed;
+        }
+        drop(transforms);
+
+        if changed {
+            scene.update_transform(entity, context.queue);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::compose;
+
+    fn assert_matrix_eq(a: cg::Matrix4<f32>, b: cg::Matrix4<f32>) {
+        let a: &[f32; 16] = a.as_ref();
+        let b: &[f32; 16] = b.as_ref();
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn decompose_round_trips_trs() {
+        let position = cg::vec3(1.0, -2.0, 3.5);
+        let rotation = quaternion_from_euler(cg::vec3(20.0, -75.0, 140.0));
+        let scale = cg::vec3(0.5, 2.0, 3.0);
+
+        let (p, r, s) = decompose(compose(position, rotation, scale));
+        assert_matrix_eq(compose(p, r, s), compose(position, rotation, scale));
+        assert!((s - scale).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn decompose_keeps_negative_scale() {
+        let rotation = quaternion_from_euler(cg::vec3(0.0, 30.0, 0.0));
+        for scale in [cg::vec3(-1.0, 1.0, 1.0), cg::vec3(2.0, -3.0, 1.0), cg::vec3(-1.0, -1.0, -1.0)] {
+            let matrix = compose(cg::vec3(0.0, 1.0, 0.0), rotation, scale);
+            let (p, r, s) = decompose(matrix);
+
+            assert_matrix_eq(compose(p, r, s), matrix);
+            assert!((r.magnitude() - 1.0).abs() < 1e-5);
+            assert_eq!(s.x * s.y * s.z < 0.0, matrix.determinant() < 0.0);
+        }
+    }
+
+    #[test]
+    fn decompose_drops_shear() {
+        let mut matrix = compose(cg::vec3(4.0, 5.0, 6.0), quaternion_from_euler(cg::vec3(10.0, 20.0, 30.0)), cg::vec3(1.0, 2.0, 1.0));
+        matrix.y += matrix.x * 0.5;
+
+        let (p, r, s) = decompose(matrix);
+        assert!((p - cg::vec3(4.0, 5.0, 6.0)).magnitude() < 1e-5);
+        assert!((r.magnitude() - 1.0).abs() < 1e-5);
+        assert!((s.x - matrix.x.truncate().magnitude()).abs() < 1e-5);
+        assert!((s.y - matrix.y.truncate().magnitude()).abs() < 1e-5);
+
+        // The x axis keeps its direction, the others are made orthogonal to it.
+        let x = cg::Matrix3::from(r).x;
+        assert!((x - matrix.x.truncate().normalize()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn decompose_handles_zero_scale() {
+        let (_, r, s) = decompose(compose(cg::vec3(0.0, 0.0, 0.0), quaternion_from_euler(cg::vec3(0.0, 0.0, 45.0)), cg::vec3(0.0, 1.0, 1.0)));
+        assert!(r.s.is_finite() && r.v.x.is_finite() && r.v.y.is_finite() && r.v.z.is_finite());
+        assert!((r.magnitude() - 1.0).abs() < 1e-5);
+        assert_eq!(s.x, 0.0);
+    }
+
+    #[test]
+    fn euler_round_trips() {
+        for euler in [cg::vec3(10.0, 20.0, 30.0), cg::vec3(-45.0, 60.0, -170.0), cg::vec3(0.0, 0.0, 0.0)] {
+            let back = euler_from_quaternion(quaternion_from_euler(euler));
+            assert!((back - euler).magnitude() < 1e-3, "{:?} != {:?}", back, euler);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 pub struct Context {
-    pub surface: wgpu::Surface,
+    pub surface: Option<wgpu::Surface>,
     pub device: Arc<wgpu::Device>,
     pub queue: Arc<wgpu::Queue>,
     pub config: wgpu::SurfaceConfiguration,
@@ -11,10 +11,7 @@ impl Context {
     pub async fn new(window: &winit::window::Window) -> Self {
         let window_size = window.inner_size();
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
-        });
+        let instance = create_instance();
         let surface = unsafe { instance.create_surface(&window) }.unwrap();
         let adapter = instance.request_adapter(
             &wgpu::RequestAdapterOptions {
@@ -24,24 +21,7 @@ impl Context {
             }
         ).await.unwrap();
 
-        let (device, queue) = adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::BUFFER_BINDING_ARRAY | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY | wgpu::Features::TEXTURE_BINDING_ARRAY,
-                limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                label: None
-            },
-            None,
-        ).await.unwrap();
-
-
-        let queue = Arc::new(queue);
-        let device = Arc::new(device);
-        
-        dbg!(adapter.get_info());
+        let (device, queue) = request_device(&adapter).await;
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps.formats.iter()
@@ -62,7 +42,53 @@ impl Context {
         surface.configure(&device, &config);
 
         Self {
-            surface,
+            surface: Some(surface),
+            device,
+            queue,
+            config,
+        }
+    }
+
+    pub async fn new_headless(width: u32, height: u32, force_fallback_adapter: bool) -> Self {
+        let instance = create_instance();
+
+        let hardware_adapter = if force_fallback_adapter {
+            None
+        } else {
+            instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                    force_fallback_adapter: false,
+                }
+            ).await
+        };
+
+        let adapter = match hardware_adapter {
+            Some(adapter) => adapter,
+            None => instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::LowPower,
+                    compatible_surface: None,
+                    force_fallback_adapter: true,
+                }
+            ).await.expect("No adapter available for headless rendering"),
+        };
+
+        let (device, queue) = request_device(&adapter).await;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+
+        Self {
+            surface: None,
             device,
             queue,
             config,
@@ -70,6 +96,32 @@ impl Context {
     }
 }
 
+fn create_instance() -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
+    })
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> (Arc<wgpu::Device>, Arc<wgpu::Queue>) {
+    let (device, queue) = adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::BUFFER_BINDING_ARRAY | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY | wgpu::Features::TEXTURE_BINDING_ARRAY,
+            limits: if cfg!(target_arch = "wasm32") {
+                wgpu::Limits::downlevel_webgl2_defaults()
+            } else {
+                wgpu::Limits::default()
+            },
+            label: None
+        },
+        None,
+    ).await.unwrap();
+
+    log::info!("{:?}", adapter.get_info());
+
+    (Arc::new(device), Arc::new(queue))
+}
+
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
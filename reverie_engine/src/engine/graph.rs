@@ -0,0 +1,406 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::scene::Scene;
+use super::camera::Camera;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ResourceId(usize);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextureDesc {
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+}
+
+enum Resource<'a> {
+    // Owned outside the graph, like the output view or the shadow maps.
+    Imported(&'a wgpu::TextureView),
+    // Only lives between the first and last pass using it, backed by a texture from the pool.
+    Transient(TextureDesc),
+}
+
+struct PassNode<'a> {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    run: Box<dyn FnOnce(&GraphResources<'_>, &mut wgpu::CommandEncoder) + 'a>,
+}
+
+// Adds a feature's passes to the frame's graph. Nodes find the frame's targets by name, "output",
+// "hdr" and "depth", and can name their own resources for nodes added after them.
+pub trait RenderNode {
+    fn add_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, scene: &'a Scene, camera: &'a Camera);
+}
+
+// One frame's passes and the textures they use. Passes writing the same resource run in the order
+// they were added, and a pass only reading it runs after all of them, so a node added late can still
+// draw into "hdr" before tone mapping reads it. Passes whose results nothing reads are dropped
+// before anything runs, and a cycle between passes panics.
+pub struct RenderGraph<'a> {
+    names: HashMap<String, Vec<ResourceId>>,
+    resources: Vec<Resource<'a>>,
+    passes: Vec<PassNode<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            names: HashMap::new(),
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn import(&mut self, name: &str, view: &'a wgpu::TextureView) -> ResourceId {
+        self.add_resource(name, Resource::Imported(view))
+    }
+
+    pub fn create(&mut self, name: &str, desc: TextureDesc) -> ResourceId {
+        self.add_resource(name, Resource::Transient(desc))
+    }
+
+    // The last resource added under `name`.
+    pub fn resource(&self, name: &str) -> Option<ResourceId> {
+        self.names.get(name).and_then(|ids| ids.last()).copied()
+    }
+
+    // Every resource added under `name`, several shadow maps go by "shadow_map" for instance.
+    pub fn resources(&self, name: &str) -> &[ResourceId] {
+        self.names.get(name).map_or(&[], |ids| ids.as_slice())
+    }
+
+    pub fn add_pass(&mut self, name: &str, reads: &[ResourceId], writes: &[ResourceId], run: impl FnOnce(&GraphResources<'_>, &mut wgpu::CommandEncoder) + 'a) {
+        self.passes.push(PassNode {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            run: Box::new(run),
+        });
+    }
+
+    pub fn execute(self, device: &wgpu::Device, pool: &mut TexturePool, encoder: &mut wgpu::CommandEncoder) {
+        let order = self.order();
+
+        let mut first = vec![None; self.resources.len()];
+        let mut last = vec![0; self.resources.len()];
+        for (step, &pass) in order.iter().enumerate() {
+            let pass = &self.passes[pass];
+            for id in pass.reads.iter().chain(pass.writes.iter()) {
+                first[id.0].get_or_insert(step);
+                last[id.0] = step;
+            }
+        }
+
+        let transients = self.resources.iter().enumerate().filter_map(|(i, resource)| match resource {
+            Resource::Transient(desc) => first[i].map(|first| (i, *desc, first, last[i])),
+            Resource::Imported(_) => None,
+        }).collect::<Vec<_>>();
+        let slots = pool.allocate(device, &transients);
+
+        let resources = GraphResources {
+            device,
+            views: self.resources.iter().enumerate().map(|(i, resource)| match resource {
+                Resource::Imported(view) => Some(*view),
+                Resource::Transient(_) => slots.get(&i).map(|&slot| pool.view(slot)),
+            }).collect(),
+        };
+
+        let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+        for pass in order {
+            let pass = passes[pass].take().unwrap();
+            encoder.push_debug_group(&pass.name);
+            (pass.run)(&resources, encoder);
+            encoder.pop_debug_group();
+        }
+    }
+
+    fn add_resource(&mut self, name: &str, resource: Resource<'a>) -> ResourceId {
+        let id = ResourceId(self.resources.len());
+        self.resources.push(resource);
+        self.names.entry(name.to_string()).or_default().push(id);
+        id
+    }
+
+    fn order(&self) -> Vec<usize> {
+        let imported = self.resources.iter().map(|resource| matches!(resource, Resource::Imported(_))).collect::<Vec<_>>();
+        order(&self.passes, &imported)
+    }
+}
+
+// Each writer of a resource waits for the one added before it, which covers write after write and,
+// for passes changing it in place, write after read. Passes only reading it wait for its last
+// writer. A pass is kept when it writes an imported resource or something a kept pass waits on,
+// and among the passes ready to run the one added first goes next.
+fn order(passes: &[PassNode<'_>], imported: &[bool]) -> Vec<usize> {
+    let mut writers = vec![Vec::new(); imported.len()];
+    let mut readers = vec![Vec::new(); imported.len()];
+    for (i, pass) in passes.iter().enumerate() {
+        for id in &pass.writes {
+            if writers[id.0].last() != Some(&i) {
+                writers[id.0].push(i);
+            }
+        }
+        for id in pass.reads.iter().filter(|id| !pass.writes.contains(id)) {
+            readers[id.0].push(i);
+        }
+    }
+
+    let mut inputs = vec![Vec::new(); passes.len()];
+    for (writers, readers) in writers.iter().zip(&readers) {
+        for pair in writers.windows(2) {
+            inputs[pair[1]].push(pair[0]);
+        }
+        if let Some(&last) = writers.last() {
+            for &reader in readers {
+                inputs[reader].push(last);
+            }
+        }
+    }
+    for inputs in &mut inputs {
+        inputs.sort();
+        inputs.dedup();
+    }
+
+    let mut alive = passes.iter().map(|pass| pass.writes.iter().any(|id| imported[id.0])).collect::<Vec<_>>();
+    let mut stack = (0..passes.len()).filter(|&i| alive[i]).collect::<Vec<_>>();
+    while let Some(i) = stack.pop() {
+        for &input in &inputs[i] {
+            if !alive[input] {
+                alive[input] = true;
+                stack.push(input);
+            }
+        }
+    }
+
+    let mut outputs = vec![Vec::new(); passes.len()];
+    for (i, inputs) in inputs.iter().enumerate() {
+        for &input in inputs {
+            outputs[input].push(i);
+        }
+    }
+    let mut waiting = inputs.iter().map(|inputs| inputs.len()).collect::<Vec<_>>();
+    let mut ready = (0..passes.len()).filter(|&i| alive[i] && waiting[i] == 0).collect::<BTreeSet<_>>();
+    let mut order = Vec::new();
+    while let Some(i) = ready.pop_first() {
+        order.push(i);
+        for &output in &outputs[i] {
+            waiting[output] -= 1;
+            if waiting[output] == 0 && alive[output] {
+                ready.insert(output);
+            }
+        }
+    }
+
+    if order.len() < alive.iter().filter(|&&alive| alive).count() {
+        let stuck = (0..passes.len()).filter(|&i| alive[i] && waiting[i] > 0).map(|i| passes[i].name.as_str()).collect::<Vec<_>>();
+        panic!("render graph has a cycle between {}", stuck.join(", "));
+    }
+    order
+}
+
+pub struct GraphResources<'r> {
+    device: &'r wgpu::Device,
+    views: Vec<Option<&'r wgpu::TextureView>>,
+}
+
+impl<'r> GraphResources<'r> {
+    // For bind groups over transient views, which change from frame to frame.
+    pub fn device(&self) -> &wgpu::Device {
+        self.device
+    }
+
+    pub fn view(&self, id: ResourceId) -> &wgpu::TextureView {
+        self.views[id.0].expect("resource used by a pass that didn't declare it")
+    }
+}
+
+struct PoolSlot {
+    desc: TextureDesc,
+    view: wgpu::TextureView,
+    used: bool,
+}
+
+// Textures backing transient resources, kept between frames so the same graph reuses them. Two
+// transients with the same description share a texture when their lifetimes don't overlap.
+pub struct TexturePool {
+    slots: Vec<PoolSlot>,
+}
+
+impl TexturePool {
+    pub fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+
+    // Takes (resource, desc, first step, last step) and returns the slot given to each resource.
+    fn allocate(&mut self, device: &wgpu::Device, transients: &[(usize, TextureDesc, usize, usize)]) -> HashMap<usize, usize> {
+        // Anything last frame's graph didn't ask for, after a resize say, is dropped.
+        self.slots.retain(|slot| slot.used);
+
+        let existing = self.slots.iter().map(|slot| slot.desc).collect::<Vec<_>>();
+        let (assigned, descs) = assign_slots(&existing, transients);
+        for desc in &descs[existing.len()..] {
+            self.slots.push(PoolSlot {
+                desc: *desc,
+                view: create_texture(device, desc).create_view(&wgpu::TextureViewDescriptor::default()),
+                used: false,
+            });
+        }
+
+        for slot in &mut self.slots {
+            slot.used = false;
+        }
+        for &slot in assigned.values() {
+            self.slots[slot].used = true;
+        }
+        assigned
+    }
+
+    fn view(&self, slot: usize) -> &wgpu::TextureView {
+        &self.slots[slot].view
+    }
+}
+
+// Gives each transient the first slot with its description that is free by its first step, adding
+// slots when none is. Returns the slot of each resource and the descriptions of all the slots,
+// `existing` followed by the added ones.
+fn assign_slots(existing: &[TextureDesc], transients: &[(usize, TextureDesc, usize, usize)]) -> (HashMap<usize, usize>, Vec<TextureDesc>) {
+    let mut descs = existing.to_vec();
+    // Last step each slot is taken until, None while free.
+    let mut busy_until = vec![None; descs.len()];
+
+    let mut transients = transients.to_vec();
+    transients.sort_by_key(|&(_, _, first, _)| first);
+
+    let mut assigned = HashMap::new();
+    for (resource, desc, first, last) in transients {
+        let free = (0..descs.len()).find(|&slot| descs[slot] == desc && busy_until[slot].map_or(true, |end| end < first));
+        let slot = match free {
+            Some(slot) => slot,
+            None => {
+                descs.push(desc);
+                busy_until.push(None);
+                descs.len() - 1
+            }
+        };
+        busy_until[slot] = Some(last);
+        assigned.insert(resource, slot);
+    }
+    (assigned, descs)
+}
+
+fn create_texture(device: &wgpu::Device, desc: &TextureDesc) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("transient_texture"),
+        size: desc.size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: desc.format,
+        usage: desc.usage,
+        view_formats: &[],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(name: &str, reads: &[usize], writes: &[usize]) -> PassNode<'static> {
+        PassNode {
+            name: name.to_string(),
+            reads: reads.iter().map(|&id| ResourceId(id)).collect(),
+            writes: writes.iter().map(|&id| ResourceId(id)).collect(),
+            run: Box::new(|_, _| {}),
+        }
+    }
+
+    fn names(passes: &[PassNode<'_>], order: &[usize]) -> Vec<String> {
+        order.iter().map(|&i| passes[i].name.clone()).collect()
+    }
+
+    fn desc(width: u32) -> TextureDesc {
+        TextureDesc {
+            size: wgpu::Extent3d {
+                width,
+                height: width,
+                depth_or_array_layers: 1,
+            },
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        }
+    }
+
+    #[test]
+    fn unread_passes_are_culled() {
+        // 0 is the imported output, 1 and 2 transients.
+        let passes = [
+            pass("unused", &[], &[2]),
+            pass("producer", &[], &[1]),
+            pass("present", &[1], &[0]),
+            pass("reads_unused", &[2], &[]),
+        ];
+        let order = order(&passes, &[true, false, false]);
+        assert_eq!(names(&passes, &order), ["producer", "present"]);
+    }
+
+    #[test]
+    fn readers_run_after_late_writers() {
+        // 0 output, 1 hdr, 2 tone mapped. The overlay is added after tone mapping but draws into
+        // hdr, so tone mapping has to wait for it.
+        let passes = [
+            pass("opaque", &[], &[1]),
+            pass("bloom", &[1], &[1]),
+            pass("tone_mapping", &[1], &[2]),
+            pass("blit", &[2], &[0]),
+            pass("overlay", &[1], &[1]),
+        ];
+        let order = order(&passes, &[true, true, false]);
+        assert_eq!(names(&passes, &order), ["opaque", "bloom", "overlay", "tone_mapping", "blit"]);
+    }
+
+    #[test]
+    fn independent_passes_keep_insertion_order() {
+        let passes = [
+            pass("shadow_b", &[], &[2]),
+            pass("shadow_a", &[], &[1]),
+            pass("opaque", &[1, 2], &[0]),
+        ];
+        let order = order(&passes, &[true, true, true]);
+        assert_eq!(names(&passes, &order), ["shadow_b", "shadow_a", "opaque"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle between a, b")]
+    fn cycles_panic() {
+        let passes = [
+            pass("a", &[2], &[0, 1]),
+            pass("b", &[1], &[2]),
+        ];
+        order(&passes, &[true, false, false]);
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_a_slot() {
+        let (assigned, descs) = assign_slots(&[], &[(0, desc(64), 0, 1), (1, desc(64), 1, 2), (2, desc(64), 2, 3)]);
+        // 1 is still in use when 2 starts, but 0 is done with by then.
+        assert_eq!(assigned[&0], assigned[&2]);
+        assert_ne!(assigned[&0], assigned[&1]);
+        assert_eq!(descs.len(), 2);
+    }
+
+    #[test]
+    fn different_descriptions_never_share() {
+        let (assigned, descs) = assign_slots(&[], &[(0, desc(64), 0, 0), (1, desc(32), 1, 1)]);
+        assert_ne!(assigned[&0], assigned[&1]);
+        assert_eq!(descs, [desc(64), desc(32)]);
+    }
+
+    #[test]
+    fn existing_slots_are_reused() {
+        let (assigned, descs) = assign_slots(&[desc(32), desc(64)], &[(0, desc(64), 0, 0), (1, desc(64), 0, 1)]);
+        assert_eq!(assigned[&0], 1);
+        assert_eq!(assigned[&1], 2);
+        assert_eq!(descs, [desc(32), desc(64), desc(64)]);
+    }
+}
@@ -3,11 +3,16 @@ pub mod instance;
 pub mod resources;
 pub mod context;
 pub mod renderer;
+pub mod graph;
+pub mod layouts;
+pub mod nodes;
 pub mod input;
 pub mod window;
 pub mod gpu;
 pub mod registry;
 pub mod scene;
+pub mod tonemap;
+pub mod post;
 
 pub mod components;
 pub mod asset;
\ No newline at end of file
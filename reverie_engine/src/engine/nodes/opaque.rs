@@ -0,0 +1,173 @@
+use specs::prelude::*;
+
+use crate::engine::{
+    asset::model::{DrawModel, Vertex, ModelVertex},
+    camera::Camera,
+    components::{mesh::Mesh, material::MaterialComponent, transform::TransformComponent},
+    context::create_render_pipeline,
+    graph::{RenderGraph, RenderNode},
+    layouts::Layouts,
+    scene::Scene,
+    tonemap::HDR_FORMAT,
+};
+
+// The skybox and the lit meshes, drawn into "hdr" with "depth" after the shadow maps are done.
+pub struct OpaqueNode {
+    pipeline: wgpu::RenderPipeline,
+    skybox_pipeline: wgpu::RenderPipeline,
+}
+
+impl OpaqueNode {
+    pub fn new(device: &wgpu::Device, layouts: &Layouts) -> Self {
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &layouts.transform,
+                &layouts.camera,
+                &layouts.material,
+                &layouts.light,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let render_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(include_str!("../../../shaders/pbr.wgsl").into()),
+            };
+            create_render_pipeline(
+                &device,
+                &render_pipeline_layout,
+                HDR_FORMAT,
+                Some(wgpu::TextureFormat::Depth32Float),
+                &[ModelVertex::desc()],
+                shader,
+            )
+        };
+
+        let skybox_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &layouts.skybox,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let skybox_pipeline = {
+            let shader_desc = wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(include_str!("../../../shaders/skybox.wgsl").into()),
+            };
+            let shader = device.create_shader_module(shader_desc);
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("skybox pass"),
+                layout: Some(&skybox_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &[
+                            wgpu::VertexAttribute {
+                                offset: 0,
+                                shader_location: 0,
+                                format: wgpu::VertexFormat::Float32x3,
+                            }
+                        ]
+                    }],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false
+                },
+                depth_stencil: Some(wgpu::TextureFormat::Depth32Float).map(|format| wgpu::DepthStencilState {
+                    format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState  {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None
+            })
+        };
+
+        Self {
+            pipeline: render_pipeline,
+            skybox_pipeline,
+        }
+    }
+}
+
+impl RenderNode for OpaqueNode {
+    fn add_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, scene: &'a Scene, camera: &'a Camera) {
+        let hdr = graph.resource("hdr").unwrap();
+        let depth = graph.resource("depth").unwrap();
+        let mut shadow_maps = graph.resources("shadow_map").to_vec();
+        shadow_maps.extend(graph.resource("point_shadow_atlas"));
+
+        graph.add_pass("opaque", &shadow_maps, &[hdr, depth], move |resources, encoder| {
+            let meshes = scene.world.read_storage::<Mesh>();
+            let transforms = scene.world.read_storage::<TransformComponent>();
+            let materials_c = scene.world.read_storage::<MaterialComponent>();
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("render_pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(hdr),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(camera.clear_color),
+                            store: true,
+                        }
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: resources.view(depth),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_pipeline(&self.skybox_pipeline);
+            render_pass.set_bind_group(0, &scene.skybox.bind_group, &[]);
+
+            render_pass.set_vertex_buffer(0, scene.skybox.vertex_buffer.slice(..));
+            render_pass.draw(0..36, 0..1);
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(1, &camera.bind_group, &[]);
+            render_pass.set_bind_group(3, &scene.light_manager.bind_group, &[]);
+
+            for (mesh, transform, material) in (&meshes, &transforms, &materials_c).join()  {
+                render_pass.set_bind_group(0, &transform.bind_group, &[]);
+                for m in (*mesh.mesh).iter() {
+                    render_pass.draw_mesh(&m, &material.material);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,151 @@
+use specs::prelude::*;
+
+use crate::engine::{
+    asset::model::{Vertex, ModelVertex},
+    camera::Camera,
+    components::{mesh::Mesh, material::MaterialComponent, transform::TransformComponent},
+    graph::{RenderGraph, RenderNode},
+    layouts::Layouts,
+    scene::Scene,
+};
+
+// Renders the scene's depth into every shadow map the light manager keeps, the point light atlas
+// and a map per directional cascade and shadowed spot light.
+pub struct ShadowNode {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowNode {
+    pub fn new(device: &wgpu::Device, layouts: &Layouts) -> Self {
+        let light_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &layouts.shadow,
+                &layouts.transform,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let light_pipeline = {
+            let shader_desc = wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(include_str!("../../../shaders/shadow.wgsl").into()),
+            };
+            let shader = device.create_shader_module(shader_desc);
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("light pass"),
+                layout: Some(&light_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[ModelVertex::desc()],
+                },
+                fragment: None,
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Front),
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false
+                },
+                depth_stencil: Some(wgpu::TextureFormat::Depth32Float).map(|format| wgpu::DepthStencilState {
+                    format,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState  {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None
+            })
+        };
+
+        Self {
+            pipeline: light_pipeline,
+        }
+    }
+}
+
+impl RenderNode for ShadowNode {
+    fn add_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, scene: &'a Scene, _camera: &'a Camera) {
+        let atlas = graph.import("point_shadow_atlas", &scene.light_manager.point_atlas.view);
+        graph.add_pass("point_shadows", &[], &[atlas], move |resources, encoder| {
+            let meshes = scene.world.read_storage::<Mesh>();
+            let transforms = scene.world.read_storage::<TransformComponent>();
+            let materials_c = scene.world.read_storage::<MaterialComponent>();
+
+            let mut light_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("point_shadow_pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: resources.view(atlas),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            light_pass.set_pipeline(&self.pipeline);
+
+            for shadow in &scene.light_manager.point_shadows {
+                let tiles = shadow.tiles.iter().flatten();
+                for (tile, bind_group) in tiles.zip(shadow.bind_groups.iter()) {
+                    light_pass.set_viewport(tile.x as f32, tile.y as f32, tile.size as f32, tile.size as f32, 0.0, 1.0);
+                    light_pass.set_scissor_rect(tile.x, tile.y, tile.size, tile.size);
+                    light_pass.set_bind_group(0, bind_group, &[]);
+
+                    for (transform, mesh, _) in (&transforms, &meshes, &materials_c).join() {
+                        light_pass.set_bind_group(1, &transform.bind_group, &[]);
+                        for m in (*mesh.mesh).iter() {
+                            light_pass.set_vertex_buffer(0, m.vertex_buffer.slice(..));
+                            light_pass.set_index_buffer(m.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                            light_pass.draw_indexed(0..m.element_count, 0, 0..1);
+                        }
+                    }
+                }
+            }
+        });
+
+        let cascades = scene.light_manager.directional_shadows.iter().flat_map(|shadow| shadow.views.iter().zip(shadow.bind_groups.iter()));
+        let spots = scene.light_manager.spot_shadows.iter().map(|shadow| (&shadow.view, &shadow.bind_group));
+        for (view, bind_group) in cascades.chain(spots) {
+            let shadow_map = graph.import("shadow_map", view);
+            graph.add_pass("shadows", &[], &[shadow_map], move |resources, encoder| {
+                let meshes = scene.world.read_storage::<Mesh>();
+                let transforms = scene.world.read_storage::<TransformComponent>();
+                let materials_c = scene.world.read_storage::<MaterialComponent>();
+
+                let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("shadow_pass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view(shadow_map),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                shadow_pass.set_pipeline(&self.pipeline);
+                shadow_pass.set_bind_group(0, bind_group, &[]);
+
+                for (transform, mesh, _) in (&transforms, &meshes, &materials_c).join() {
+                    shadow_pass.set_bind_group(1, &transform.bind_group, &[]);
+                    for m in (*mesh.mesh).iter() {
+                        shadow_pass.set_vertex_buffer(0, m.vertex_buffer.slice(..));
+                        shadow_pass.set_index_buffer(m.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                        shadow_pass.draw_indexed(0..m.element_count, 0, 0..1);
+                    }
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use serde::{Serialize, Deserialize};
+
+use crate::engine::{graph::{GraphResources, RenderGraph, ResourceId, TextureDesc}, tonemap::HDR_FORMAT};
+
+use super::{create_pipeline, create_sampler, source_layout_entries};
+use crate::util::cast_slice;
+
+const MAX_MIPS: u32 = 6;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    // How much of the blurred image is added back onto the scene.
+    pub intensity: f32,
+    pub threshold: f32,
+    pub knee: f32,
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.05,
+            threshold: 1.0,
+            knee: 0.5,
+            radius: 0.005,
+        }
+    }
+}
+
+impl BloomSettings {
+    pub fn inspect(&mut self, ui: &imgui::Ui) -> bool {
+        let mut changed = false;
+        changed |= imgui::Drag::new("Intensity").speed(0.005).range(0.0, 1.0).build(ui, &mut self.intensity);
+        changed |= imgui::Drag::new("Threshold").speed(0.05).range(0.0, 100.0).build(ui, &mut self.threshold);
+        changed |= imgui::Drag::new("Knee").speed(0.01).range(0.0, 10.0).build(ui, &mut self.knee);
+        changed |= imgui::Drag::new("Radius").speed(0.0005).range(0.0, 0.05).build(ui, &mut self.radius);
+        changed
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    _padding: f32,
+}
+
+// Downsamples the bright parts of the HDR image through a half resolution mip chain, then
+// upsamples back up it, adding each level onto the next, and blends the result onto the image.
+// The mips are transients, free for later passes to reuse once bloom is done.
+pub struct Bloom {
+    layout: wgpu::BindGroupLayout,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    hdr_bind_group: wgpu::BindGroup,
+    size: wgpu::Extent3d,
+    intensity: f32,
+}
+
+impl Bloom {
+    pub fn new(device: &wgpu::Device, hdr: &wgpu::Texture) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &source_layout_entries(),
+            label: Some("bloom_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("bloom_shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../../shaders/bloom.wgsl").into()),
+        });
+
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let constant = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Constant,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        let prefilter_pipeline = create_pipeline(device, "bloom_prefilter", &layout, &shader, "fs_prefilter", HDR_FORMAT, wgpu::BlendState::REPLACE);
+        let downsample_pipeline = create_pipeline(device, "bloom_downsample", &layout, &shader, "fs_downsample", HDR_FORMAT, wgpu::BlendState::REPLACE);
+        let upsample_pipeline = create_pipeline(device, "bloom_upsample", &layout, &shader, "fs_upsample", HDR_FORMAT, wgpu::BlendState { color: additive, alpha: additive });
+        let composite_pipeline = create_pipeline(device, "bloom_composite", &layout, &shader, "fs_upsample", HDR_FORMAT, wgpu::BlendState { color: constant, alpha: additive });
+
+        let sampler = create_sampler(device);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("bloom_buffer"),
+            size: std::mem::size_of::<BloomUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let hdr_view = hdr.create_view(&wgpu::TextureViewDescriptor::default());
+        let hdr_bind_group = create_bind_group(device, &layout, &hdr_view, &sampler, &uniform_buffer);
+
+        Self {
+            layout,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+            sampler,
+            uniform_buffer,
+            hdr_bind_group,
+            size: hdr.size(),
+            intensity: 0.0,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &wgpu::Texture) {
+        let hdr_view = hdr.create_view(&wgpu::TextureViewDescriptor::default());
+        self.hdr_bind_group = create_bind_group(device, &self.layout, &hdr_view, &self.sampler, &self.uniform_buffer);
+        self.size = hdr.size();
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &BloomSettings) {
+        self.intensity = settings.intensity;
+        let uniform = BloomUniform {
+            threshold: settings.threshold,
+            knee: settings.knee,
+            radius: settings.radius,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniform]));
+    }
+
+    pub fn add_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, hdr: ResourceId) {
+        let mips = mip_sizes(self.size).into_iter().map(|size| graph.create("bloom_mip", TextureDesc {
+            size,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        })).collect::<Vec<_>>();
+
+        let mut used = mips.clone();
+        used.push(hdr);
+        graph.add_pass("bloom", &used, &used, move |resources, encoder| self.draw(resources, hdr, &mips, encoder));
+    }
+
+    fn draw(&self, resources: &GraphResources<'_>, hdr: ResourceId, mips: &[ResourceId], encoder: &mut wgpu::CommandEncoder) {
+        let mip_views = mips.iter().map(|&mip| resources.view(mip)).collect::<Vec<_>>();
+        let mip_bind_groups = mip_views.iter().map(|view| create_bind_group(resources.device(), &self.layout, view, &self.sampler, &self.uniform_buffer)).collect::<Vec<_>>();
+        let last = mip_views.len() - 1;
+
+        draw_pass(encoder, &self.prefilter_pipeline, &self.hdr_bind_group, mip_views[0], true, None);
+        for i in 0..last {
+            draw_pass(encoder, &self.downsample_pipeline, &mip_bind_groups[i], mip_views[i + 1], true, None);
+        }
+        for i in (0..last).rev() {
+            draw_pass(encoder, &self.upsample_pipeline, &mip_bind_groups[i + 1], mip_views[i], false, None);
+        }
+        draw_pass(encoder, &self.composite_pipeline, &mip_bind_groups[0], resources.view(hdr), false, Some(self.intensity));
+    }
+}
+
+fn draw_pass(encoder: &mut wgpu::CommandEncoder, pipeline: &wgpu::RenderPipeline, bind_group: &wgpu::BindGroup, view: &wgpu::TextureView, clear: bool, blend_constant: Option<f32>) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("bloom_pass"),
+        color_attachments: &[
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(wgpu::Color::BLACK) } else { wgpu::LoadOp::Load },
+                    store: true,
+                }
+            }),
+        ],
+        depth_stencil_attachment: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    if let Some(intensity) = blend_constant {
+        let intensity = intensity as f64;
+        pass.set_blend_constant(wgpu::Color { r: intensity, g: intensity, b: intensity, a: intensity });
+    }
+    pass.draw(0..3, 0..1);
+}
+
+// Half the size of the HDR image, halving again down to a few texels or `MAX_MIPS` levels.
+fn mip_sizes(hdr: wgpu::Extent3d) -> Vec<wgpu::Extent3d> {
+    let width = (hdr.width / 2).max(1);
+    let height = (hdr.height / 2).max(1);
+    let mut mips = 1;
+    while mips < MAX_MIPS && (width >> mips) >= 2 && (height >> mips) >= 2 {
+        mips += 1;
+    }
+
+    (0..mips).map(|mip| wgpu::Extent3d {
+        width: (width >> mip).max(1),
+        height: (height >> mip).max(1),
+        depth_or_array_layers: 1,
+    }).collect()
+}
+
+fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, view: &wgpu::TextureView, sampler: &wgpu::Sampler, uniform_buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("bloom_bind_group"),
+    })
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+
+use crate::engine::{asset::lut::Lut, registry::{AssetType, Registry}, tonemap::HDR_FORMAT};
+
+use super::EffectPass;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorGradingSettings {
+    pub enabled: bool,
+    // Texture asset holding the LUT strip.
+    pub lut: Option<usize>,
+    pub contribution: f32,
+    #[serde(skip)]
+    pub texture: Option<Arc<Lut>>,
+}
+
+impl Default for ColorGradingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            lut: None,
+            contribution: 1.0,
+            texture: None,
+        }
+    }
+}
+
+impl ColorGradingSettings {
+    pub fn resolve(&mut self, registry: &mut Registry) {
+        self.texture = self.lut.and_then(|id| registry.get_lut(id));
+    }
+
+    pub fn inspect(&mut self, ui: &imgui::Ui, registry: &mut Registry) -> bool {
+        let mut changed = false;
+
+        let lut_name = match self.lut {
+            Some(id) => match registry.metadata.get(&id) {
+                Some(asset) => asset.file_path.file_name().unwrap().to_str().unwrap().to_string(),
+                None => String::from("Missing"),
+            },
+            None => String::from("None"),
+        };
+        ui.button(format!("{}##lut", lut_name));
+        match ui.drag_drop_target() {
+            Some(target) => {
+                match target.accept_payload::<Option<usize>, _>(AssetType::Texture.to_string(), imgui::DragDropFlags::empty()) {
+                    Some(Ok(payload_data)) => {
+                        self.lut = payload_data.data;
+                        self.resolve(registry);
+                        changed = true;
+                    },
+                    Some(Err(e)) => {
+                        println!("{}", e);
+                    },
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+        ui.same_line();
+        ui.text("LUT");
+
+        changed |= imgui::Drag::new("Contribution").speed(0.01).range(0.0, 1.0).build(ui, &mut self.contribution);
+        changed
+    }
+}
+
+// Looks the tone mapped image up in a 3D LUT. Skipped until the settings have one loaded.
+pub struct ColorGrading {
+    pass: EffectPass,
+    lut: Option<Arc<Lut>>,
+}
+
+impl ColorGrading {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let lut_entry = wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D3,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        Self {
+            pass: EffectPass::new(device, "color_grading", include_str!("../../../shaders/color_grading.wgsl"), HDR_FORMAT, 16, &[lut_entry]),
+            lut: None,
+        }
+    }
+
+    // Returns whether the effect can run this frame.
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &ColorGradingSettings) -> bool {
+        self.lut = settings.texture.clone();
+        if self.lut.is_none() {
+            return false;
+        }
+        self.pass.write(queue, &[settings.contribution, 0.0, 0.0, 0.0]);
+        true
+    }
+
+    pub fn draw(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, source: &wgpu::TextureView, output: &wgpu::TextureView) {
+        if let Some(lut) = &self.lut {
+            self.pass.draw(device, encoder, source, output, &[lut_binding(lut)]);
+        }
+    }
+}
+
+fn lut_binding(lut: &Lut) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding: 3,
+        resource: wgpu::BindingResource::TextureView(&lut.view),
+    }
+}
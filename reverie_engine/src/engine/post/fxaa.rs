@@ -0,0 +1,51 @@
+use serde::{Serialize, Deserialize};
+
+use crate::engine::tonemap::HDR_FORMAT;
+
+use super::EffectPass;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FxaaSettings {
+    pub enabled: bool,
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0,
+        }
+    }
+}
+
+impl FxaaSettings {
+    pub fn inspect(&mut self, ui: &imgui::Ui) -> bool {
+        let mut changed = false;
+        changed |= imgui::Drag::new("Span Max").speed(0.1).range(1.0, 16.0).build(ui, &mut self.span_max);
+        changed |= imgui::Drag::new("Reduce Mul").speed(0.005).range(0.0, 1.0).build(ui, &mut self.reduce_mul);
+        changed |= imgui::Drag::new("Reduce Min").speed(0.001).range(0.0, 0.1).build(ui, &mut self.reduce_min);
+        changed
+    }
+}
+
+pub struct Fxaa {
+    pub(crate) pass: EffectPass,
+}
+
+impl Fxaa {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            pass: EffectPass::new(device, "fxaa", include_str!("../../../shaders/fxaa.wgsl"), HDR_FORMAT, 16, &[]),
+        }
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &FxaaSettings) {
+        self.pass.write(queue, &[settings.span_max, settings.reduce_mul, settings.reduce_min, 0.0]);
+    }
+}
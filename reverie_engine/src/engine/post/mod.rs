@@ -0,0 +1,429 @@
+pub mod bloom;
+pub mod color_grading;
+pub mod fxaa;
+pub mod vignette;
+
+use serde::{Serialize, Deserialize};
+
+use crate::util::cast_slice;
+
+use self::{bloom::{Bloom, BloomSettings}, color_grading::{ColorGrading, ColorGradingSettings}, fxaa::{Fxaa, FxaaSettings}, vignette::{Vignette, VignetteSettings}};
+use super::{camera::Camera, graph::{RenderGraph, RenderNode, TextureDesc}, registry::Registry, scene::Scene, tonemap::{ToneMappingPass, HDR_FORMAT}};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EffectKind {
+    Bloom,
+    Fxaa,
+    Vignette,
+    ColorGrading,
+}
+
+impl EffectKind {
+    const ALL: [EffectKind; 4] = [EffectKind::Bloom, EffectKind::Fxaa, EffectKind::Vignette, EffectKind::ColorGrading];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            EffectKind::Bloom => "Bloom",
+            EffectKind::Fxaa => "FXAA",
+            EffectKind::Vignette => "Vignette",
+            EffectKind::ColorGrading => "Color Grading",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Effect {
+    Bloom(BloomSettings),
+    Fxaa(FxaaSettings),
+    Vignette(VignetteSettings),
+    ColorGrading(ColorGradingSettings),
+}
+
+impl Effect {
+    fn new(kind: EffectKind) -> Self {
+        match kind {
+            EffectKind::Bloom => Effect::Bloom(BloomSettings::default()),
+            EffectKind::Fxaa => Effect::Fxaa(FxaaSettings::default()),
+            EffectKind::Vignette => Effect::Vignette(VignetteSettings::default()),
+            EffectKind::ColorGrading => Effect::ColorGrading(ColorGradingSettings::default()),
+        }
+    }
+
+    pub fn kind(&self) -> EffectKind {
+        match self {
+            Effect::Bloom(_) => EffectKind::Bloom,
+            Effect::Fxaa(_) => EffectKind::Fxaa,
+            Effect::Vignette(_) => EffectKind::Vignette,
+            Effect::ColorGrading(_) => EffectKind::ColorGrading,
+        }
+    }
+
+    fn enabled(&mut self) -> &mut bool {
+        match self {
+            Effect::Bloom(settings) => &mut settings.enabled,
+            Effect::Fxaa(settings) => &mut settings.enabled,
+            Effect::Vignette(settings) => &mut settings.enabled,
+            Effect::ColorGrading(settings) => &mut settings.enabled,
+        }
+    }
+
+    fn inspect(&mut self, ui: &imgui::Ui, registry: &mut Registry) -> bool {
+        match self {
+            Effect::Bloom(settings) => settings.inspect(ui),
+            Effect::Fxaa(settings) => settings.inspect(ui),
+            Effect::Vignette(settings) => settings.inspect(ui),
+            Effect::ColorGrading(settings) => settings.inspect(ui, registry),
+        }
+    }
+}
+
+// The effects a camera runs after the main pass, in order. Bloom works on the HDR image so it
+// always runs before tone mapping, the others run after it in the order listed.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessing {
+    pub effects: Vec<Effect>,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            effects: vec![
+                Effect::Bloom(BloomSettings::default()),
+                Effect::Fxaa(FxaaSettings::default()),
+            ],
+        }
+    }
+}
+
+impl PostProcessing {
+    // Loads the assets the effects refer to by id.
+    pub fn resolve(&mut self, registry: &mut Registry) {
+        for effect in &mut self.effects {
+            if let Effect::ColorGrading(settings) = effect {
+                settings.resolve(registry);
+            }
+        }
+    }
+
+    pub fn inspect(&mut self, ui: &imgui::Ui, registry: &mut Registry) -> bool {
+        let mut changed = false;
+        let mut moved = None;
+        let mut removed = None;
+        let count = self.effects.len();
+
+        for (i, effect) in self.effects.iter_mut().enumerate() {
+            let _id = ui.push_id_usize(i);
+            changed |= ui.checkbox("##enabled", effect.enabled());
+            ui.same_line();
+            let open = ui.tree_node(effect.kind().label());
+            ui.same_line();
+            if ui.arrow_button("up", imgui::Direction::Up) && i > 0 {
+                moved = Some((i, i - 1));
+            }
+            ui.same_line();
+            if ui.arrow_button("down", imgui::Direction::Down) && i + 1 < count {
+                moved = Some((i, i + 1));
+            }
+            ui.same_line();
+            if ui.small_button("Remove") {
+                removed = Some(i);
+            }
+            if let Some(_node) = open {
+                changed |= effect.inspect(ui, registry);
+            }
+        }
+
+        if let Some((from, to)) = moved {
+            self.effects.swap(from, to);
+            changed = true;
+        }
+        if let Some(i) = removed {
+            self.effects.remove(i);
+            changed = true;
+        }
+
+        if ui.button("Add Effect") {
+            ui.open_popup("add_effect");
+        }
+        ui.popup("add_effect", || {
+            for kind in EffectKind::ALL {
+                if !self.effects.iter().any(|effect| effect.kind() == kind) && ui.selectable(kind.label()) {
+                    let mut effect = Effect::new(kind);
+                    if let Effect::ColorGrading(settings) = &mut effect {
+                        settings.resolve(registry);
+                    }
+                    self.effects.push(effect);
+                    changed = true;
+                }
+            }
+        });
+
+        changed
+    }
+}
+
+// Runs a camera's effects and tone mapping. After tone mapping each effect writes a new transient
+// target, the pool lets every other one share a texture, and the last is copied to "output".
+pub struct PostStack {
+    pub tone_mapping: ToneMappingPass,
+    size: wgpu::Extent3d,
+    bloom: Bloom,
+    fxaa: Fxaa,
+    vignette: Vignette,
+    color_grading: ColorGrading,
+    blit: EffectPass,
+    encode_srgb: bool,
+    bloom_enabled: bool,
+    // Effects after tone mapping, bloom is never among them.
+    order: Vec<EffectKind>,
+}
+
+impl PostStack {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, hdr: &wgpu::Texture) -> Self {
+        let size = hdr.size();
+
+        Self {
+            tone_mapping: ToneMappingPass::new(device, hdr),
+            bloom: Bloom::new(device, hdr),
+            fxaa: Fxaa::new(device),
+            vignette: Vignette::new(device, size.width as f32 / size.height as f32),
+            color_grading: ColorGrading::new(device),
+            blit: EffectPass::new(device, "blit", include_str!("../../../shaders/blit.wgsl"), format, 16, &[]),
+            size,
+            encode_srgb: !format.describe().srgb,
+            bloom_enabled: false,
+            order: Vec::new(),
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &wgpu::Texture) {
+        self.size = hdr.size();
+        self.tone_mapping.resize(device, hdr);
+        self.bloom.resize(device, hdr);
+        self.vignette.resize(self.size.width as f32 / self.size.height as f32);
+    }
+
+    // `dt` is the frame time automatic exposure adapts over, None meters the frame on its own.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, dt: Option<f32>) {
+        self.tone_mapping.update(queue, &camera.tone_mapping, dt);
+        self.bloom_enabled = false;
+        self.order.clear();
+        for effect in &camera.post_processing.effects {
+            match effect {
+                Effect::Bloom(bloom) if bloom.enabled => {
+                    self.bloom.update(queue, bloom);
+                    self.bloom_enabled = true;
+                }
+                Effect::Fxaa(fxaa) if fxaa.enabled => {
+                    self.fxaa.update(queue, fxaa);
+                    self.order.push(effect.kind());
+                }
+                Effect::Vignette(vignette) if vignette.enabled => {
+                    self.vignette.update(queue, vignette);
+                    self.order.push(effect.kind());
+                }
+                Effect::ColorGrading(grading) if grading.enabled => {
+                    if self.color_grading.update(queue, grading) {
+                        self.order.push(effect.kind());
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.blit.write(queue, &[self.encode_srgb as u32, 0, 0, 0]);
+    }
+}
+
+impl RenderNode for PostStack {
+    fn add_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, _scene: &'a Scene, _camera: &'a Camera) {
+        let hdr = graph.resource("hdr").unwrap();
+        let output = graph.resource("output").unwrap();
+        let target_desc = TextureDesc {
+            size: self.size,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        };
+
+        // Bloom works on the HDR image in place, before tone mapping.
+        if self.bloom_enabled {
+            self.bloom.add_pass(graph, hdr);
+        }
+        let mut source = graph.create("post_target", target_desc);
+        graph.add_pass("tone_mapping", &[hdr], &[source], move |resources, encoder| {
+            self.tone_mapping.draw(resources.view(source), encoder);
+        });
+
+        for &effect in &self.order {
+            let target = graph.create("post_target", target_desc);
+            graph.add_pass(effect.label(), &[source], &[target], move |resources, encoder| {
+                let (source, target) = (resources.view(source), resources.view(target));
+                match effect {
+                    EffectKind::Fxaa => self.fxaa.pass.draw(resources.device(), encoder, source, target, &[]),
+                    EffectKind::Vignette => self.vignette.pass.draw(resources.device(), encoder, source, target, &[]),
+                    EffectKind::ColorGrading => self.color_grading.draw(resources.device(), encoder, source, target),
+                    EffectKind::Bloom => unreachable!("bloom runs on the HDR image before tone mapping"),
+                }
+            });
+            source = target;
+        }
+        graph.add_pass("blit", &[source], &[output], move |resources, encoder| {
+            self.blit.draw(resources.device(), encoder, resources.view(source), resources.view(output), &[]);
+        });
+    }
+}
+
+pub(crate) fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("post_sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+// Source texture, its sampler and the effect's uniform, shared by every full screen effect.
+pub(crate) fn source_layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+    [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ]
+}
+
+pub(crate) fn create_pipeline(device: &wgpu::Device, label: &str, layout: &wgpu::BindGroupLayout, shader: &wgpu::ShaderModule, entry_point: &str, format: wgpu::TextureFormat, blend: wgpu::BlendState) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        })),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// A full screen effect from one transient target into the next. The targets come from the graph's
+// pool, so the bind group is made when the pass runs.
+pub(crate) struct EffectPass {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl EffectPass {
+    pub fn new(device: &wgpu::Device, label: &str, source: &str, format: wgpu::TextureFormat, uniform_size: u64, extra_entries: &[wgpu::BindGroupLayoutEntry]) -> Self {
+        let mut entries = source_layout_entries().to_vec();
+        entries.extend_from_slice(extra_entries);
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some(label),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        Self {
+            pipeline: create_pipeline(device, label, &layout, &shader, "fs_main", format, wgpu::BlendState::REPLACE),
+            layout,
+            sampler: create_sampler(device),
+            uniform_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: uniform_size,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        }
+    }
+
+    pub fn write<T>(&self, queue: &wgpu::Queue, uniform: &T) {
+        queue.write_buffer(&self.uniform_buffer, 0, cast_slice(std::slice::from_ref(uniform)));
+    }
+
+    pub fn draw(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, source: &wgpu::TextureView, output: &wgpu::TextureView, extra_entries: &[wgpu::BindGroupEntry]) {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(source),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+        ];
+        entries.extend(extra_entries.iter().cloned());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &entries,
+            label: Some("effect_bind_group"),
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("effect_pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    }
+                }),
+            ],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
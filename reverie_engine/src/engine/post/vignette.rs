@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+
+use crate::engine::tonemap::HDR_FORMAT;
+
+use super::EffectPass;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VignetteSettings {
+    pub enabled: bool,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub radius: f32,
+    pub smoothness: f32,
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            color: [0.0, 0.0, 0.0],
+            intensity: 0.5,
+            radius: 1.4,
+            smoothness: 0.8,
+        }
+    }
+}
+
+impl VignetteSettings {
+    pub fn inspect(&mut self, ui: &imgui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui.color_edit3("Color", &mut self.color);
+        changed |= imgui::Drag::new("Intensity").speed(0.01).range(0.0, 1.0).build(ui, &mut self.intensity);
+        changed |= imgui::Drag::new("Radius").speed(0.01).range(0.0, 3.0).build(ui, &mut self.radius);
+        changed |= imgui::Drag::new("Smoothness").speed(0.01).range(0.0, 3.0).build(ui, &mut self.smoothness);
+        changed
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct VignetteUniform {
+    color: [f32; 3],
+    intensity: f32,
+    radius: f32,
+    smoothness: f32,
+    aspect: f32,
+    _padding: f32,
+}
+
+pub struct Vignette {
+    pub(crate) pass: EffectPass,
+    aspect: f32,
+}
+
+impl Vignette {
+    pub fn new(device: &wgpu::Device, aspect: f32) -> Self {
+        Self {
+            pass: EffectPass::new(device, "vignette", include_str!("../../../shaders/vignette.wgsl"), HDR_FORMAT, std::mem::size_of::<VignetteUniform>() as u64, &[]),
+            aspect,
+        }
+    }
+
+    pub fn resize(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &VignetteSettings) {
+        self.pass.write(queue, &VignetteUniform {
+            color: settings.color,
+            intensity: settings.intensity,
+            radius: settings.radius,
+            smoothness: settings.smoothness,
+            aspect: self.aspect,
+            _padding: 0.0,
+        });
+    }
+}
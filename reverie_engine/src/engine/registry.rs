@@ -1,4 +1,4 @@
-use std::{path::PathBuf, collections::HashMap, sync::{Arc, Mutex, mpsc::Receiver}, ffi::OsStr};
+use std::{path::{Path, PathBuf}, collections::HashMap, sync::{Arc, Mutex, mpsc::Receiver}, ffi::OsStr};
 use rand::random;
 use serde::{Serialize, Deserialize};
 use wgpu::util::DeviceExt;
@@ -6,7 +6,7 @@ use std::error::Error;
 
 use crate::util::cast_slice;
 
-use super::{asset::{texture::Texture, model::Mesh, material::Material}, gpu::Gpu, renderer::Renderer, resources};
+use super::{asset::{texture::Texture, model::Mesh, material::Material, lut::Lut}, gpu::Gpu, layouts::Layouts, resources};
 
 #[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum AssetType {
@@ -23,6 +23,8 @@ impl AssetType {
             "revmat" => AssetType::Material,
             "png" => AssetType::Texture,
             "jpg" => AssetType::Texture,
+            "hdr" => AssetType::Texture,
+            "exr" => AssetType::Texture,
             "obj" => AssetType::Mesh,
             "revscene" => AssetType::Scene,
             _ => AssetType::Unknown,
@@ -58,10 +60,14 @@ impl AssetMetadata {
 pub struct Registry {
     device: Arc<wgpu::Device>,
     queue: Arc<wgpu::Queue>,
-    imgui_renderer: Arc<Mutex<imgui_wgpu::Renderer>>,
+    imgui_renderer: Option<Arc<Mutex<imgui_wgpu::Renderer>>>,
+    // Project directory holding registry.yaml and res, the paths in registry.yaml are relative to it.
+    pub root: PathBuf,
+    pub layouts: Arc<Layouts>,
     pub textures: HashMap<usize, Arc<Texture>>,
     pub materials: HashMap<usize, Arc<Gpu<Material>>>,
     pub meshes: HashMap<usize, Arc<Vec<Mesh>>>,
+    pub luts: HashMap<usize, Arc<Lut>>,
     pub metadata: HashMap<usize, AssetMetadata>,
     pub loading: Vec<usize>,
     pub rx: Vec<Receiver<(usize, Arc<Texture>)>>
@@ -69,15 +75,39 @@ pub struct Registry {
 
 impl Registry {
     pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, imgui_renderer: Arc<Mutex<imgui_wgpu::Renderer>>) -> Self {
-        
+        let layouts = Arc::new(Layouts::new(&device));
+        let root = std::env::current_dir().unwrap();
+
+        Self {
+            device,
+            queue,
+            imgui_renderer: Some(imgui_renderer),
+            metadata: load_metadata(&root).unwrap(),
+            root,
+            layouts,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            meshes: HashMap::new(),
+            luts: HashMap::new(),
+            loading: Vec::new(),
+            rx: Vec::new(),
+        }
+    }
+
+    pub fn new_headless(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, root: PathBuf) -> Self {
+        let layouts = Arc::new(Layouts::new(&device));
+
         Self {
             device,
             queue,
-            imgui_renderer,
+            imgui_renderer: None,
+            metadata: load_metadata(&root).unwrap(),
+            root,
+            layouts,
             textures: HashMap::new(),
             materials: HashMap::new(),
             meshes: HashMap::new(),
-            metadata: load_metadata().unwrap(),
+            luts: HashMap::new(),
             loading: Vec::new(),
             rx: Vec::new(),
         }
@@ -119,7 +149,9 @@ impl Registry {
             let img = image::load_from_memory(bytes.as_slice()).unwrap();
             let imgui_img = img.clone();
             
-            create_imgui_texture(imgui_renderer, &imgui_img, file_path.to_str().unwrap(), device, queue, 64, 64, id);
+            if let Some(imgui_renderer) = imgui_renderer {
+                create_imgui_texture(imgui_renderer, &imgui_img, file_path.to_str().unwrap(), device, queue, 64, 64, id);
+            }
 
             let texture = Texture::from_image(&self.device, &self.queue, &img, Some(asset.file_path.to_str().unwrap()), normal).unwrap();
             self.textures.insert(id, Arc::new(texture));
@@ -157,7 +189,9 @@ impl Registry {
                 let img = image::load_from_memory(bytes.as_slice()).unwrap();
                 let imgui_img = img.clone();
                 
-                create_imgui_texture(imgui_renderer, &imgui_img, file_path.to_str().unwrap(), device.clone(), queue.clone(), 64, 64, id);
+                if let Some(imgui_renderer) = imgui_renderer {
+                    create_imgui_texture(imgui_renderer, &imgui_img, file_path.to_str().unwrap(), device.clone(), queue.clone(), 64, 64, id);
+                }
 
                 let texture = Texture::from_image(&device, &queue, &img, Some(asset.file_path.to_str().unwrap()), normal).unwrap();
                 tx.send((id, Arc::new(texture))).unwrap();
@@ -201,6 +235,21 @@ impl Registry {
         self.meshes.get(&id).cloned()
     }
 
+    // Color grading LUTs are textures too, but kept apart since they load into a 3D texture.
+    pub fn get_lut(&mut self, id: usize) -> Option<Arc<Lut>> {
+        if !self.luts.contains_key(&id) {
+            let asset = self.metadata.get(&id)?;
+            match Lut::load(&self.device, &self.queue, &asset.file_path) {
+                Ok(lut) => {
+                    self.luts.insert(id, Arc::new(lut));
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        self.luts.get(&id).cloned()
+    }
+
     pub fn get_id(&mut self, file_path: PathBuf) -> usize {
         match self.metadata.iter()
             .find(|(_, asset)| asset.file_path == file_path)
@@ -296,7 +345,7 @@ impl Registry {
                 });
                 
                 let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &Renderer::get_material_layout(),
+                    layout: &self.layouts.material,
                     entries: &[
                         wgpu::BindGroupEntry {
                             binding: 0,
@@ -366,9 +415,25 @@ impl Registry {
         self.get_material(id)
     }
 
+    // A file under the project's res directory, `file_name` separated by forward slashes.
+    pub fn res(&self, file_name: &str) -> PathBuf {
+        let mut path = self.root.join("res");
+        for dir in file_name.split("/") {
+            path = path.join(dir);
+        }
+
+        path
+    }
+
     fn save_metadata(&self) -> Result<(), Box<dyn Error>> {
-        let yaml = serde_yaml::to_string(&self.metadata)?;
-        std::fs::write(std::env::current_dir().unwrap().join("registry.yaml"), yaml)?;
+        let mut metadata = self.metadata.clone();
+        for asset in metadata.values_mut() {
+            if let Ok(relative) = asset.file_path.strip_prefix(&self.root) {
+                asset.file_path = relative.to_path_buf();
+            }
+        }
+        let yaml = serde_yaml::to_string(&metadata)?;
+        std::fs::write(self.root.join("registry.yaml"), yaml)?;
 
         Ok(())
     }
@@ -385,9 +450,12 @@ impl Registry {
     }
 }
 
-fn load_metadata() -> Result<HashMap<usize, AssetMetadata>, Box<dyn Error>> {
-    let yaml = std::fs::read_to_string(std::env::current_dir().unwrap().join("registry.yaml"))?;
-    let metadata: HashMap<usize, AssetMetadata> = serde_yaml::from_str(&yaml)?;
+fn load_metadata(root: &Path) -> Result<HashMap<usize, AssetMetadata>, Box<dyn Error>> {
+    let yaml = std::fs::read_to_string(root.join("registry.yaml"))?;
+    let mut metadata: HashMap<usize, AssetMetadata> = serde_yaml::from_str(&yaml)?;
+    for asset in metadata.values_mut() {
+        asset.file_path = root.join(&asset.file_path);
+    }
     Ok(metadata)
 }
 
@@ -1,580 +1,183 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use once_cell::sync::Lazy;
-use specs::prelude::*;
-use wgpu::BindGroupLayout;
+use anyhow::Result;
 
 use super::{
-    components::{
-        mesh::Mesh, 
-        material::MaterialComponent, transform::TransformComponent,
-    },
-    context::create_render_pipeline, 
     camera::Camera,
-    asset::model::{DrawModel, Vertex, ModelVertex}, scene::Scene,
+    graph::{RenderGraph, RenderNode, TextureDesc, TexturePool},
+    layouts::Layouts,
+    nodes::{opaque::OpaqueNode, shadow::ShadowNode},
+    post::PostStack,
+    scene::Scene,
+    tonemap::create_hdr_texture,
 };
 
-use super::asset::texture::Texture;
+use crate::util::cast_slice;
 
 pub struct Renderer {
-    pub clear_color: wgpu::Color,
-    pub texture_view: wgpu::TextureView,
-    pub depth_texture: Texture,
-    pub camera_bind_group_layout: wgpu::BindGroupLayout,
-    pub render_pipeline: wgpu::RenderPipeline,
-    pub light_pipeline: wgpu::RenderPipeline,
-    pub skybox_pipeline: wgpu::RenderPipeline,
+    pub format: wgpu::TextureFormat,
+    device: Arc<wgpu::Device>,
+    pub hdr_texture: wgpu::Texture,
+    hdr_view: wgpu::TextureView,
+    pool: TexturePool,
+    shadows: ShadowNode,
+    opaque: OpaqueNode,
+    pub post: PostStack,
+    // Extra passes, picking or debug views say, added to the graph after the built in ones. The
+    // graph orders passes by what they read and write, so these can still draw into "hdr" before
+    // tone mapping reads it.
+    pub nodes: Vec<Box<dyn RenderNode>>,
 }
 
-static MATERIAL_LAYOUT: Lazy<Mutex<Option<Arc<wgpu::BindGroupLayout>>>> = Lazy::new(|| Mutex::new(None));
-static TRANSFORM_LAYOUT: Lazy<Mutex<Option<Arc<wgpu::BindGroupLayout>>>> = Lazy::new(|| Mutex::new(None));
-static LIGHT_LAYOUT: Lazy<Mutex<Option<Arc<wgpu::BindGroupLayout>>>> = Lazy::new(|| Mutex::new(None));
-static SHADOW_LAYOUT: Lazy<Mutex<Option<Arc<wgpu::BindGroupLayout>>>> = Lazy::new(|| Mutex::new(None));
-static SKYBOX_LAYOUT: Lazy<Mutex<Option<Arc<wgpu::BindGroupLayout>>>> = Lazy::new(|| Mutex::new(None));
-
 impl Renderer {
     pub fn new(
-        device: &wgpu::Device,
+        device: &Arc<wgpu::Device>,
+        layouts: &Layouts,
         config: &wgpu::SurfaceConfiguration,
         extent: &wgpu::Extent3d,
     ) -> Self {
-        let clear_color = wgpu::Color::BLACK;
-
-        let (texture_view, depth_texture) = create_depth_texture(device, extent);
-
-        let mut transform_layout = TRANSFORM_LAYOUT.lock().unwrap();
-        *transform_layout = Some(Arc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }
-            ],
-            label: None,
-        })));
-
-        let mut material_layout = MATERIAL_LAYOUT.lock().unwrap();
-        *material_layout = Some(Arc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 6,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 7,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 8,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 9,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 10,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 11,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-            label: Some("material_bind_group_layout"),
-        })));
-
-        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }
-            ],
-            label: Some("camera_bind_group_layout"),
-        });
-        
-        let mut light_layout = LIGHT_LAYOUT.lock().unwrap();
-        *light_layout = Some(Arc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        min_binding_size: None,
-                        has_dynamic_offset: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        min_binding_size: None,
-                        has_dynamic_offset: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        min_binding_size: None,
-                        has_dynamic_offset: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        min_binding_size: None,
-                        has_dynamic_offset: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::CubeArray,
-                        sample_type: wgpu::TextureSampleType::Depth,
-                    },
-                    count: Some(std::num::NonZeroU32::new(16).unwrap()),
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-            label: Some("light_bind_group_layout")
-        })));
-
-        let mut shadow_layout = SHADOW_LAYOUT.lock().unwrap();
-        *shadow_layout = Some(Arc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-            label: Some("shadow_layout"),
-        })));
-
-        let mut skybox_layout = SKYBOX_LAYOUT.lock().unwrap();
-        *skybox_layout = Some(Arc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::Cube,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-            label: Some("shadow_layout"),
-        })));
-
-        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[
-                transform_layout.as_ref().unwrap(),
-                &camera_bind_group_layout,
-                material_layout.as_ref().unwrap(),
-                light_layout.as_ref().unwrap(),
-            ],
-            push_constant_ranges: &[],
-        });
-
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: None,
-                source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/pbr.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(wgpu::TextureFormat::Depth32Float),
-                &[ModelVertex::desc()],
-                shader,
-            )
-        };
-
-        let light_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[
-                shadow_layout.as_ref().unwrap(),
-                transform_layout.as_ref().unwrap(),
-            ],
-            push_constant_ranges: &[],
-        });
-
-        let light_pipeline = {
-            let shader_desc = wgpu::ShaderModuleDescriptor {
-                label: None,
-                source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/shadow.wgsl").into()),
-            };
-            let shader = device.create_shader_module(shader_desc);
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("light pass"),
-                layout: Some(&light_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[ModelVertex::desc()],
-                },
-                fragment: None,
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Front),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false
-                },
-                depth_stencil: Some(wgpu::TextureFormat::Depth32Float).map(|format| wgpu::DepthStencilState {
-                    format,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState  {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None
-            })
-        };
-
-        let skybox_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[
-                skybox_layout.as_ref().unwrap(),
-            ],
-            push_constant_ranges: &[],
-        });
-
-        let skybox_pipeline = {
-            let shader_desc = wgpu::ShaderModuleDescriptor {
-                label: None,
-                source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/skybox.wgsl").into()),
-            };
-            let shader = device.create_shader_module(shader_desc);
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("skybox pass"),
-                layout: Some(&skybox_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[wgpu::VertexBufferLayout {
-                        array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                        step_mode: wgpu::VertexStepMode::Vertex,
-                        attributes: &[
-                            wgpu::VertexAttribute {
-                                offset: 0,
-                                shader_location: 0,
-                                format: wgpu::VertexFormat::Float32x3,
-                            }
-                        ]
-                    }],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: None,
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false
-                },
-                depth_stencil: Some(wgpu::TextureFormat::Depth32Float).map(|format| wgpu::DepthStencilState {
-                    format,
-                    depth_write_enabled: false,
-                    depth_compare: wgpu::CompareFunction::LessEqual,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState  {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None
-            })
-        };
+        let hdr_texture = create_hdr_texture(device, extent);
+        let hdr_view = hdr_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let post = PostStack::new(device, config.format, &hdr_texture);
 
         Self {
-            clear_color,
-            texture_view,
-            depth_texture,
-            camera_bind_group_layout,
-            render_pipeline,
-            light_pipeline,
-            skybox_pipeline,
+            format: config.format,
+            device: device.clone(),
+            hdr_texture,
+            hdr_view,
+            pool: TexturePool::new(),
+            shadows: ShadowNode::new(device, layouts),
+            opaque: OpaqueNode::new(device, layouts),
+            post,
+            nodes: Vec::new(),
         }
     }
 
     pub fn resize(&mut self, device: &wgpu::Device, extent: &wgpu::Extent3d) {
-        (self.texture_view, self.depth_texture) = create_depth_texture(device, extent);
+        self.hdr_texture = create_hdr_texture(device, extent);
+        self.hdr_view = self.hdr_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.post.resize(device, &self.hdr_texture);
     }
 
-    pub fn get_material_layout() -> Arc<BindGroupLayout> {
-        MATERIAL_LAYOUT.lock().unwrap().as_ref().unwrap().clone()
+    // `dt` is the frame time automatic exposure adapts over, None meters the frame on its own.
+    pub fn update_post_processing(&mut self, queue: &wgpu::Queue, camera: &Camera, dt: Option<f32>) {
+        self.post.update(queue, camera, dt);
     }
 
-    pub fn get_transform_layout() -> Arc<BindGroupLayout> {
-        TRANSFORM_LAYOUT.lock().unwrap().as_ref().unwrap().clone()
-    }
+    pub fn render_to_image(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &mut Scene, camera: &Camera, extent: &wgpu::Extent3d) -> Result<image::RgbaImage> {
+        if self.hdr_texture.size() != *extent {
+            self.resize(device, extent);
+        }
 
-    pub fn get_light_layout() -> Arc<BindGroupLayout> {
-        LIGHT_LAYOUT.lock().unwrap().as_ref().unwrap().clone()
-    }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_texture"),
+            size: *extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        queue.write_buffer(&camera.buffer, 0, cast_slice(&[camera.uniform]));
+        scene.light_manager.update_cascades(queue, camera);
+        scene.light_manager.update_point_shadows(queue, camera);
+        scene.skybox.update_projection(camera, queue);
+        self.update_post_processing(queue, camera, None);
+
+        let bytes_per_pixel = self.format.describe().block_size as u32;
+        let unpadded_bytes_per_row = extent.width * bytes_per_pixel;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_buffer"),
+            size: (padded_bytes_per_row * extent.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
 
-    pub fn get_shadow_layout() -> Arc<BindGroupLayout> {
-        SHADOW_LAYOUT.lock().unwrap().as_ref().unwrap().clone()
-    }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("offscreen_encoder")
+        });
 
-    pub fn get_skybox_layout() -> Arc<BindGroupLayout> {
-        SKYBOX_LAYOUT.lock().unwrap().as_ref().unwrap().clone()
-    }
-}
+        self.draw(&view, scene, camera, &mut encoder);
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &output_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(extent.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: extent.width,
+                height: extent.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        queue.submit([encoder.finish()]);
+
+        let buffer_slice = output_buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).unwrap();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv().unwrap()?;
 
-pub fn create_depth_texture(device: &wgpu::Device, extent: &wgpu::Extent3d) -> (wgpu::TextureView, Texture){
-    let texture = device.create_texture(&wgpu::TextureDescriptor {
-        label: Some("texture"),
-        size: *extent,
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Depth32Float,
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-        view_formats: &[],
-    });
+        let padded = buffer_slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * extent.height) as usize);
+        for row in padded.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        drop(padded);
+        output_buffer.unmap();
 
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let depth_texture = Texture::create_depth_texture(&device, extent, "depth_texture");
+        if matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
 
-    (texture_view, depth_texture)
+        Ok(image::RgbaImage::from_raw(extent.width, extent.height, pixels).unwrap())
+    }
 }
 
 pub trait Pass {
-    fn draw(&mut self, view: &wgpu::TextureView, scene: &mut Scene, camera: &Camera, encoder: &mut wgpu::CommandEncoder) -> Result<(), wgpu::SurfaceError>;
+    fn draw(&mut self, view: &wgpu::TextureView, scene: &mut Scene, camera: &Camera, encoder: &mut wgpu::CommandEncoder);
 }
 
 impl Pass for Renderer {
-    fn draw(&mut self, view: &wgpu::TextureView, scene: &mut Scene, camera: &Camera, encoder: &mut wgpu::CommandEncoder) -> Result<(), wgpu::SurfaceError> {
-        
-        for shadow in &scene.light_manager.point_shadows {
-            for (i, depth_texture_view) in shadow.views.iter().enumerate() {
-                let meshes = scene.world.read_storage::<Mesh>();
-                let transforms = scene.world.read_storage::<TransformComponent>();
-                let materials_c = scene.world.read_storage::<MaterialComponent>();
-
-                let light_pass_descriptor = wgpu::RenderPassDescriptor {
-                    label: Some("light_pass_desc"),
-                    color_attachments: &[],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: depth_texture_view,
-                        depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
-                            store: true,
-                        }),
-                        stencil_ops: None,
-                    }),
-                };
-                let mut light_pass = encoder.begin_render_pass(&light_pass_descriptor);
-                light_pass.set_pipeline(&self.light_pipeline);
-                light_pass.set_bind_group(0, &shadow.bind_groups[i], &[]);
-
-                for (transform, mesh, _) in (&transforms, &meshes, &materials_c).join() {
-                    light_pass.set_bind_group(1, &transform.bind_group, &[]);
-                    for m in (*mesh.mesh).iter() {
-                        light_pass.set_vertex_buffer(0, m.vertex_buffer.slice(..));
-                        light_pass.set_index_buffer(m.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                        light_pass.draw_indexed(0..m.element_count, 0, 0..1);
-                    }
-                }
-            }
-        }
-
-        let meshes = scene.world.read_storage::<Mesh>();
-        let transforms = scene.world.read_storage::<TransformComponent>();
-        let materials_c = scene.world.read_storage::<MaterialComponent>();
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render_pass"),
-                color_attachments: &[
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(self.clear_color),
-                            store: true,
-                        }
-                    }),
-                ],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            }); 
-
-            render_pass.set_pipeline(&self.skybox_pipeline);
-            render_pass.set_bind_group(0, &scene.skybox.as_ref().unwrap().bind_group, &[]);
-            
-            render_pass.set_vertex_buffer(0, scene.skybox.as_ref().unwrap().vertex_buffer.slice(..));
-            render_pass.draw(0..36, 0..1);
+    fn draw(&mut self, view: &wgpu::TextureView, scene: &mut Scene, camera: &Camera, encoder: &mut wgpu::CommandEncoder) {
+        let scene = &*scene;
+        let mut graph = RenderGraph::new();
+        graph.import("output", view);
+        graph.import("hdr", &self.hdr_view);
+        graph.create("depth", TextureDesc {
+            size: self.hdr_texture.size(),
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(1, &camera.bind_group, &[]);
-            render_pass.set_bind_group(3, &scene.light_manager.bind_group, &[]);
-            
-            for (mesh, transform, material) in (&meshes, &transforms, &materials_c).join()  {
-                render_pass.set_bind_group(0, &transform.bind_group, &[]);
-                for m in (*mesh.mesh).iter() {
-                    render_pass.draw_mesh(&m, &material.material);
-                }
-            }
+        self.shadows.add_passes(&mut graph, scene, camera);
+        self.opaque.add_passes(&mut graph, scene, camera);
+        self.post.add_passes(&mut graph, scene, camera);
+        for node in &self.nodes {
+            node.add_passes(&mut graph, scene, camera);
         }
 
-        Ok(())
+        graph.execute(&self.device, &mut self.pool, encoder);
     }
-}
\ No newline at end of file
+}
@@ -7,7 +7,7 @@ use std::sync::Arc;
 use crate::util::cast_slice;
 
 use super::asset::texture::Texture;
-use super::asset::model::{ModelVertex, Mesh};
+use super::asset::model::{ModelVertex, Mesh, Bounds};
 
 pub fn load_string(file_name: &str) -> Result<String> {
     let mut path = std::env::current_dir().unwrap().join("res");
@@ -161,6 +161,7 @@ pub fn load_mesh(
                 index_buffer,
                 element_count: material.mesh.indices.len() as u32,
                 material: material.mesh.material_id.unwrap_or(0),
+                bounds: Bounds::from_vertices(&vertices),
             }
         })
         .collect::<Vec<_>>();
@@ -0,0 +1,402 @@
+use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}, path::{Path, PathBuf}};
+
+use anyhow::{bail, Result};
+use wgpu::util::DeviceExt;
+
+use crate::util::cast_slice;
+
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTERED_SIZE: u32 = 128;
+// Roughness 0 to 1 is spread evenly across the prefiltered mips.
+pub const PREFILTERED_MIPS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 256;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BYTES_PER_TEXEL: u32 = 8;
+const CACHE_MAGIC: &[u8; 4] = b"RIBL";
+// Bumped whenever the bake itself changes, so caches from an older one are baked again.
+const BAKE_VERSION: u32 = 2;
+
+// Image based lighting baked from a skybox: diffuse irradiance, specular prefiltered by roughness
+// down the mip chain, and the split sum BRDF lookup table.
+pub struct Environment {
+    irradiance: wgpu::Texture,
+    prefiltered: wgpu::Texture,
+    brdf_lut: wgpu::Texture,
+    pub irradiance_view: wgpu::TextureView,
+    pub prefiltered_view: wgpu::TextureView,
+    pub brdf_lut_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl Environment {
+    // Loaded from `cache` when it was baked from the same skybox files, otherwise baked and written there.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, skybox: &wgpu::Texture, files: &[PathBuf], cache: &Path) -> Self {
+        let irradiance = create_texture(device, "irradiance", IRRADIANCE_SIZE, 6, 1);
+        let prefiltered = create_texture(device, "prefiltered environment", PREFILTERED_SIZE, 6, PREFILTERED_MIPS);
+        let brdf_lut = create_texture(device, "brdf lut", BRDF_LUT_SIZE, 1, 1);
+
+        let cube_view = |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let environment = Self {
+            irradiance_view: cube_view(&irradiance),
+            prefiltered_view: cube_view(&prefiltered),
+            brdf_lut_view: brdf_lut.create_view(&wgpu::TextureViewDescriptor::default()),
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("environment_sampler"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+        };
+
+        let key = cache_key(files);
+        match environment.load(queue, cache, key) {
+            Ok(()) => return environment,
+            Err(e) => println!("Baking environment lighting ({})", e),
+        }
+
+        environment.bake(device, queue, skybox);
+        if let Err(e) = environment.save(device, queue, cache, key) {
+            println!("Failed to cache environment lighting to {}: {}", cache.display(), e);
+        }
+        environment
+    }
+
+    fn bake(&self, device: &wgpu::Device, queue: &wgpu::Queue, skybox: &wgpu::Texture) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("environment_shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../../shaders/environment.wgsl").into()),
+        });
+
+        let cube_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("environment_bind_group_layout"),
+        });
+
+        let lut_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("brdf_lut_bind_group_layout"),
+        });
+
+        let pipeline = |layout: &wgpu::BindGroupLayout, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("environment_pipeline_layout"),
+                    bind_group_layouts: &[layout],
+                    push_constant_ranges: &[],
+                })),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let downsample_pipeline = pipeline(&cube_layout, "downsample");
+        let irradiance_pipeline = pipeline(&cube_layout, "irradiance");
+        let prefilter_pipeline = pipeline(&cube_layout, "prefilter");
+        let brdf_pipeline = pipeline(&lut_layout, "brdf");
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("environment_bake_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // The skybox copied into a cube with a full mip chain, so the prefilter can read each sample
+        // from the mip whose texels cover about as much of the sphere as the sample stands for.
+        let source_size = skybox.width();
+        let source_mips = 32 - source_size.leading_zeros();
+        let source = create_texture(device, "environment source", source_size, 6, source_mips);
+        let cube_view = |texture: &wgpu::Texture, base_mip_level: u32, mip_level_count: u32| texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            base_mip_level,
+            mip_level_count: std::num::NonZeroU32::new(mip_level_count),
+            ..Default::default()
+        });
+        let skybox_view = cube_view(skybox, 0, 1);
+        let source_view = cube_view(&source, 0, source_mips);
+        let source_mip_views: Vec<wgpu::TextureView> = (0..source_mips).map(|mip| cube_view(&source, mip, 1)).collect();
+
+        // One pass per target: the skybox into the source's top mip and each mip after filtered down
+        // from the one above it, then the irradiance map and every prefiltered mip with its own roughness.
+        let mut targets = vec![(&downsample_pipeline, &skybox_view, &source, 0, 0.0)];
+        for mip in 1..source_mips {
+            targets.push((&downsample_pipeline, &source_mip_views[mip as usize - 1], &source, mip, 0.0));
+        }
+        targets.push((&irradiance_pipeline, &source_view, &self.irradiance, 0, 0.0));
+        for mip in 0..PREFILTERED_MIPS {
+            targets.push((&prefilter_pipeline, &source_view, &self.prefiltered, mip, mip as f32 / (PREFILTERED_MIPS - 1) as f32));
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("environment_encoder"),
+        });
+
+        for (pipeline, input, texture, mip, roughness) in targets {
+            let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: mip,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            });
+            let roughness_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("roughness_buffer"),
+                contents: cast_slice(&[roughness, 0.0, 0.0, 0.0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &cube_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: roughness_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("environment_bind_group"),
+            });
+
+            let size = (texture.width() >> mip).max(1);
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("environment_pass") });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((size + 7) / 8, (size + 7) / 8, 6);
+        }
+
+        let lut_view = self.brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+        let lut_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &lut_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&lut_view),
+                },
+            ],
+            label: Some("brdf_lut_bind_group"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("brdf_lut_pass") });
+            pass.set_pipeline(&brdf_pipeline);
+            pass.set_bind_group(0, &lut_bind_group, &[]);
+            pass.dispatch_workgroups((BRDF_LUT_SIZE + 7) / 8, (BRDF_LUT_SIZE + 7) / 8, 1);
+        }
+
+        queue.submit([encoder.finish()]);
+    }
+
+    // Every mip of every texture in a fixed order, so the file needs no table of contents.
+    fn levels(&self) -> Vec<(&wgpu::Texture, u32)> {
+        let mut levels = vec![(&self.irradiance, 0)];
+        levels.extend((0..PREFILTERED_MIPS).map(|mip| (&self.prefiltered, mip)));
+        levels.push((&self.brdf_lut, 0));
+        levels
+    }
+
+    fn load(&self, queue: &wgpu::Queue, cache: &Path, key: u64) -> Result<()> {
+        let bytes = std::fs::read(cache)?;
+        let expected: usize = self.levels().iter().map(|(texture, mip)| level_size(texture, *mip)).sum();
+        if bytes.len() != 12 + expected || &bytes[..4] != CACHE_MAGIC || bytes[4..12] != key.to_le_bytes() {
+            bail!("cache at {} is out of date", cache.display());
+        }
+
+        let mut offset = 12;
+        for (texture, mip) in self.levels() {
+            let size = (texture.width() >> mip).max(1);
+            let length = level_size(texture, mip);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture,
+                    mip_level: mip,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &bytes[offset..offset + length],
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(size * BYTES_PER_TEXEL),
+                    rows_per_image: std::num::NonZeroU32::new(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: texture.depth_or_array_layers(),
+                },
+            );
+            offset += length;
+        }
+        Ok(())
+    }
+
+    fn save(&self, device: &wgpu::Device, queue: &wgpu::Queue, cache: &Path, key: u64) -> Result<()> {
+        let mut bytes = CACHE_MAGIC.to_vec();
+        bytes.extend_from_slice(&key.to_le_bytes());
+        for (texture, mip) in self.levels() {
+            bytes.extend(read_level(device, queue, texture, mip)?);
+        }
+        std::fs::write(cache, bytes)?;
+        Ok(())
+    }
+}
+
+// Changes whenever one of the skybox images or the baked layout does.
+fn cache_key(files: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (BAKE_VERSION, IRRADIANCE_SIZE, PREFILTERED_SIZE, PREFILTERED_MIPS, BRDF_LUT_SIZE).hash(&mut hasher);
+    for file in files {
+        file.hash(&mut hasher);
+        if let Ok(metadata) = std::fs::metadata(file) {
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn create_texture(device: &wgpu::Device, label: &str, size: u32, layers: u32, mips: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: mips,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn level_size(texture: &wgpu::Texture, mip: u32) -> usize {
+    let size = (texture.width() >> mip).max(1);
+    (size * size * BYTES_PER_TEXEL * texture.depth_or_array_layers()) as usize
+}
+
+fn read_level(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, mip: u32) -> Result<Vec<u8>> {
+    let size = (texture.width() >> mip).max(1);
+    let layers = texture.depth_or_array_layers();
+    let unpadded_bytes_per_row = size * BYTES_PER_TEXEL;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("environment_readback_buffer"),
+        size: (padded_bytes_per_row * size * layers) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("environment_readback_encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture,
+            mip_level: mip,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: std::num::NonZeroU32::new(size),
+            },
+        },
+        wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    let (tx, rx) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        tx.send(result).unwrap();
+    });
+    device.poll(wgpu::Maintain::Wait);
+    rx.recv()??;
+
+    let padded = slice.get_mapped_range();
+    let mut bytes = Vec::with_capacity((unpadded_bytes_per_row * size * layers) as usize);
+    for row in padded.chunks(padded_bytes_per_row as usize) {
+        bytes.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+    }
+    drop(padded);
+    buffer.unmap();
+    Ok(bytes)
+}
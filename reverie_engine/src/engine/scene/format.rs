@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Serialize, Deserialize};
+use serde_yaml::Value;
+use specs::{Component, HashMapStorage};
+use uuid::Uuid;
+
+use crate::engine::components::transform::quaternion_from_euler;
+
+pub const SCENE_VERSION: u32 = 3;
+
+// Legacy files open with this section, they usually aren't valid YAML as a whole since every section
+// repeats the entity indices.
+const LEGACY_HEADER: &str = "# Names";
+const LEGACY_SECTIONS: [&str; 6] = ["name", "transform", "material", "mesh", "point_light", "directional_light"];
+
+#[derive(Serialize, Deserialize)]
+pub struct SceneFile {
+    pub version: u32,
+    // Registry id of the skybox texture, the default skybox when missing.
+    #[serde(default)]
+    pub skybox: Option<usize>,
+    #[serde(default)]
+    pub entities: Vec<EntityData>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EntityData {
+    pub id: Uuid,
+    #[serde(default)]
+    pub components: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, Default, Component)]
+#[storage(HashMapStorage)]
+pub struct UnknownComponents(pub BTreeMap<String, Value>);
+
+// Version 1 entities were identified by their position in the list.
+#[derive(Deserialize)]
+struct SceneFileV1 {
+    #[serde(default)]
+    entities: Vec<EntityDataV1>,
+}
+
+#[derive(Default, Deserialize)]
+struct EntityDataV1 {
+    #[serde(default)]
+    components: BTreeMap<String, Value>,
+}
+
+impl SceneFile {
+    pub fn new(entities: Vec<EntityData>) -> Self {
+        Self {
+            version: SCENE_VERSION,
+            skybox: None,
+            entities,
+        }
+    }
+
+    pub fn parse(yaml: &str) -> Result<Self> {
+        let value = serde_yaml::from_str::<Value>(yaml);
+        let versioned = value.as_ref().map_or(false, |value| value.get("version").is_some());
+        if !versioned && yaml.trim_start().lines().next() == Some(LEGACY_HEADER) {
+            return migrate_v2(migrate_v1(parse_legacy(yaml)?));
+        }
+
+        let value = value?;
+        let version = value.get("version")
+            .ok_or_else(|| anyhow!("scene file has no version"))?
+            .as_u64()
+            .ok_or_else(|| anyhow!("scene version must be a number"))? as u32;
+
+        match version {
+            1 => migrate_v2(migrate_v1(serde_yaml::from_value(value)?)),
+            2 => migrate_v2(serde_yaml::from_value(value)?),
+            SCENE_VERSION => Ok(serde_yaml::from_value(value)?),
+            _ => bail!("unsupported scene version {}, expected at most {}", version, SCENE_VERSION),
+        }
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+}
+
+fn migrate_v1(scene: SceneFileV1) -> SceneFile {
+    let ids: Vec<Uuid> = scene.entities.iter().map(|_| Uuid::new_v4()).collect();
+    let remap = |value: &mut Value| {
+        if let Some(index) = value.as_u64() {
+            *value = match ids.get(index as usize) {
+                Some(id) => Value::from(id.to_string()),
+                None => Value::Null,
+            };
+        }
+    };
+
+    let mut entities = Vec::new();
+    for (id, mut entity) in ids.iter().zip(scene.entities) {
+        remap_transform_references(&mut entity.components, &remap);
+
+        entities.push(EntityData {
+            id: *id,
+            components: entity.components,
+        });
+    }
+
+    SceneFile::new(entities)
+}
+
+// Version 2 stored rotations as x * y * z euler degrees, version 3 stores quaternions.
+fn migrate_v2(mut scene: SceneFile) -> Result<SceneFile> {
+    for entity in &mut scene.entities {
+        if let Some(rotation) = entity.components.get_mut("transform").and_then(|transform| transform.get_mut("rotation")) {
+            let euler: cg::Vector3<f32> = serde_yaml::from_value(rotation.clone())?;
+            *rotation = serde_yaml::to_value(quaternion_from_euler(euler))?;
+        }
+    }
+
+    scene.version = SCENE_VERSION;
+    Ok(scene)
+}
+
+fn parse_legacy(yaml: &str) -> Result<SceneFileV1> {
+    let mut sections: Vec<String> = Vec::new();
+    for line in yaml.lines() {
+        if line.starts_with("# ") {
+            sections.push(String::new());
+        } else if let Some(section) = sections.last_mut() {
+            section.push_str(line);
+            section.push('\n');
+        }
+    }
+
+    if sections.len() != LEGACY_SECTIONS.len() {
+        bail!("legacy scene has {} sections, expected {}", sections.len(), LEGACY_SECTIONS.len());
+    }
+
+    let mut legacy: BTreeMap<u32, EntityDataV1> = BTreeMap::new();
+    for (component, section) in LEGACY_SECTIONS.iter().zip(sections.iter()) {
+        let values: Option<BTreeMap<u32, Value>> = serde_yaml::from_str(section)?;
+        for (id, value) in values.unwrap_or_default() {
+            legacy.entry(id).or_default().components.insert(component.to_string(), value);
+        }
+    }
+
+    let indices: BTreeMap<u32, u64> = legacy.keys().enumerate().map(|(index, id)| (*id, index as u64)).collect();
+    let remap = |value: &mut Value| {
+        if let Some(id) = value.as_u64() {
+            *value = match indices.get(&(id as u32)) {
+                Some(index) => Value::from(*index),
+                None => Value::Null,
+            };
+        }
+    };
+
+    let mut entities: Vec<EntityDataV1> = legacy.into_values().collect();
+    for entity in &mut entities {
+        remap_transform_references(&mut entity.components, &remap);
+    }
+
+    Ok(SceneFileV1 { entities })
+}
+
+fn remap_transform_references(components: &mut BTreeMap<String, Value>, remap: &impl Fn(&mut Value)) {
+    if let Some(transform) = components.get_mut("transform") {
+        if let Some(parent) = transform.get_mut("parent") {
+            remap(parent);
+        }
+        if let Some(Value::Sequence(children)) = transform.get_mut("children") {
+            children.iter_mut().for_each(remap);
+            children.retain(|child| !child.is_null());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY: &str = "\
+# Names
+4: Child
+7: Parent
+
+# Transforms
+4:
+  position: {x: 0.0, y: 1.0, z: 0.0}
+  rotation: {x: 90.0, y: 0.0, z: 0.0}
+  scale: {x: 1.0, y: 1.0, z: 1.0}
+  parent: 7
+  children: []
+7:
+  position: {x: 0.0, y: 0.0, z: 0.0}
+  rotation: {x: 0.0, y: 0.0, z: 0.0}
+  scale: {x: 2.0, y: 2.0, z: 2.0}
+  parent: null
+  children: [4, 9]
+
+# Materials
+{}
+
+# Meshes
+{}
+
+# Point Lights
+7:
+  diffuse_color: [1.0, 0.0, 0.0]
+
+# Directional Lights
+{}
+";
+
+    fn component<'a>(entity: &'a EntityData, name: &str) -> &'a Value {
+        entity.components.get(name).unwrap()
+    }
+
+    fn rotation(entity: &EntityData) -> cg::Quaternion<f32> {
+        serde_yaml::from_value(component(entity, "transform")["rotation"].clone()).unwrap()
+    }
+
+    fn assert_rotation_eq(a: cg::Quaternion<f32>, b: cg::Quaternion<f32>) {
+        // q and -q are the same rotation.
+        let dot = a.s * b.s + a.v.x * b.v.x + a.v.y * b.v.y + a.v.z * b.v.z;
+        assert!((dot.abs() - 1.0).abs() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn legacy_scenes_migrate_to_the_current_version() {
+        let scene = SceneFile::parse(LEGACY).unwrap();
+        assert_eq!(scene.version, SCENE_VERSION);
+        assert_eq!(scene.skybox, None);
+        assert_eq!(scene.entities.len(), 2);
+
+        let (child, parent) = (&scene.entities[0], &scene.entities[1]);
+        assert_eq!(component(child, "name"), &Value::from("Child"));
+        assert_eq!(component(parent, "name"), &Value::from("Parent"));
+        assert!(parent.components.contains_key("point_light"));
+        assert!(!child.components.contains_key("point_light"));
+
+        // Legacy ids become references to the new uuids, dangling ones are dropped.
+        assert_eq!(component(child, "transform")["parent"], Value::from(parent.id.to_string()));
+        assert_eq!(component(parent, "transform")["children"], Value::Sequence(vec![Value::from(child.id.to_string())]));
+
+        assert_rotation_eq(rotation(child), quaternion_from_euler(cg::vec3(90.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn legacy_scenes_need_every_section() {
+        let truncated = LEGACY.split("# Point Lights").next().unwrap();
+        assert!(SceneFile::parse(truncated).is_err());
+    }
+
+    #[test]
+    fn version_1_indices_become_uuids() {
+        let yaml = "\
+version: 1
+entities:
+- components:
+    transform:
+      rotation: {x: 0.0, y: 45.0, z: 0.0}
+      parent: null
+      children: [1, 5]
+- components:
+    transform:
+      rotation: {x: 0.0, y: 0.0, z: 0.0}
+      parent: 0
+      children: []
+";
+        let scene = SceneFile::parse(yaml).unwrap();
+        assert_eq!(scene.version, SCENE_VERSION);
+
+        let (parent, child) = (&scene.entities[0], &scene.entities[1]);
+        assert_ne!(parent.id, child.id);
+        assert_eq!(component(child, "transform")["parent"], Value::from(parent.id.to_string()));
+        assert_eq!(component(parent, "transform")["children"], Value::Sequence(vec![Value::from(child.id.to_string())]));
+        assert_rotation_eq(rotation(parent), quaternion_from_euler(cg::vec3(0.0, 45.0, 0.0)));
+    }
+
+    #[test]
+    fn version_2_euler_rotations_become_quaternions() {
+        let id = Uuid::new_v4();
+        let yaml = format!("\
+version: 2
+entities:
+- id: {}
+  components:
+    transform:
+      rotation: {{x: 30.0, y: -60.0, z: 10.0}}
+    mesh:
+      id: 3
+", id);
+        let scene = SceneFile::parse(&yaml).unwrap();
+        assert_eq!(scene.version, SCENE_VERSION);
+        assert_eq!(scene.entities[0].id, id);
+        assert_eq!(component(&scene.entities[0], "mesh")["id"], Value::from(3));
+        assert_rotation_eq(rotation(&scene.entities[0]), quaternion_from_euler(cg::vec3(30.0, -60.0, 10.0)));
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let mut components = BTreeMap::new();
+        components.insert("name".to_string(), Value::from("Entity"));
+        components.insert("transform".to_string(), serde_yaml::to_value(serde_yaml::Mapping::from_iter([
+            (Value::from("rotation"), serde_yaml::to_value(quaternion_from_euler(cg::vec3(10.0, 20.0, 30.0))).unwrap()),
+        ])).unwrap());
+        let mut scene = SceneFile::new(vec![EntityData { id: Uuid::new_v4(), components }]);
+        scene.skybox = Some(12);
+
+        let parsed = SceneFile::parse(&scene.to_yaml().unwrap()).unwrap();
+        assert_eq!(parsed.version, SCENE_VERSION);
+        assert_eq!(parsed.skybox, Some(12));
+        assert_eq!(parsed.entities[0].id, scene.entities[0].id);
+        assert_eq!(parsed.entities[0].components, scene.entities[0].components);
+    }
+
+    #[test]
+    fn leading_comments_are_not_legacy() {
+        let yaml = format!("# Names of things\nversion: {}\nentities: []\n", SCENE_VERSION);
+        assert_eq!(SceneFile::parse(&yaml).unwrap().version, SCENE_VERSION);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        assert!(SceneFile::parse(&format!("version: {}\nentities: []\n", SCENE_VERSION + 1)).is_err());
+        assert!(SceneFile::parse("entities: []\n").is_err());
+    }
+}
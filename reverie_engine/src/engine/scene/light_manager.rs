@@ -1,57 +1,276 @@
-use specs::{*, WorldExt};
+use std::{collections::HashMap, sync::Arc};
+
+use cg::{EuclideanSpace, InnerSpace, Rotation};
+use specs::{*, WorldExt, storage::ComponentEvent, world::Index};
 use wgpu::util::DeviceExt;
 
 use crate::util::{cast_slice, align::Align16};
 
-use super::super::{components::{light::{PointLight, DirectionalLight}, transform::TransformComponent}, renderer::Renderer};
+use super::super::{
+    components::{light::{PointLight, DirectionalLight, SpotLight, CascadeSettings}, transform::TransformComponent},
+    camera::{Camera, ProjectionKind, OPENGL_TO_WGPU_MATRIX},
+    layouts::Layouts,
+};
+use super::{shadow_atlas::{ShadowAtlas, Tile}, environment::Environment};
+
+pub const MAX_CASCADES: usize = 4;
+pub const MAX_DIRECTIONAL_SHADOWS: usize = 4;
+pub const MAX_SPOT_SHADOWS: usize = 8;
+// How far behind a cascade's bounding sphere casters are still rendered into it.
+const CASTER_DISTANCE: f32 = 50.0;
+const SPOT_SHADOW_RESOLUTION: u32 = 1024;
+const SPOT_NEAR: f32 = 0.1;
+
+const POINT_NEAR: f32 = 0.1;
 
+#[repr(C)]
 #[derive(Clone)]
 struct LightData {
-    _projections: Align16<[cg::Matrix4<f32>; 6]>,
-    _position: Align16<cg::Vector3<f32>>,
-    _color: Align16<cg::Vector3<f32>>,
+    _projections: [cg::Matrix4<f32>; 6],
+    // Offset and scale of each face in the point shadow atlas.
+    _tiles: [[f32; 4]; 6],
+    _position: cg::Vector3<f32>,
+    _range: f32,
+    _color: cg::Vector3<f32>,
+    // Luminous intensity in candela.
+    _intensity: f32,
+    // -1 when the light has no room in the atlas or casts no shadow.
+    _shadow_index: i32,
+    _bias: f32,
+    _padding: [i32; 2],
 }
 
+impl LightData {
+    fn new(light: &PointLight, position: cg::Vector3<f32>) -> Self {
+        Self {
+            _projections: calculate_point_light_projection(position, light.range),
+            _tiles: [[0.0; 4]; 6],
+            _position: position,
+            _range: light.range,
+            _color: light.get_color(),
+            _intensity: light.candela(),
+            _shadow_index: -1,
+            _bias: light.shadows.bias,
+            _padding: [0; 2],
+        }
+    }
+
+    fn set_tiles(&mut self, shadow_index: usize, tiles: Option<&[Tile]>, atlas_size: u32) {
+        match tiles {
+            Some(tiles) => {
+                self._shadow_index = shadow_index as i32;
+                for (uv, tile) in self._tiles.iter_mut().zip(tiles) {
+                    *uv = tile.uv(atlas_size);
+                }
+            }
+            None => self._shadow_index = -1,
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            _projections: [cg::SquareMatrix::identity(); 6],
+            _tiles: [[0.0; 4]; 6],
+            _position: cg::vec3(0.0, 0.0, 0.0),
+            // Out of range everywhere, so free slots cost the shader nothing.
+            _range: 0.0,
+            _color: cg::vec3(0.0, 0.0, 0.0),
+            _intensity: 0.0,
+            _shadow_index: -1,
+            _bias: 0.0,
+            _padding: [0; 2],
+        }
+    }
+}
+
+#[repr(C)]
 #[derive(Clone)]
 struct DirectionalData {
     _direction: Align16<cg::Vector3<f32>>,
-    _color: Align16<[f32; 3]>
+    _color: Align16<[f32; 3]>,
+    _cascades: [cg::Matrix4<f32>; MAX_CASCADES],
+    _cascade_count: i32,
+    _pcf_radius: i32,
+    // Slot in the directional shadow texture array, or -1 when the light casts no shadow.
+    _shadow_index: i32,
+    _debug: i32,
+}
+
+impl DirectionalData {
+    fn new(light: &DirectionalLight, shadow_index: Option<usize>) -> Self {
+        Self {
+            _direction: Align16(light.direction),
+            _color: Align16(light.color),
+            _cascades: [cg::SquareMatrix::identity(); MAX_CASCADES],
+            _cascade_count: light.shadows.cascades.clamp(1, MAX_CASCADES as u32) as i32,
+            _pcf_radius: light.shadows.pcf_radius as i32,
+            _shadow_index: shadow_index.map_or(-1, |index| index as i32),
+            _debug: light.debug_cascades as i32,
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            _direction: Align16(cg::vec3(0.0, 0.0, 0.0)),
+            _color: Align16([0.0, 0.0, 0.0]),
+            _cascades: [cg::SquareMatrix::identity(); MAX_CASCADES],
+            _cascade_count: 0,
+            _pcf_radius: 0,
+            _shadow_index: -1,
+            _debug: 0,
+        }
+    }
 }
 
 pub struct PointShadow {
-    pub views: [wgpu::TextureView; 6],
-    pub cube_view: wgpu::TextureView,
     pub buffers: Vec<wgpu::Buffer>,
     pub bind_groups: Vec<wgpu::BindGroup>,
+    // Where each face is drawn in the atlas, or None when it had no room this frame.
+    pub tiles: Option<Vec<Tile>>,
+    light: usize,
+    resolution: u32,
 }
 
-pub struct LightManager {
-    point_lights: Vec<LightData>,
-    directional_lights: Vec<DirectionalData>,
-    pub point_shadows: Vec<PointShadow>,
+impl PointShadow {
+    fn new(device: &wgpu::Device, layouts: &Layouts, light: usize, resolution: u32, projections: &[cg::Matrix4<f32>; 6]) -> Self {
+        let buffers: Vec<wgpu::Buffer> = projections.iter().map(|projection| device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("perspective buffer"),
+            contents: cast_slice(&[Align16(*projection)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })).collect();
+
+        let bind_groups = buffers.iter().map(|buffer| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.shadow,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding()
+                },
+            ],
+            label: Some("shadow bind group")
+        })).collect();
+
+        Self {
+            buffers,
+            bind_groups,
+            tiles: None,
+            light,
+            resolution,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone)]
+struct SpotData {
+    _projection: cg::Matrix4<f32>,
+    _position: cg::Vector3<f32>,
+    _range: f32,
+    _direction: cg::Vector3<f32>,
+    _inner_cos: f32,
+    _color: cg::Vector3<f32>,
+    _outer_cos: f32,
+    // Layer in the spot shadow texture, or -1 when the light casts no shadow.
+    _shadow_index: i32,
+    // Candela.
+    _intensity: f32,
+    _padding: [i32; 2],
+}
+
+impl SpotData {
+    fn new(light: &SpotLight, transform: &TransformComponent, shadow_index: Option<usize>) -> Self {
+        let position = transform.get_world_position();
+        let direction = transform.get_world_rotation().rotate_vector(-cg::Vector3::unit_z()).normalize();
+        let outer = light.outer_angle.clamp(0.1, 89.0);
+        Self {
+            _projection: calculate_spot_light_projection(position, direction, outer, light.range),
+            _position: position,
+            _range: light.range,
+            _direction: direction,
+            _inner_cos: cg::Rad::from(cg::Deg(light.inner_angle.min(outer))).0.cos(),
+            _color: light.get_color(),
+            _outer_cos: cg::Rad::from(cg::Deg(outer)).0.cos(),
+            _shadow_index: shadow_index.map_or(-1, |index| index as i32),
+            _intensity: light.candela(),
+            _padding: [0; 2],
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            _projection: cg::SquareMatrix::identity(),
+            _position: cg::vec3(0.0, 0.0, 0.0),
+            _range: 0.0,
+            _direction: cg::vec3(0.0, 0.0, -1.0),
+            _inner_cos: 1.0,
+            _color: cg::vec3(0.0, 0.0, 0.0),
+            _outer_cos: 1.0,
+            _shadow_index: -1,
+            _intensity: 0.0,
+            _padding: [0; 2],
+        }
+    }
+}
+
+pub struct SpotShadow {
+    pub view: wgpu::TextureView,
+    pub buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
-    pub point_buffer: wgpu::Buffer,
-    pub point_count_buffer: wgpu::Buffer,
-    pub directional_buffer: wgpu::Buffer,
-    pub directional_count_buffer: wgpu::Buffer,
+    light: usize,
+    // Layer in the spot shadow texture.
+    layer: usize,
 }
 
-impl LightManager {
-    pub fn new(device: &wgpu::Device, world: &World) -> Self {
-        let mut point_lights: Vec<LightData> = Vec::new();
-        let mut point_light_shadows: Vec<PointShadow> = Vec::new();
+impl SpotShadow {
+    fn new(device: &wgpu::Device, layouts: &Layouts, texture: &wgpu::Texture, light: usize, layer: usize, projection: cg::Matrix4<f32>) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("spot shadow buffer"),
+            contents: cast_slice(&[Align16(projection)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.shadow,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding()
+                },
+            ],
+            label: Some("spot shadow bind group")
+        });
 
-        let transform_components = world.read_component::<TransformComponent>();
-        let point_light_components = world.read_component::<PointLight>();
+        Self {
+            view: spot_shadow_layer_view(texture, layer),
+            buffer,
+            bind_group,
+            light,
+            layer,
+        }
+    }
+}
 
-        let point_light_count = point_light_components.count() as i32 + 1;
+// One depth layer per cascade, refit to the camera every frame.
+pub struct DirectionalShadow {
+    pub views: Vec<wgpu::TextureView>,
+    pub array_view: wgpu::TextureView,
+    pub buffers: Vec<wgpu::Buffer>,
+    pub bind_groups: Vec<wgpu::BindGroup>,
+    // Index into the directional light data.
+    light: usize,
+    // Slot in the shader's array of cascade textures.
+    index: usize,
+    settings: CascadeSettings,
+}
 
-        let shadow_depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("shadow"),
+impl DirectionalShadow {
+    fn new(device: &wgpu::Device, layouts: &Layouts, light: usize, index: usize, settings: &CascadeSettings) -> Self {
+        let cascades = settings.cascades.clamp(1, MAX_CASCADES as u32);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("directional shadow"),
             size: wgpu::Extent3d {
-                width: 2048,
-                height: 2048,
-                depth_or_array_layers: 6 * 8,
+                width: settings.resolution,
+                height: settings.resolution,
+                depth_or_array_layers: cascades,
             },
             mip_level_count: 1,
             sample_count: 1,
@@ -61,80 +280,132 @@ impl LightManager {
             view_formats: &[],
         });
 
-        for (i, (transform, light)) in (&transform_components, &point_light_components).join().enumerate() {
-            let light_position = transform.get_position();
-            let light_data = light.get_color();
-            let projections = calculate_point_light_projection(light_position);
-
-            let shadow_depth_views: [wgpu::TextureView; 6] = array_init::array_init(|j| {
-                shadow_depth_texture.create_view(&wgpu::TextureViewDescriptor {
-                    label: Some(format!("shadow view {}", i).as_str()),
-                    format: Some(wgpu::TextureFormat::Depth32Float),
-                    dimension: Some(wgpu::TextureViewDimension::D2),
-                    aspect: wgpu::TextureAspect::DepthOnly,
-                    base_mip_level: 0,
-                    mip_level_count: None,
-                    base_array_layer: (i * 6 + j) as u32,
-                    array_layer_count: None,
-                })
-            }
-            );
-    
-            let mut shadow_buffers: Vec<wgpu::Buffer> = Vec::new();
-            for projection in projections {
-                shadow_buffers.push(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("perspective buffer"),
-                    contents: cast_slice(&[Align16(projection)]),
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                }));
-            }
-    
-            let mut shadow_bind_groups: Vec<wgpu::BindGroup> = Vec::new();
-            for buffer in &shadow_buffers {
-                shadow_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &Renderer::get_shadow_layout(),
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: buffer.as_entire_binding()
-                        },
-                    ],
-                    label: Some("shadow bind group")
-                }));
+        let views = (0..cascades).map(|i| texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(format!("cascade view {}", i).as_str()),
+            format: Some(wgpu::TextureFormat::Depth32Float),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            base_mip_level: 0,
+            mip_level_count: None,
+            base_array_layer: i,
+            array_layer_count: std::num::NonZeroU32::new(1),
+        })).collect();
+
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("cascades"),
+            format: Some(wgpu::TextureFormat::Depth32Float),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+
+        let buffers: Vec<wgpu::Buffer> = (0..cascades).map(|_| device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cascade buffer"),
+            contents: cast_slice(&[Align16(<cg::Matrix4<f32> as cg::SquareMatrix>::identity())]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })).collect();
+
+        let bind_groups = buffers.iter().map(|buffer| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.shadow,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding()
+                },
+            ],
+            label: Some("cascade bind group")
+        })).collect();
+
+        Self {
+            views,
+            array_view,
+            buffers,
+            bind_groups,
+            light,
+            index,
+            settings: settings.clone(),
+        }
+    }
+}
+
+// What the light bind group points at besides the shadow maps, kept so it can be rebuilt when a buffer or the atlas is reallocated.
+struct LightResources {
+    point_buffer: wgpu::Buffer,
+    point_count_buffer: wgpu::Buffer,
+    directional_buffer: wgpu::Buffer,
+    directional_count_buffer: wgpu::Buffer,
+    spot_buffer: wgpu::Buffer,
+    spot_count_buffer: wgpu::Buffer,
+    cascade_fallback: wgpu::TextureView,
+    shadow_sampler: wgpu::Sampler,
+    spot_shadow_texture: wgpu::Texture,
+    spot_shadow_view: wgpu::TextureView,
+    environment: Arc<Environment>,
+    layouts: Arc<Layouts>,
+}
+
+pub struct LightManager {
+    // Slots are stable for as long as their light exists; freed ones are cleared and reused.
+    point_lights: Vec<LightData>,
+    point_slots: HashMap<Index, usize>,
+    free_point_slots: Vec<usize>,
+    point_capacity: usize,
+    point_reader: ReaderId<ComponentEvent>,
+    directional_lights: Vec<DirectionalData>,
+    directional_slots: HashMap<Index, usize>,
+    free_directional_slots: Vec<usize>,
+    directional_capacity: usize,
+    directional_reader: ReaderId<ComponentEvent>,
+    pub point_shadows: Vec<PointShadow>,
+    pub point_atlas: ShadowAtlas,
+    pub directional_shadows: Vec<DirectionalShadow>,
+    spot_lights: Vec<SpotData>,
+    spot_slots: HashMap<Index, usize>,
+    free_spot_slots: Vec<usize>,
+    spot_capacity: usize,
+    spot_reader: ReaderId<ComponentEvent>,
+    pub spot_shadows: Vec<SpotShadow>,
+    pub bind_group: wgpu::BindGroup,
+    resources: LightResources,
+}
+
+impl LightManager {
+    pub fn new(device: &wgpu::Device, layouts: Arc<Layouts>, world: &World, environment: Arc<Environment>) -> Self {
+        // Registered before reading the world, so the light system only sees what changes after this.
+        let point_reader = world.write_storage::<PointLight>().register_reader();
+        let directional_reader = world.write_storage::<DirectionalLight>().register_reader();
+        let spot_reader = world.write_storage::<SpotLight>().register_reader();
+
+        let mut point_lights: Vec<LightData> = Vec::new();
+        let mut point_slots = HashMap::new();
+        let mut free_point_slots = Vec::new();
+        let mut point_light_shadows: Vec<PointShadow> = Vec::new();
+
+        let transform_components = world.read_component::<TransformComponent>();
+        let point_light_components = world.read_component::<PointLight>();
+
+        for (entity, transform, light) in (&world.entities(), &transform_components, &point_light_components).join() {
+            let data = LightData::new(light, transform.get_world_position());
+            if light.shadows.enabled {
+                point_light_shadows.push(PointShadow::new(device, &layouts, point_lights.len(), light.shadows.resolution, &data._projections));
             }
+            point_slots.insert(entity.id(), point_lights.len());
+            point_lights.push(data);
+        }
 
-            let cube_view = shadow_depth_texture.create_view(&wgpu::TextureViewDescriptor {
-                label: Some("depthcube"),
-                format: Some(wgpu::TextureFormat::Depth32Float),
-                dimension: Some(wgpu::TextureViewDimension::CubeArray),
-                aspect: wgpu::TextureAspect::DepthOnly,
-                base_mip_level: 0,
-                mip_level_count: None,
-                base_array_layer: i as u32 * 6,
-                array_layer_count: None,
-            });
-
-            point_lights.push(LightData {
-                _projections: Align16(projections),
-                _position: Align16(light_position),
-                _color: Align16(light_data),
-            });
-
-            point_light_shadows.push(
-                PointShadow { 
-                    views: shadow_depth_views, 
-                    cube_view, 
-                    buffers: shadow_buffers, 
-                    bind_groups: shadow_bind_groups
-                }
-            );
+        // Until there is a camera to rank them by, lights are packed in the order they were found.
+        let requests: Vec<u32> = point_light_shadows.iter().map(|shadow| shadow.resolution).collect();
+        let point_atlas = ShadowAtlas::new(device, &requests, 6);
+        for (i, (shadow, tiles)) in point_light_shadows.iter_mut().zip(point_atlas.allocate(&requests, 6)).enumerate() {
+            point_lights[shadow.light].set_tiles(i, tiles.as_deref(), point_atlas.size);
+            shadow.tiles = tiles;
         }
-        
-        // point_lights.push(LightData {
-        //     _projections: Align16([cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity()]),
-        //     _position: Align16(cg::vec3(0.0, 0.0, 0.0)),
-        //     _color: Align16(cg::vec3(0.0, 0.0, 0.0)),
-        // });
+
+        if point_lights.is_empty() {
+            point_lights.push(LightData::empty());
+            free_point_slots.push(0);
+        }
+        let point_light_count = point_lights.len() as i32;
 
         let point_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("light_data_buffer"),
@@ -149,21 +420,26 @@ impl LightManager {
         });
 
         let mut directional_lights = Vec::new();
+        let mut directional_slots = HashMap::new();
+        let mut free_directional_slots = Vec::new();
+        let mut directional_shadows: Vec<DirectionalShadow> = Vec::new();
 
         let directional_light_components = world.read_component::<DirectionalLight>();
-        let directional_light_count = directional_light_components.count() as i32 + 1;
 
-        directional_lights.push(DirectionalData {
-            _direction: Align16(cg::vec3(0.0, 0.0, 0.0)),
-            _color: Align16([0.0, 0.0, 0.0])
-        });
+        for (entity, light) in (&world.entities(), &directional_light_components).join() {
+            let shadow_index = Some(directional_shadows.len()).filter(|index| light.shadows.enabled && *index < MAX_DIRECTIONAL_SHADOWS);
+            if let Some(index) = shadow_index {
+                directional_shadows.push(DirectionalShadow::new(device, &layouts, directional_lights.len(), index, &light.shadows));
+            }
+            directional_slots.insert(entity.id(), directional_lights.len());
+            directional_lights.push(DirectionalData::new(light, shadow_index));
+        }
 
-        for light in directional_light_components.join() {
-            directional_lights.push(DirectionalData {
-                _direction: Align16(light.direction),
-                _color: Align16(light.color)
-            });
+        if directional_lights.is_empty() {
+            directional_lights.push(DirectionalData::empty());
+            free_directional_slots.push(0);
         }
+        let directional_light_count = directional_lights.len() as i32;
 
         let directional_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("light_data_buffer"),
@@ -177,266 +453,669 @@ impl LightManager {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let cubemap_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("cubemap_sampler"),
+        let spot_light_components = world.read_component::<SpotLight>();
+        let shadowed_spot_lights = spot_light_components.join().filter(|light| light.shadows).count().clamp(1, MAX_SPOT_SHADOWS);
+        let spot_shadow_texture = create_spot_shadow_texture(device, shadowed_spot_lights as u32);
+
+        let mut spot_lights = Vec::new();
+        let mut spot_slots = HashMap::new();
+        let mut free_spot_slots = Vec::new();
+        let mut spot_shadows: Vec<SpotShadow> = Vec::new();
+        for (entity, transform, light) in (&world.entities(), &transform_components, &spot_light_components).join() {
+            let shadow_index = Some(spot_shadows.len()).filter(|index| light.shadows && *index < MAX_SPOT_SHADOWS);
+            let data = SpotData::new(light, transform, shadow_index);
+            if let Some(index) = shadow_index {
+                spot_shadows.push(SpotShadow::new(device, &layouts, &spot_shadow_texture, spot_lights.len(), index, data._projection));
+            }
+            spot_slots.insert(entity.id(), spot_lights.len());
+            spot_lights.push(data);
+        }
+
+        if spot_lights.is_empty() {
+            spot_lights.push(SpotData::empty());
+            free_spot_slots.push(0);
+        }
+        let spot_light_count = spot_lights.len() as i32;
+
+        let spot_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("spot_light_buffer"),
+            contents: cast_slice(&spot_lights.clone().into_boxed_slice()),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let spot_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("spot_light_count_buffer"),
+            contents: cast_slice(&[spot_light_count]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let spot_shadow_view = spot_shadow_array_view(&spot_shadow_texture);
+
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_sampler"),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: 0.1,
-            lod_max_clamp: 100.0,
-            compare: None,
-            anisotropy_clamp: None,
-            border_color: Some(wgpu::SamplerBorderColor::OpaqueWhite),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
         });
 
-        let mut shadow_views: Vec<&wgpu::TextureView> = Vec::new();
-        for i in 0..16 {
-            if i >= point_light_shadows.len() {
-                shadow_views.push(&point_light_shadows[0].cube_view);
-            } else {
-                shadow_views.push(&point_light_shadows[i].cube_view);
-            }
-        }
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_light_layout(),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: point_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: point_count_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: directional_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: directional_count_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::TextureViewArray(&shadow_views[..]),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::Sampler(&cubemap_sampler),
-                },
-            ],
-            label: Some("light_bind_group"),
+        let cascade_fallback = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("cascade fallback"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        }).create_view(&wgpu::TextureViewDescriptor {
+            label: Some("cascade fallback"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
         });
 
+        let resources = LightResources {
+            point_buffer,
+            point_count_buffer,
+            directional_buffer,
+            directional_count_buffer,
+            spot_buffer,
+            spot_count_buffer,
+            cascade_fallback,
+            shadow_sampler,
+            spot_shadow_texture,
+            spot_shadow_view,
+            environment,
+            layouts,
+        };
+        let bind_group = create_bind_group(device, &resources, &point_atlas, &directional_shadows);
+
         Self {
+            point_capacity: point_lights.len(),
             point_lights,
+            point_slots,
+            free_point_slots,
+            point_reader,
+            directional_capacity: directional_lights.len(),
             directional_lights,
+            directional_slots,
+            free_directional_slots,
+            directional_reader,
             point_shadows: point_light_shadows,
+            point_atlas,
+            directional_shadows,
+            spot_capacity: spot_lights.len(),
+            spot_lights,
+            spot_slots,
+            free_spot_slots,
+            spot_reader,
+            spot_shadows,
             bind_group,
-            point_buffer,
-            point_count_buffer,
-            directional_buffer,
-            directional_count_buffer,
+            resources,
         }
     }
 
-    pub fn update_light_position(&mut self, queue: &wgpu::Queue, index: usize, position: cg::Vector3<f32>) {
-        self.point_lights[index]._position = Align16(position);
-        queue.write_buffer(&self.point_buffer, (std::mem::size_of::<LightData>() * index + std::mem::size_of::<Align16<[cg::Matrix4<f32>; 6]>>()) as u64, cast_slice(&[Align16(position)]));
+    // Starts over from the lights in `world`, keeping the environment lighting.
+    pub fn rebuild(&mut self, device: &wgpu::Device, world: &World) {
+        *self = Self::new(device, self.resources.layouts.clone(), world, self.resources.environment.clone());
+    }
 
-        let projections = calculate_point_light_projection(position);
+    // Lights the scene with a different skybox, keeping every light where it is.
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: Arc<Environment>) {
+        self.resources.environment = environment;
+        self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+    }
 
-        queue.write_buffer(&self.point_buffer, (std::mem::size_of::<LightData>() * index) as u64, cast_slice(&[Align16(projections)]));
-        for (i, buffer) in self.point_shadows[index].buffers.iter().enumerate() {
-            queue.write_buffer(&buffer, 0, cast_slice(&[Align16(projections[i])]));
-        }
+    pub fn point_slot(&self, entity: Entity) -> Option<usize> {
+        self.point_slots.get(&entity.id()).copied()
     }
 
-    pub fn update_light_color(&self, queue: &wgpu::Queue, index: usize, data: cg::Vector3<f32>) {
-        queue.write_buffer(&self.point_buffer, (std::mem::size_of::<LightData>() * index + std::mem::size_of::<Align16<[cg::Matrix4<f32>; 6]>>() + std::mem::size_of::<Align16<cg::Vector3<f32>>>()) as u64, cast_slice(&[data]));
+    // Writes a light into its slot, taking a free one for a light seen for the first time,
+    // and creates or drops its shadow to match its settings.
+    pub fn set_point_light(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, entity: Entity, light: &PointLight, position: cg::Vector3<f32>) {
+        let slot = match self.point_slot(entity) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.free_point_slots.pop().unwrap_or(self.point_lights.len());
+                if slot == self.point_lights.len() {
+                    self.point_lights.push(LightData::empty());
+                }
+                self.point_slots.insert(entity.id(), slot);
+                slot
+            }
+        };
+
+        let previous = std::mem::replace(&mut self.point_lights[slot], LightData::new(light, position));
+        match (self.point_shadows.iter().position(|shadow| shadow.light == slot), light.shadows.enabled) {
+            (Some(i), true) => {
+                let data = &mut self.point_lights[slot];
+                data._tiles = previous._tiles;
+                data._shadow_index = previous._shadow_index;
+                let shadow = &mut self.point_shadows[i];
+                if shadow.resolution != light.shadows.resolution {
+                    shadow.resolution = light.shadows.resolution;
+                    shadow.tiles = None;
+                }
+            }
+            (Some(i), false) => {
+                self.point_shadows.remove(i);
+            }
+            (None, true) => {
+                let shadow = PointShadow::new(device, &self.resources.layouts, slot, light.shadows.resolution, &self.point_lights[slot]._projections);
+                self.point_shadows.push(shadow);
+            }
+            (None, false) => {}
+        }
+
+        if self.point_lights.len() > self.point_capacity {
+            self.grow_point_buffer(device, queue);
+        } else {
+            self.write_point_light(queue, slot);
+        }
     }
 
-    pub fn update_directional_data(&self, queue: &wgpu::Queue, index: usize, direction: cg::Vector3<f32>, color: [f32; 3]) {
-        let index = index + 1;
-        queue.write_buffer(&self.directional_buffer, (std::mem::size_of::<DirectionalData>()  * index) as u64, cast_slice(&[DirectionalData {
-            _direction: Align16(direction),
-            _color: Align16(color)
-        }]));
+    pub fn remove_point_light(&mut self, queue: &wgpu::Queue, id: Index) {
+        if let Some(slot) = self.point_slots.remove(&id) {
+            self.point_lights[slot] = LightData::empty();
+            self.point_shadows.retain(|shadow| shadow.light != slot);
+            self.free_point_slots.push(slot);
+            self.write_point_light(queue, slot);
+        }
     }
 
-    pub fn add_point_light(&mut self, device: &wgpu::Device, transform: &TransformComponent, light: &PointLight) {
-        let transform_data = transform.get_position();
-        let light_data = light.get_color();
+    // Reallocates the point shadow atlas once its lights no longer fit at their full resolution.
+    // Tiles are packed again on the next `update_point_shadows`.
+    pub fn fit_point_atlas(&mut self, device: &wgpu::Device) {
+        let requests: Vec<u32> = self.point_shadows.iter().map(|shadow| shadow.resolution).collect();
+        if ShadowAtlas::size_for(device, &requests, 6) <= self.point_atlas.size {
+            return;
+        }
 
-        self.point_lights.push(LightData {
-            _position: Align16(transform_data),
-            _color: Align16(light_data),
-            _projections: Align16([cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity(), cg::SquareMatrix::identity()])
-        });
+        self.point_atlas = ShadowAtlas::new(device, &requests, 6);
+        for shadow in &mut self.point_shadows {
+            shadow.tiles = None;
+        }
+        self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+    }
+
+    pub fn read_point_events(&mut self, lights: &ReadStorage<PointLight>) -> Vec<ComponentEvent> {
+        lights.channel().read(&mut self.point_reader).cloned().collect()
+    }
 
-        self.point_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+    fn grow_point_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.point_capacity = self.point_lights.len() * 2;
+        let mut contents = self.point_lights.clone();
+        contents.resize(self.point_capacity, LightData::empty());
+        self.resources.point_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("light_data_buffer"),
-            contents: cast_slice(&self.point_lights.clone().into_boxed_slice()),
+            contents: cast_slice(&contents.into_boxed_slice()),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
+        queue.write_buffer(&self.resources.point_count_buffer, 0, cast_slice(&[self.point_lights.len() as i32]));
+        for slot in 0..self.point_lights.len() {
+            self.write_point_light(queue, slot);
+        }
+        self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+    }
 
-        self.point_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("light_count_buffer"),
-            contents: cast_slice(&[self.point_lights.len() as i32]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    pub fn update_light_position(&mut self, queue: &wgpu::Queue, index: usize, position: cg::Vector3<f32>) {
+        let data = &mut self.point_lights[index];
+        data._position = position;
+        data._projections = calculate_point_light_projection(position, data._range);
+        self.write_point_light(queue, index);
+    }
 
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_light_layout(),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: self.point_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: self.point_count_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: self.directional_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: self.directional_count_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("light_bind_group"),
-        });
+    // Repacks the point shadow atlas so lights covering more of the screen get the bigger tiles.
+    pub fn update_point_shadows(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        let coverage: Vec<f32> = self.point_shadows.iter().map(|shadow| {
+            let light = &self.point_lights[shadow.light];
+            screen_coverage(camera, light._position, light._range)
+        }).collect();
+        let mut order: Vec<usize> = (0..self.point_shadows.len()).collect();
+        order.sort_by(|a, b| coverage[*b].total_cmp(&coverage[*a]));
+        let requests: Vec<u32> = order.iter().map(|&i| (self.point_shadows[i].resolution as f32 * coverage[i]).ceil() as u32).collect();
+
+        for (i, tiles) in order.into_iter().zip(self.point_atlas.allocate(&requests, 6)) {
+            if self.point_shadows[i].tiles == tiles {
+                continue;
+            }
+            let light = self.point_shadows[i].light;
+            self.point_lights[light].set_tiles(i, tiles.as_deref(), self.point_atlas.size);
+            self.point_shadows[i].tiles = tiles;
+            self.write_point_light(queue, light);
+        }
     }
 
-    pub fn remove_point_light(&mut self, device: &wgpu::Device, index: usize) {
-        self.point_lights.remove(index + 1);
+    fn write_point_light(&self, queue: &wgpu::Queue, index: usize) {
+        if index + 1 == self.point_lights.len() {
+            queue.write_buffer(&self.resources.point_count_buffer, 0, cast_slice(&[self.point_lights.len() as i32]));
+        }
+        let data = &self.point_lights[index];
+        queue.write_buffer(&self.resources.point_buffer, (std::mem::size_of::<LightData>() * index) as u64, cast_slice(&[data.clone()]));
+        if let Some(shadow) = self.point_shadows.iter().find(|shadow| shadow.light == index) {
+            for (projection, buffer) in data._projections.iter().zip(shadow.buffers.iter()) {
+                queue.write_buffer(buffer, 0, cast_slice(&[Align16(*projection)]));
+            }
+        }
+    }
 
-        self.point_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("light_data_buffer"),
-            contents: cast_slice(&self.point_lights.clone().into_boxed_slice()),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
+    pub fn directional_slot(&self, entity: Entity) -> Option<usize> {
+        self.directional_slots.get(&entity.id()).copied()
+    }
 
-        self.point_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("light_count_buffer"),
-            contents: cast_slice(&[self.point_lights.len() as i32]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    // Like `set_point_light`. Shadow textures are only recreated when the light starts or stops
+    // casting shadows or its cascade count or resolution changes.
+    pub fn set_directional_light(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, entity: Entity, light: &DirectionalLight) {
+        let slot = match self.directional_slot(entity) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.free_directional_slots.pop().unwrap_or(self.directional_lights.len());
+                if slot == self.directional_lights.len() {
+                    self.directional_lights.push(DirectionalData::empty());
+                }
+                self.directional_slots.insert(entity.id(), slot);
+                slot
+            }
+        };
 
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_light_layout(),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: self.point_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: self.point_count_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: self.directional_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: self.directional_count_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("light_bind_group"),
-        });
+        let existing = self.directional_shadows.iter().position(|shadow| shadow.light == slot);
+        let textures_changed = match (existing, light.shadows.enabled) {
+            (Some(i), true) => {
+                let shadow = &mut self.directional_shadows[i];
+                if shadow.settings.cascades != light.shadows.cascades || shadow.settings.resolution != light.shadows.resolution {
+                    *shadow = DirectionalShadow::new(device, &self.resources.layouts, slot, shadow.index, &light.shadows);
+                    true
+                } else {
+                    shadow.settings = light.shadows.clone();
+                    false
+                }
+            }
+            (Some(i), false) => {
+                self.directional_shadows.remove(i);
+                true
+            }
+            (None, true) => {
+                let free = (0..MAX_DIRECTIONAL_SHADOWS).find(|index| !self.directional_shadows.iter().any(|shadow| shadow.index == *index));
+                match free {
+                    Some(index) => {
+                        self.directional_shadows.push(DirectionalShadow::new(device, &self.resources.layouts, slot, index, &light.shadows));
+                        true
+                    }
+                    None => false,
+                }
+            }
+            (None, false) => false,
+        };
+
+        let shadow_index = self.directional_shadows.iter().find(|shadow| shadow.light == slot).map(|shadow| shadow.index);
+        let cascades = self.directional_lights[slot]._cascades;
+        self.directional_lights[slot] = DirectionalData::new(light, shadow_index);
+        self.directional_lights[slot]._cascades = cascades;
+
+        if self.directional_lights.len() > self.directional_capacity {
+            self.grow_directional_buffer(device, queue);
+        } else {
+            self.write_directional_light(queue, slot);
+            if textures_changed {
+                self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+            }
+        }
     }
 
-    pub fn add_directional_light(&mut self, device: &wgpu::Device, light: &DirectionalLight) {
-        self.directional_lights.push(DirectionalData {
-            _direction: Align16(light.direction),
-            _color: Align16(light.color)
-        });
+    pub fn remove_directional_light(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, id: Index) {
+        if let Some(slot) = self.directional_slots.remove(&id) {
+            self.directional_lights[slot] = DirectionalData::empty();
+            self.free_directional_slots.push(slot);
+            self.write_directional_light(queue, slot);
 
-        self.directional_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            if let Some(i) = self.directional_shadows.iter().position(|shadow| shadow.light == slot) {
+                self.directional_shadows.remove(i);
+                self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+            }
+        }
+    }
+
+    pub fn read_directional_events(&mut self, lights: &ReadStorage<DirectionalLight>) -> Vec<ComponentEvent> {
+        lights.channel().read(&mut self.directional_reader).cloned().collect()
+    }
+
+    fn grow_directional_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.directional_capacity = self.directional_lights.len() * 2;
+        let mut contents = self.directional_lights.clone();
+        contents.resize(self.directional_capacity, DirectionalData::empty());
+        self.resources.directional_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("light_data_buffer"),
-            contents: cast_slice(&self.directional_lights.clone().into_boxed_slice()),
+            contents: cast_slice(&contents.into_boxed_slice()),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
+        queue.write_buffer(&self.resources.directional_count_buffer, 0, cast_slice(&[self.directional_lights.len() as i32]));
+        self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+    }
 
-        self.directional_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("light_count_buffer"),
-            contents: cast_slice(&[self.directional_lights.len() as i32]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    fn write_directional_light(&self, queue: &wgpu::Queue, index: usize) {
+        if index + 1 == self.directional_lights.len() {
+            queue.write_buffer(&self.resources.directional_count_buffer, 0, cast_slice(&[self.directional_lights.len() as i32]));
+        }
+        queue.write_buffer(&self.resources.directional_buffer, (std::mem::size_of::<DirectionalData>() * index) as u64, cast_slice(&[self.directional_lights[index].clone()]));
+    }
 
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_light_layout(),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: self.point_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: self.point_count_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: self.directional_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: self.directional_count_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("light_bind_group"),
-        });
+    pub fn spot_slot(&self, entity: Entity) -> Option<usize> {
+        self.spot_slots.get(&entity.id()).copied()
     }
 
-    pub fn remove_directional_light(&mut self, device: &wgpu::Device, index: usize) {
-        self.directional_lights.remove(index + 1);
+    // Like `set_point_light`. Shadowed lights each keep a layer of the spot shadow texture, which
+    // grows when it runs out of them, up to MAX_SPOT_SHADOWS.
+    pub fn set_spot_light(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, entity: Entity, light: &SpotLight, transform: &TransformComponent) {
+        let slot = match self.spot_slot(entity) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.free_spot_slots.pop().unwrap_or(self.spot_lights.len());
+                if slot == self.spot_lights.len() {
+                    self.spot_lights.push(SpotData::empty());
+                }
+                self.spot_slots.insert(entity.id(), slot);
+                slot
+            }
+        };
 
-        self.directional_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("light_data_buffer"),
-            contents: cast_slice(&self.directional_lights.clone().into_boxed_slice()),
+        let existing = self.spot_shadows.iter().position(|shadow| shadow.light == slot);
+        match (existing, light.shadows) {
+            (Some(i), false) => {
+                self.spot_shadows.remove(i);
+            }
+            (None, true) => {
+                let free = (0..MAX_SPOT_SHADOWS).find(|layer| !self.spot_shadows.iter().any(|shadow| shadow.layer == *layer));
+                if let Some(layer) = free {
+                    if layer as u32 >= self.resources.spot_shadow_texture.depth_or_array_layers() {
+                        self.grow_spot_shadows(device, (layer * 2).clamp(1, MAX_SPOT_SHADOWS) as u32);
+                    }
+                    let shadow = SpotShadow::new(device, &self.resources.layouts, &self.resources.spot_shadow_texture, slot, layer, cg::SquareMatrix::identity());
+                    self.spot_shadows.push(shadow);
+                }
+            }
+            _ => {}
+        }
+
+        let shadow_index = self.spot_shadows.iter().find(|shadow| shadow.light == slot).map(|shadow| shadow.layer);
+        self.spot_lights[slot] = SpotData::new(light, transform, shadow_index);
+
+        if self.spot_lights.len() > self.spot_capacity {
+            self.grow_spot_buffer(device, queue);
+        } else {
+            self.write_spot_light(queue, slot);
+        }
+    }
+
+    pub fn remove_spot_light(&mut self, queue: &wgpu::Queue, id: Index) {
+        if let Some(slot) = self.spot_slots.remove(&id) {
+            self.spot_lights[slot] = SpotData::empty();
+            self.spot_shadows.retain(|shadow| shadow.light != slot);
+            self.free_spot_slots.push(slot);
+            self.write_spot_light(queue, slot);
+        }
+    }
+
+    // Moves a spot light along with its transform, keeping its shadow layer.
+    pub fn update_spot_light(&mut self, queue: &wgpu::Queue, index: usize, light: &SpotLight, transform: &TransformComponent) {
+        let shadow_index = self.spot_lights[index]._shadow_index;
+        self.spot_lights[index] = SpotData::new(light, transform, usize::try_from(shadow_index).ok());
+        self.write_spot_light(queue, index);
+    }
+
+    pub fn read_spot_events(&mut self, lights: &ReadStorage<SpotLight>) -> Vec<ComponentEvent> {
+        lights.channel().read(&mut self.spot_reader).cloned().collect()
+    }
+
+    fn grow_spot_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.spot_capacity = self.spot_lights.len() * 2;
+        let mut contents = self.spot_lights.clone();
+        contents.resize(self.spot_capacity, SpotData::empty());
+        self.resources.spot_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("spot_light_buffer"),
+            contents: cast_slice(&contents.into_boxed_slice()),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
+        queue.write_buffer(&self.resources.spot_count_buffer, 0, cast_slice(&[self.spot_lights.len() as i32]));
+        for slot in 0..self.spot_lights.len() {
+            self.write_spot_light(queue, slot);
+        }
+        self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+    }
 
-        self.directional_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("light_count_buffer"),
-            contents: cast_slice(&[self.directional_lights.len() as i32]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    // Shadow maps are drawn again every frame, so the old layers' contents aren't copied over.
+    fn grow_spot_shadows(&mut self, device: &wgpu::Device, layers: u32) {
+        self.resources.spot_shadow_texture = create_spot_shadow_texture(device, layers);
+        self.resources.spot_shadow_view = spot_shadow_array_view(&self.resources.spot_shadow_texture);
+        for shadow in &mut self.spot_shadows {
+            shadow.view = spot_shadow_layer_view(&self.resources.spot_shadow_texture, shadow.layer);
+        }
+        self.bind_group = create_bind_group(device, &self.resources, &self.point_atlas, &self.directional_shadows);
+    }
 
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_light_layout(),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: self.point_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: self.point_count_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: self.directional_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: self.directional_count_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("light_bind_group"),
-        });
+    fn write_spot_light(&self, queue: &wgpu::Queue, index: usize) {
+        if index + 1 == self.spot_lights.len() {
+            queue.write_buffer(&self.resources.spot_count_buffer, 0, cast_slice(&[self.spot_lights.len() as i32]));
+        }
+        let data = &self.spot_lights[index];
+        queue.write_buffer(&self.resources.spot_buffer, (std::mem::size_of::<SpotData>() * index) as u64, cast_slice(&[data.clone()]));
+        if let Some(shadow) = self.spot_shadows.iter().find(|shadow| shadow.light == index) {
+            queue.write_buffer(&shadow.buffer, 0, cast_slice(&[Align16(data._projection)]));
+        }
     }
+
+    // Splits the camera's view range into cascades and fits an orthographic light projection around each slice.
+    pub fn update_cascades(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        for shadow in &self.directional_shadows {
+            let light = &mut self.directional_lights[shadow.light];
+            let direction = light._direction.0;
+            if direction.magnitude2() == 0.0 {
+                continue;
+            }
+
+            let cascades = cascade_matrices(camera, direction.normalize(), &shadow.settings);
+            for (i, (matrix, buffer)) in cascades.iter().zip(shadow.buffers.iter()).enumerate() {
+                light._cascades[i] = *matrix;
+                queue.write_buffer(buffer, 0, cast_slice(&[Align16(*matrix)]));
+            }
+            let offset = std::mem::size_of::<Align16<cg::Vector3<f32>>>() * 2;
+            queue.write_buffer(&self.resources.directional_buffer, (std::mem::size_of::<DirectionalData>() * shadow.light + offset) as u64, cast_slice(&light._cascades));
+        }
+    }
+}
+
+fn create_bind_group(device: &wgpu::Device, resources: &LightResources, point_atlas: &ShadowAtlas, directional_shadows: &[DirectionalShadow]) -> wgpu::BindGroup {
+    let cascade_views: Vec<&wgpu::TextureView> = (0..MAX_DIRECTIONAL_SHADOWS)
+        .map(|i| directional_shadows.iter().find(|shadow| shadow.index == i).map_or(&resources.cascade_fallback, |shadow| &shadow.array_view))
+        .collect();
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &resources.layouts.light,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: resources.point_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: resources.point_count_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: resources.directional_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: resources.directional_count_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&point_atlas.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureViewArray(&cascade_views[..]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Sampler(&resources.shadow_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(&resources.spot_shadow_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: resources.spot_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: resources.spot_count_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::TextureView(&resources.environment.irradiance_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: wgpu::BindingResource::TextureView(&resources.environment.prefiltered_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: wgpu::BindingResource::TextureView(&resources.environment.brdf_lut_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: wgpu::BindingResource::Sampler(&resources.environment.sampler),
+            },
+        ],
+        label: Some("light_bind_group"),
+    })
+}
+
+// Practical split scheme: blends logarithmic and uniform splits of [near, far] by `lambda`.
+fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count).map(|i| {
+        let fraction = i as f32 / count as f32;
+        let log = near * (far / near).powf(fraction);
+        let uniform = near + (far - near) * fraction;
+        lambda * log + (1.0 - lambda) * uniform
+    }).collect()
+}
+
+fn cascade_matrices(camera: &Camera, direction: cg::Vector3<f32>, settings: &CascadeSettings) -> Vec<cg::Matrix4<f32>> {
+    let projection = &camera.projection;
+    let count = settings.cascades.clamp(1, MAX_CASCADES as u32) as usize;
+    let near = projection.znear();
+    let far = projection.zfar().min(near + settings.distance.max(0.01));
+
+    let forward = camera.forward();
+    let right = forward.cross(cg::Vector3::unit_y()).normalize();
+    let up = right.cross(forward);
+    let half_extents = |distance: f32| {
+        let half_height = match projection.kind {
+            ProjectionKind::Perspective => distance * (projection.fovy().0 * 0.5).tan(),
+            ProjectionKind::Orthographic => projection.ortho_size(),
+        };
+        (half_height * projection.aspect, half_height)
+    };
+
+    let light_up = if direction.y.abs() > 0.99 { cg::Vector3::unit_z() } else { cg::Vector3::unit_y() };
+    let light_view = cg::Matrix4::look_to_rh(cg::Point3::origin(), direction, light_up);
+
+    let mut start = near;
+    cascade_splits(near, far, count, settings.split_lambda).into_iter().map(|end| {
+        let mut corners = Vec::with_capacity(8);
+        for distance in [start, end] {
+            let (half_width, half_height) = half_extents(distance);
+            let center = camera.position.to_vec() + forward * distance;
+            for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                corners.push(center + right * half_width * x + up * half_height * y);
+            }
+        }
+        start = end;
+
+        // A bounding sphere keeps the projection size constant as the camera turns, and snapping its
+        // center to whole texels keeps shadow edges from shimmering as it moves.
+        let center = corners.iter().fold(cg::vec3(0.0, 0.0, 0.0), |sum, corner| sum + corner) / corners.len() as f32;
+        let radius = corners.iter().map(|corner| (corner - center).magnitude()).fold(0.0f32, f32::max).ceil();
+        let texel = radius * 2.0 / settings.resolution as f32;
+        let center = (light_view * center.extend(1.0)).truncate();
+        let x = (center.x / texel).floor() * texel;
+        let y = (center.y / texel).floor() * texel;
+
+        let ortho = cg::ortho(x - radius, x + radius, y - radius, y + radius, -center.z - radius - CASTER_DISTANCE, -center.z + radius);
+        OPENGL_TO_WGPU_MATRIX * ortho * light_view
+    }).collect()
+}
+
+fn create_spot_shadow_texture(device: &wgpu::Device, layers: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("spot shadow"),
+        size: wgpu::Extent3d {
+            width: SPOT_SHADOW_RESOLUTION,
+            height: SPOT_SHADOW_RESOLUTION,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Depth32Float,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn spot_shadow_array_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("spot shadows"),
+        format: Some(wgpu::TextureFormat::Depth32Float),
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        aspect: wgpu::TextureAspect::DepthOnly,
+        ..Default::default()
+    })
+}
+
+fn spot_shadow_layer_view(texture: &wgpu::Texture, layer: usize) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some(format!("spot shadow view {}", layer).as_str()),
+        format: Some(wgpu::TextureFormat::Depth32Float),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        aspect: wgpu::TextureAspect::DepthOnly,
+        base_mip_level: 0,
+        mip_level_count: None,
+        base_array_layer: layer as u32,
+        array_layer_count: std::num::NonZeroU32::new(1),
+    })
+}
+
+fn calculate_spot_light_projection(position: cg::Vector3<f32>, direction: cg::Vector3<f32>, outer_angle: f32, range: f32) -> cg::Matrix4<f32> {
+    let up = if direction.y.abs() > 0.99 { cg::Vector3::unit_z() } else { cg::Vector3::unit_y() };
+    let projection = cg::perspective(cg::Deg(outer_angle * 2.0), 1.0, SPOT_NEAR, range.max(SPOT_NEAR * 2.0));
+    OPENGL_TO_WGPU_MATRIX * projection * cg::Matrix4::look_to_rh(cg::Point3::from_vec(position), direction, up)
+}
+
+// Fraction of the screen height a light's range spans, 1 once the camera is inside it.
+fn screen_coverage(camera: &Camera, position: cg::Vector3<f32>, range: f32) -> f32 {
+    let projection = &camera.projection;
+    let half_height = match projection.kind {
+        ProjectionKind::Perspective => (position - camera.position.to_vec()).magnitude() * (projection.fovy().0 * 0.5).tan(),
+        ProjectionKind::Orthographic => projection.ortho_size(),
+    };
+    (range / half_height.max(f32::EPSILON)).min(1.0)
 }
 
-fn calculate_point_light_projection(position: cg::Vector3<f32>) -> [cg::Matrix4<f32>; 6] {
-    let projection = cg::perspective(cg::Deg(90.0), 1.0, 0.1, 100.0);
+// The light's range is the far plane, so nothing beyond where it stops lighting casts a shadow.
+fn calculate_point_light_projection(position: cg::Vector3<f32>, range: f32) -> [cg::Matrix4<f32>; 6] {
+    let projection = cg::perspective(cg::Deg(90.0), 1.0, POINT_NEAR, range.max(POINT_NEAR * 2.0));
     let centers = vec![position + cg::vec3(1.0, 0.0, 0.0), position + cg::vec3(-1.0, 0.0, 0.0), position + cg::vec3(0.0, 1.0, 0.0), position + cg::vec3(0.0, -1.0, 0.0), position + cg::vec3(0.0, 0.0, 1.0), position + cg::vec3(0.0, 0.0, -1.0)];
     let up_vectors = vec![
         cg::vec3(0.0, -1.0, 0.0),
@@ -0,0 +1,75 @@
+use specs::{Entities, ReadStorage, System, storage::ComponentEvent};
+
+use crate::engine::components::{light::{PointLight, SpotLight, DirectionalLight}, transform::TransformComponent};
+
+use super::light_manager::LightManager;
+
+// Applies lights added, edited or removed since the last run to their slots in the light
+// manager, so the editor goes through the same path as loading a scene instead of rebuilding it.
+pub struct LightSystem<'a> {
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    manager: &'a mut LightManager,
+}
+
+impl<'a> LightSystem<'a> {
+    pub fn new(device: &'a wgpu::Device, queue: &'a wgpu::Queue, manager: &'a mut LightManager) -> Self {
+        Self {
+            device,
+            queue,
+            manager,
+        }
+    }
+}
+
+impl<'a, 'b> System<'a> for LightSystem<'b> {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, PointLight>,
+        ReadStorage<'a, SpotLight>,
+        ReadStorage<'a, DirectionalLight>,
+        ReadStorage<'a, TransformComponent>,
+    );
+
+    fn run(&mut self, (entities, lights, spot_lights, directional_lights, transforms): Self::SystemData) {
+        for event in self.manager.read_point_events(&lights) {
+            match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) => {
+                    let entity = entities.entity(id);
+                    match (lights.get(entity), transforms.get(entity)) {
+                        (Some(light), Some(transform)) => self.manager.set_point_light(self.device, self.queue, entity, light, transform.get_world_position()),
+                        _ => self.manager.remove_point_light(self.queue, id),
+                    }
+                }
+                ComponentEvent::Removed(id) => self.manager.remove_point_light(self.queue, id),
+            }
+        }
+        self.manager.fit_point_atlas(self.device);
+
+        for event in self.manager.read_spot_events(&spot_lights) {
+            match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) => {
+                    let entity = entities.entity(id);
+                    match (spot_lights.get(entity), transforms.get(entity)) {
+                        (Some(light), Some(transform)) => self.manager.set_spot_light(self.device, self.queue, entity, light, transform),
+                        _ => self.manager.remove_spot_light(self.queue, id),
+                    }
+                }
+                ComponentEvent::Removed(id) => self.manager.remove_spot_light(self.queue, id),
+            }
+        }
+
+        for event in self.manager.read_directional_events(&directional_lights) {
+            match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) => {
+                    let entity = entities.entity(id);
+                    match directional_lights.get(entity) {
+                        Some(light) => self.manager.set_directional_light(self.device, self.queue, entity, light),
+                        None => self.manager.remove_directional_light(self.device, self.queue, id),
+                    }
+                }
+                ComponentEvent::Removed(id) => self.manager.remove_directional_light(self.device, self.queue, id),
+            }
+        }
+    }
+}
@@ -1,172 +1,588 @@
+pub mod environment;
+pub mod format;
 pub mod light_manager;
+pub mod light_system;
+pub mod shadow_atlas;
 pub mod skybox;
+pub mod transform_system;
 
-use std::{path::PathBuf, collections::HashMap};
+use std::{path::PathBuf, collections::{HashMap, BTreeMap}, sync::Arc};
 
-use serde::Deserialize;
-use specs::{World, WorldExt, Join, Builder};
+use anyhow::{anyhow, bail, Result};
+use cg::SquareMatrix;
+use serde_yaml::Value;
+use specs::{World, WorldExt, Join, Builder, Entity, RunNow};
+use uuid::Uuid;
 
-use crate::util::res;
 
-use self::{light_manager::LightManager, skybox::Skybox};
+use self::{light_manager::LightManager, light_system::LightSystem, skybox::{Skybox, DEFAULT_SKYBOX}, format::{SceneFile, EntityData, UnknownComponents}, transform_system::TransformSystem};
 
 use super::{
     components::{
+        id::Id,
         name::Name, 
-        transform::{Transform, DeserializedTransform, TransformComponent}, 
-        material::MaterialComponent, 
-        mesh::Mesh, 
-        light::{PointLight, DirectionalLight},
+        transform::{Transform, TransformComponent}, 
+        light::{PointLight, SpotLight},
+        mesh::Mesh,
+        camera::CameraComponent,
+        registry::{ComponentRegistry, SaveContext, LoadContext},
     }, 
     registry::Registry, 
     camera::Camera, 
+    asset::model::Bounds,
 };
 
 pub struct Scene {
     pub path: PathBuf,
     pub world: World,
+    pub components: Arc<ComponentRegistry>,
     pub light_manager: LightManager,
-    pub skybox: Option<Skybox>,
+    pub skybox: Skybox,
+    skybox_asset: Option<usize>,
+    // Order of the top level entities; children are ordered by their parent's `children`.
+    root_order: Vec<Entity>,
 }
 
 impl Scene {
-    pub fn new(path: PathBuf, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera) -> Self {
-        let world = load_world(&path, registry, device);
-        let light_manager = LightManager::new(device, &world);
-        let skybox = Skybox::new(device, queue, camera, &res("textures/skyboxes/starfield/"));
+    pub fn new(path: PathBuf, components: Arc<ComponentRegistry>, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera) -> Result<Self> {
+        let (world, skybox_asset) = load_world(&path, &components, registry, device)?;
+        let skybox = load_skybox_or_default(skybox_asset, registry, device, queue, camera)?;
+        let light_manager = LightManager::new(device, registry.layouts.clone(), &world, skybox.environment.clone());
 
-        Self {
+        let mut scene = Self {
             path,
             world,
+            components,
             light_manager,
-            skybox: Some(skybox),
-        }
+            skybox,
+            skybox_asset,
+            root_order: Vec::new(),
+        };
+        scene.root_order = scene.roots();
+        scene.update(queue);
+        Ok(scene)
     }
 
-    pub fn save_scene(&mut self) {
-        let entities = self.world.entities();
-        let names = self.world.read_storage::<Name>();
+    // Propagates dirty transforms down the hierarchy and moves point and spot lights along with them.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let mut system = TransformSystem::new(queue);
+        system.run_now(&self.world);
+
         let transforms = self.world.read_storage::<TransformComponent>();
-        let materials = self.world.read_storage::<MaterialComponent>();
-        let meshes = self.world.read_storage::<Mesh>();
-        let point_lights = self.world.read_storage::<PointLight>();
-        let directional_lights = self.world.read_storage::<DirectionalLight>();
+        let spot_lights = self.world.read_storage::<SpotLight>();
+        for entity in system.changed {
+            if let Some(index) = self.light_manager.point_slot(entity) {
+                self.light_manager.update_light_position(queue, index, transforms.get(entity).unwrap().get_world_position());
+            }
+            if let (Some(index), Some(light)) = (self.light_manager.spot_slot(entity), spot_lights.get(entity)) {
+                self.light_manager.update_spot_light(queue, index, light, transforms.get(entity).unwrap());
+            }
+        }
+    }
+
+    // Brings the light manager up to date with lights added, removed or edited since the last call.
+    pub fn update_lights(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        LightSystem::new(device, queue, &mut self.light_manager).run_now(&self.world);
+    }
 
-        
-        let mut s_names = HashMap::new();
-        let mut s_transforms = HashMap::new();
-        let mut s_materials = HashMap::new();
-        let mut s_meshes = HashMap::new();
-        let mut s_point_lights = HashMap::new();
-        let mut s_directional_lights = HashMap::new();
+    pub fn save_scene(&mut self) -> Result<()> {
+        self.assign_missing_ids();
 
-        for entity in entities.join() {
-            if let Some(name) = names.get(entity) {
-                s_names.insert(entity.id(), name.clone());
+        // Roots are written in hierarchy order so it survives a reload, which creates entities in file order.
+        let mut entities: Vec<Entity> = self.roots().into_iter().flat_map(|root| self.hierarchy(root)).collect();
+        for entity in self.world.entities().join() {
+            if !entities.contains(&entity) {
+                entities.push(entity);
             }
-            if let Some(transform) = transforms.get(entity) {
-                s_transforms.insert(entity.id(), transform.data.clone());
+        }
+        let mut file = self.scene_file(&entities)?;
+        file.skybox = self.skybox_asset;
+
+        std::fs::write(self.path.clone(), file.to_yaml()?)?;
+        Ok(())
+    } 
+
+    pub fn load_scene(&mut self, path: &PathBuf, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera) -> Result<()> {
+        let (world, skybox_asset) = load_world(path, &self.components, registry, device)?;
+        self.world = world;
+    
+        self.light_manager.rebuild(device, &self.world);
+        if skybox_asset != self.skybox_asset {
+            match load_skybox_or_default(skybox_asset, registry, device, queue, camera) {
+                Ok(skybox) => {
+                    self.light_manager.set_environment(device, skybox.environment.clone());
+                    self.skybox = skybox;
+                }
+                Err(e) => println!("{}", e),
             }
-            if let Some(material) = materials.get(entity) {
-                s_materials.insert(entity.id(), material.clone());
+        }
+        // Kept even when it couldn't be loaded, so saving doesn't drop it or write the previous scene's.
+        self.skybox_asset = skybox_asset;
+        self.path = path.clone();
+        self.root_order = Vec::new();
+        self.root_order = self.roots();
+        Ok(())
+    }
+
+    pub fn skybox_asset(&self) -> Option<usize> {
+        self.skybox_asset
+    }
+
+    // Switches to the texture with registry id `asset`, or the default skybox for None. The previous
+    // skybox stays when the new one can't be loaded.
+    pub fn set_skybox(&mut self, asset: Option<usize>, registry: &Registry, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera) -> Result<()> {
+        if asset == self.skybox_asset {
+            return Ok(());
+        }
+
+        let skybox = load_skybox(asset, registry, device, queue, camera)?;
+        self.light_manager.set_environment(device, skybox.environment.clone());
+        self.skybox = skybox;
+        self.skybox_asset = asset;
+        Ok(())
+    }
+
+    pub fn add_component(&mut self, name: &str, entity: Entity, device: &wgpu::Device, registry: &mut Registry) {
+        if let Some(component) = self.components.clone().get(name) {
+            component.add(self, entity, device, registry);
+        }
+    }
+
+    pub fn remove_component(&mut self, name: &str, entity: Entity, device: &wgpu::Device) {
+        if let Some(component) = self.components.clone().get(name) {
+            component.remove(self, entity, device);
+        }
+    }
+
+    fn assign_missing_ids(&mut self) {
+        let missing: Vec<Entity> = {
+            let ids = self.world.read_storage::<Id>();
+            (&self.world.entities(), !&ids).join().map(|(entity, _)| entity).collect()
+        };
+
+        let mut ids = self.world.write_storage::<Id>();
+        for entity in missing {
+            ids.insert(entity, Id::new()).unwrap();
+        }
+    }
+
+    pub fn create_entity(&mut self, device: &wgpu::Device, registry: &Registry) -> Entity {
+        let entity = self.world.create_entity().with(Id::new()).with(Name::new("Object")).with(TransformComponent::new(Transform::default(), device, &registry.layouts)).build();
+        self.root_order.push(entity);
+        entity
+    }
+
+    // Top level entities in display order.
+    pub fn roots(&self) -> Vec<Entity> {
+        let transforms = self.world.read_storage::<TransformComponent>();
+        let is_root = |entity: Entity| transforms.get(entity).map_or(true, |transform| transform.data.parent.is_none());
+
+        let mut roots: Vec<Entity> = self.root_order.iter().copied().filter(|entity| self.world.is_alive(*entity) && is_root(*entity)).collect();
+        for entity in self.world.entities().join() {
+            if is_root(entity) && !roots.contains(&entity) {
+                roots.push(entity);
             }
-            if let Some(mesh) = meshes.get(entity) {
-                s_meshes.insert(entity.id(), mesh.clone());
+        }
+        roots
+    }
+
+    // The first camera flagged as main in hierarchy order, falling back to the first camera.
+    pub fn main_camera(&self) -> Option<Entity> {
+        let cameras = self.world.read_storage::<CameraComponent>();
+        let entities: Vec<Entity> = self.roots().into_iter().flat_map(|root| self.hierarchy(root)).filter(|entity| cameras.contains(*entity)).collect();
+        entities.iter().copied().find(|entity| cameras.get(*entity).unwrap().main).or(entities.first().copied())
+    }
+
+    pub fn set_root_order(&mut self, roots: Vec<Entity>) {
+        self.root_order = roots;
+    }
+
+    // Moves `entity` under `parent` (or to the top level) at `index` among its new siblings,
+    // keeping its world transform. Returns false if the move would create a cycle.
+    pub fn move_entity(&mut self, entity: Entity, parent: Option<Entity>, index: usize, queue: &wgpu::Queue) -> bool {
+        let old_parent = match self.world.read_storage::<TransformComponent>().get(entity) {
+            Some(transform) => transform.data.parent,
+            None if parent.is_none() => None,
+            None => return false,
+        };
+
+        let index = match self.siblings(parent).iter().position(|sibling| *sibling == entity) {
+            Some(position) if position < index => index - 1,
+            _ => index,
+        };
+
+        if old_parent != parent {
+            if !self.set_parent(entity, parent, queue) {
+                return false;
             }
-            if let Some(light) = point_lights.get(entity) {
-                s_point_lights.insert(entity.id(), light.clone());
+        }
+
+        let mut siblings = self.siblings(parent);
+        siblings.retain(|sibling| *sibling != entity);
+        siblings.insert(index.min(siblings.len()), entity);
+        match parent {
+            Some(parent) => self.world.write_storage::<TransformComponent>().get_mut(parent).unwrap().data.children = siblings,
+            None => self.root_order = siblings,
+        }
+        true
+    }
+
+    fn siblings(&self, parent: Option<Entity>) -> Vec<Entity> {
+        match parent {
+            Some(parent) => self.world.read_storage::<TransformComponent>().get(parent).map(|transform| transform.data.children.clone()).unwrap_or_default(),
+            None => self.roots(),
+        }
+    }
+
+    pub fn entity_id(&self, entity: Entity) -> Option<Uuid> {
+        self.world.read_storage::<Id>().get(entity).map(|id| id.0)
+    }
+
+    pub fn find_entity(&self, id: &Uuid) -> Option<Entity> {
+        let ids = self.world.read_storage::<Id>();
+        (&self.world.entities(), &ids).join().find(|(_, entity_id)| entity_id.0 == *id).map(|(entity, _)| entity)
+    }
+
+    pub fn component_value(&self, name: &str, entity: Entity) -> Result<Option<Value>> {
+        let component = self.components.get(name).ok_or_else(|| anyhow!("unknown component {}", name))?;
+        let context = SaveContext::new(&self.world);
+        component.save(&self.world, entity, &context)
+    }
+
+    pub fn set_component_value(&mut self, name: &str, entity: Entity, value: Option<Value>, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        let component = *self.components.get(name).ok_or_else(|| anyhow!("unknown component {}", name))?;
+        match value {
+            Some(value) => component.set(self, entity, value, registry, device, queue),
+            None => {
+                component.remove(self, entity, device);
+                Ok(())
             }
-            if let Some(light) = directional_lights.get(entity) {
-                s_directional_lights.insert(entity.id(), light.clone());
+        }
+    }
+
+    pub fn delete_entity(&mut self, entity: Entity, device: &wgpu::Device) {
+        self.detach(entity);
+
+        let components = self.components.clone();
+        for entity in self.hierarchy(entity).into_iter().rev() {
+            for component in components.iter() {
+                component.remove(self, entity, device);
             }
+            self.world.delete_entity(entity).unwrap();
         }
+    }
 
-        let yaml_names = serde_yaml::to_string(&s_names).unwrap();
-        let yaml_transforms = serde_yaml::to_string(&s_transforms).unwrap();
-        let yaml_materials = serde_yaml::to_string(&s_materials).unwrap();
-        let yaml_meshes = serde_yaml::to_string(&s_meshes).unwrap();
-        let yaml_point_lights = serde_yaml::to_string(&s_point_lights).unwrap();
-        let yaml_directional_lights = serde_yaml::to_string(&s_directional_lights).unwrap();
+    pub fn copy_entity(&mut self, entity: Entity) -> Result<String> {
+        self.copy_entities(&[entity])
+    }
 
-        let yaml = format!(
-            "# Names\n{}\n\n# Transforms\n{}\n\n# Materials\n{}\n\n# Meshes\n{}\n\n# Point Lights\n{}\n\n# Directional Lights\n{}",
-            yaml_names, yaml_transforms, yaml_materials, yaml_meshes, yaml_point_lights, yaml_directional_lights,
-        );
-        
-        std::fs::write(self.path.clone(), yaml).unwrap();
-    } 
+    pub fn copy_entities(&mut self, roots: &[Entity]) -> Result<String> {
+        self.assign_missing_ids();
+        let entities: Vec<Entity> = roots.iter().flat_map(|root| self.hierarchy(*root)).collect();
+        self.scene_file(&entities)?.to_yaml()
+    }
 
-    pub fn load_scene(&mut self, path: &PathBuf, registry: &mut Registry, device: &wgpu::Device) {
-        self.world = load_world(path, registry, device);
-    
-        self.light_manager = LightManager::new(device, &self.world);
-        self.path = path.clone();
+    pub fn paste_entities(&mut self, yaml: &str, parent: Option<Entity>, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<Entity>> {
+        self.insert_entities(yaml, parent, true, registry, device, queue)
     }
 
-    pub fn create_entity(&mut self, device: &wgpu::Device) {
-        self.world.create_entity().with(Name::new("Object")).with(TransformComponent::new(Transform::default(), device)).build();
+    // Like `paste_entities` but keeps the ids stored in the yaml, used to bring back deleted entities.
+    pub fn restore_entities(&mut self, yaml: &str, parent: Option<Entity>, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<Entity>> {
+        self.insert_entities(yaml, parent, false, registry, device, queue)
     }
-}
 
-fn load_world(path: &PathBuf, registry: &mut Registry, device: &wgpu::Device) -> World {
-    let yaml = std::fs::read_to_string(path).unwrap();
-    if yaml == "" {
-        let mut world = specs::World::new();
-        register_components(&mut world);
+    fn insert_entities(&mut self, yaml: &str, parent: Option<Entity>, fresh_ids: bool, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<Entity>> {
+        let scene = SceneFile::parse(yaml)?;
+        let components = self.components.clone();
+        let entities = load_entities(&mut self.world, &components, scene.entities, registry, device, fresh_ids)?;
+
+        let roots: Vec<Entity> = {
+            let transforms = self.world.read_storage::<TransformComponent>();
+            entities.iter().copied().filter(|entity| transforms.get(*entity).map_or(true, |transform| transform.data.parent.is_none())).collect()
+        };
 
-        world.create_entity().with(TransformComponent::new(Transform::default(), device)).with(Name::new("Light")).with(PointLight::new([0.0, 0.0, 0.0])).build();
-        return world;
+        if let Some(parent) = parent {
+            let mut transforms = self.world.write_storage::<TransformComponent>();
+            for root in &roots {
+                if let Some(transform) = transforms.get_mut(*root) {
+                    transform.data.parent = Some(parent);
+                    transforms.get_mut(parent).unwrap().data.children.push(*root);
+                }
+            }
+        }
+
+        for root in &roots {
+            self.update_transform(*root, queue);
+        }
+        if parent.is_none() {
+            self.root_order.extend(roots.iter().copied());
+        }
+
+        for entity in &entities {
+            for component in components.iter() {
+                if component.has(&self.world, *entity) {
+                    component.on_add(self, *entity, device);
+                }
+            }
+        }
+
+        Ok(roots)
     }
-    let sections: Vec<&str> = yaml.split("\n\n").collect();
 
-    let s_names: HashMap<u32, Name> = serde_yaml::from_str(sections[0]).unwrap();
-    let s_transforms: HashMap<u32, DeserializedTransform> = serde_yaml::from_str(sections[1]).unwrap();
-    let s_materials: HashMap<u32, DeserializedId> = serde_yaml::from_str(sections[2]).unwrap();
-    let s_meshes: HashMap<u32, DeserializedId> = serde_yaml::from_str(sections[3]).unwrap();
-    let s_point_lights: HashMap<u32, PointLight> = serde_yaml::from_str(sections[4]).unwrap();
-    let s_directional_lights: HashMap<u32, DirectionalLight> = serde_yaml::from_str(sections[5]).unwrap();
+    pub fn duplicate_entity(&mut self, entity: Entity, registry: &mut Registry, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<Entity>> {
+        let yaml = self.copy_entity(entity)?;
+        let parent = self.world.read_storage::<TransformComponent>().get(entity).and_then(|transform| transform.data.parent);
+        self.paste_entities(&yaml, parent, registry, device, queue)
+    }
 
-    let mut world = specs::World::new();
-    register_components(&mut world);
+    // The entity followed by all of its descendants, parents before children.
+    pub fn hierarchy(&self, entity: Entity) -> Vec<Entity> {
+        let transforms = self.world.read_storage::<TransformComponent>();
+        let mut entities = vec![entity];
+        let mut i = 0;
+        while i < entities.len() {
+            if let Some(transform) = transforms.get(entities[i]) {
+                let children: Vec<Entity> = transform.data.children.iter().copied().filter(|child| !entities.contains(child)).collect();
+                entities.extend(children);
+            }
+            i += 1;
+        }
+        entities
+    }
+
+    // Nearest entity whose mesh bounds are hit by the world space ray.
+    pub fn pick(&self, origin: cg::Vector3<f32>, direction: cg::Vector3<f32>) -> Option<Entity> {
+        let meshes = self.world.read_storage::<Mesh>();
+        let transforms = self.world.read_storage::<TransformComponent>();
+
+        let mut nearest: Option<(f32, Entity)> = None;
+        for (entity, mesh, transform) in (&self.world.entities(), &meshes, &transforms).join() {
+            let inverse = match transform.get_matrix().invert() {
+                Some(inverse) => inverse,
+                None => continue,
+            };
+            let local_origin = (inverse * origin.extend(1.0)).truncate();
+            let local_direction = (inverse * direction.extend(0.0)).truncate();
+
+            for submesh in mesh.mesh.iter() {
+                if let Some(t) = submesh.bounds.intersect(local_origin, local_direction) {
+                    if nearest.map_or(true, |(nearest, _)| t < nearest) {
+                        nearest = Some((t, entity));
+                    }
+                }
+            }
+        }
+        nearest.map(|(_, entity)| entity)
+    }
 
-    for id in 0..s_names.len() as u32 {
-        let mut entity = world.create_entity();
+    // World space box around the mesh bounds of the entities; entities without a mesh add their position.
+    pub fn world_bounds(&self, entities: &[Entity]) -> Option<Bounds> {
+        let meshes = self.world.read_storage::<Mesh>();
+        let transforms = self.world.read_storage::<TransformComponent>();
 
-        if let Some(name) = s_names.get(&id) {
-            entity = entity.with(name.clone());
+        let mut points = Vec::new();
+        for entity in entities {
+            let transform = match transforms.get(*entity) {
+                Some(transform) => transform,
+                None => continue,
+            };
+            let matrix = transform.get_matrix();
+            match meshes.get(*entity) {
+                Some(mesh) => {
+                    for submesh in mesh.mesh.iter() {
+                        points.extend(submesh.bounds.corners().iter().map(|corner| (matrix * corner.extend(1.0)).truncate()));
+                    }
+                }
+                None => points.push(transform.get_world_position()),
+            }
         }
-        if let Some(transform) = s_transforms.get(&id) {
-            entity = entity.with(TransformComponent::new(Transform::new(transform.position, transform.rotation, transform.scale, transform.parent), device))
+
+        let (first, rest) = points.split_first()?;
+        Some(rest.iter().fold(Bounds { min: *first, max: *first }, |bounds, point| Bounds {
+            min: cg::vec3(bounds.min.x.min(point.x), bounds.min.y.min(point.y), bounds.min.z.min(point.z)),
+            max: cg::vec3(bounds.max.x.max(point.x), bounds.max.y.max(point.y), bounds.max.z.max(point.z)),
+        }))
+    }
+
+    pub fn set_parent(&mut self, entity: Entity, parent: Option<Entity>, queue: &wgpu::Queue) -> bool {
+        {
+            let transforms = self.world.read_storage::<TransformComponent>();
+            if !transforms.contains(entity) || parent.map_or(false, |parent| !transforms.contains(parent)) {
+                return false;
+            }
         }
-        if let Some(material) = s_materials.get(&id) {
-            entity = entity.with(MaterialComponent::new(material.id, registry))
+        if let Some(parent) = parent {
+            if self.hierarchy(entity).contains(&parent) {
+                return false;
+            }
         }
-        if let Some(mesh) = s_meshes.get(&id) {
-            entity = entity.with(Mesh::new(mesh.id, registry))
+
+        self.detach(entity);
+        self.root_order.retain(|root| *root != entity);
+        if parent.is_none() {
+            self.root_order.push(entity);
         }
-        if let Some(light) = s_point_lights.get(&id) {
-            entity = entity.with(light.clone())
+
+        {
+            let mut transforms = self.world.write_storage::<TransformComponent>();
+            let parent_matrix = parent.map(|parent| {
+                let parent_transform = transforms.get_mut(parent).unwrap();
+                parent_transform.data.children.push(entity);
+                parent_transform.get_matrix()
+            });
+
+            let transform = transforms.get_mut(entity).unwrap();
+            transform.data.parent = parent;
+            match parent_matrix {
+                Some(parent_matrix) => transform.update_local_transformation(parent_matrix),
+                None => transform.update_local_transformation(cg::SquareMatrix::identity()),
+            }
         }
-        if let Some(light) = s_directional_lights.get(&id) {
-            entity = entity.with(light.clone())
-        };
 
-        entity.build();
+        self.update_transform(entity, queue);
+        true
+    }
+
+    // Applies a change to `entity` right away instead of waiting for the next `update`.
+    pub fn update_transform(&mut self, entity: Entity, queue: &wgpu::Queue) {
+        if let Some(transform) = self.world.write_storage::<TransformComponent>().get_mut(entity) {
+            transform.data.dirty = true;
+        }
+        self.update(queue);
     }
 
-    world
+    fn detach(&mut self, entity: Entity) {
+        let mut transforms = self.world.write_storage::<TransformComponent>();
+        if let Some(parent) = transforms.get_mut(entity).and_then(|transform| transform.data.parent.take()) {
+            if let Some(parent_transform) = transforms.get_mut(parent) {
+                parent_transform.data.children.retain(|&child| child != entity);
+            }
+        }
+    }
+
+    fn scene_file(&self, entities: &[Entity]) -> Result<SceneFile> {
+        let ids = self.world.read_storage::<Id>();
+        let unknown_components = self.world.read_storage::<UnknownComponents>();
+        let context = SaveContext::new(&self.world);
+
+        let mut s_entities = Vec::new();
+        for entity in entities {
+            let mut data = EntityData {
+                id: ids.get(*entity).unwrap().0,
+                components: BTreeMap::new(),
+            };
+
+            for component in self.components.iter() {
+                if let Some(value) = component.save(&self.world, *entity, &context)? {
+                    data.components.insert(component.name.to_string(), value);
+                }
+            }
+            if let Some(unknown) = unknown_components.get(*entity) {
+                for (name, value) in &unknown.0 {
+                    data.components.insert(name.clone(), value.clone());
+                }
+            }
+
+            s_entities.push(data);
+        }
+
+        Ok(SceneFile::new(s_entities))
+    }
+}
+
+fn load_world(path: &PathBuf, components: &ComponentRegistry, registry: &mut Registry, device: &wgpu::Device) -> Result<(World, Option<usize>)> {
+    let yaml = std::fs::read_to_string(path)?;
+    let mut world = specs::World::new();
+    world.register::<Id>();
+    world.register::<UnknownComponents>();
+    for component in components.iter() {
+        component.register(&mut world);
+    }
+
+    if yaml.trim().is_empty() {
+        world.create_entity().with(Id::new()).with(TransformComponent::new(Transform::default(), device, &registry.layouts)).with(Name::new("Light")).with(PointLight::new([0.0, 0.0, 0.0])).build();
+        return Ok((world, None));
+    }
+
+    let scene = SceneFile::parse(&yaml)?;
+    load_entities(&mut world, components, scene.entities, registry, device, false)?;
+
+    Ok((world, scene.skybox))
 }
 
-#[derive(Deserialize)]
-struct DeserializedId {
-    id: usize
-} 
-
-fn register_components(world: &mut World) {
-    world.register::<TransformComponent>();
-    world.register::<MaterialComponent>();
-    world.register::<Mesh>();
-    world.register::<Name>();
-    world.register::<PointLight>();
-    world.register::<DirectionalLight>();
-}
\ No newline at end of file
+// A scene whose skybox texture was deleted or renamed still opens, lit by the default skybox.
+fn load_skybox_or_default(asset: Option<usize>, registry: &Registry, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera) -> Result<Skybox> {
+    match load_skybox(asset, registry, device, queue, camera) {
+        Err(e) if asset.is_some() => {
+            println!("Using the default skybox: {}", e);
+            load_skybox(None, registry, device, queue, camera)
+        }
+        result => result,
+    }
+}
+
+fn load_skybox(asset: Option<usize>, registry: &Registry, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera) -> Result<Skybox> {
+    let path = match asset {
+        Some(id) => registry.metadata.get(&id).ok_or_else(|| anyhow!("skybox asset {} is not in the registry", id))?.file_path.clone(),
+        None => registry.res(DEFAULT_SKYBOX),
+    };
+    Skybox::new(device, queue, &registry.layouts, camera, &path)
+}
+
+// With `fresh_ids` the entities get new UUIDs, references between them are
+// still resolved through the ids stored in the data.
+fn load_entities(world: &mut World, components: &ComponentRegistry, data: Vec<EntityData>, registry: &mut Registry, device: &wgpu::Device, fresh_ids: bool) -> Result<Vec<Entity>> {
+    let mut entities: HashMap<Uuid, Entity> = HashMap::new();
+    let mut created = Vec::new();
+    for data in &data {
+        if entities.contains_key(&data.id) {
+            bail!("duplicate entity id {}", data.id);
+        }
+        let id = if fresh_ids { Id::new() } else { Id(data.id) };
+        let entity = world.create_entity().with(id).build();
+        entities.insert(data.id, entity);
+        created.push(entity);
+    }
+
+    let mut context = LoadContext::new(device, registry, &entities);
+    for (entity, data) in created.iter().zip(data) {
+        let mut unknown = UnknownComponents::default();
+
+        for (name, value) in data.components {
+            match components.get(&name) {
+                Some(component) => component.load(world, *entity, value, &mut context)?,
+                None => {
+                    unknown.0.insert(name, value);
+                }
+            }
+        }
+
+        if !unknown.0.is_empty() {
+            world.write_storage().insert(*entity, unknown)?;
+        }
+    }
+
+    break_cycles(world, &created);
+    Ok(created)
+}
+
+// Detaches any of `entities` whose parent chain loops back on itself.
+fn break_cycles(world: &World, entities: &[Entity]) {
+    let mut transforms = world.write_storage::<TransformComponent>();
+    for entity in entities {
+        let mut chain = vec![*entity];
+        let mut current = *entity;
+        while let Some(parent) = transforms.get(current).and_then(|transform| transform.data.parent) {
+            if chain.contains(&parent) {
+                println!("transform hierarchy has a cycle, detaching entity {}", current.id());
+                transforms.get_mut(current).unwrap().data.parent = None;
+                if let Some(parent_transform) = transforms.get_mut(parent) {
+                    parent_transform.data.children.retain(|child| *child != current);
+                }
+                break;
+            }
+            chain.push(parent);
+            current = parent;
+        }
+    }
+}
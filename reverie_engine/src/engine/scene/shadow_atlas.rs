@@ -0,0 +1,193 @@
+pub const MIN_TILE_SIZE: u32 = 64;
+const MAX_ATLAS_SIZE: u32 = 8192;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+impl Tile {
+    // Offset and scale of the tile in the atlas' uv space.
+    pub fn uv(&self, atlas_size: u32) -> [f32; 4] {
+        let scale = 1.0 / atlas_size as f32;
+        [self.x as f32 * scale, self.y as f32 * scale, self.size as f32 * scale, self.size as f32 * scale]
+    }
+}
+
+// One depth texture shared by many shadow maps. Tiles are square powers of two placed in Z-order
+// from largest to smallest, so each one lands aligned and the atlas fills without gaps.
+pub struct ShadowAtlas {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub size: u32,
+}
+
+impl ShadowAtlas {
+    pub fn new(device: &wgpu::Device, requests: &[u32], count: u32) -> Self {
+        let size = Self::size_for(device, requests, count);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow atlas"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            size,
+        }
+    }
+
+    // Big enough to hold `count` tiles of every requested size, up to what the device allows.
+    pub fn size_for(device: &wgpu::Device, requests: &[u32], count: u32) -> u32 {
+        let area: u64 = requests.iter().map(|&size| tile_size(size, MAX_ATLAS_SIZE).pow(2) as u64 * count as u64).sum();
+        let limit = device.limits().max_texture_dimension_2d.min(MAX_ATLAS_SIZE);
+        let mut size = MIN_TILE_SIZE;
+        while (size as u64).pow(2) < area && size < limit {
+            size *= 2;
+        }
+        size
+    }
+
+    // Requests are ordered most important first. When they don't all fit, the least important are
+    // halved down to MIN_TILE_SIZE before the next one is touched, and whatever still doesn't fit gets None.
+    pub fn allocate(&self, requests: &[u32], count: u32) -> Vec<Option<Vec<Tile>>> {
+        allocate(self.size, requests, count)
+    }
+}
+
+fn allocate(atlas_size: u32, requests: &[u32], count: u32) -> Vec<Option<Vec<Tile>>> {
+    let mut sizes: Vec<u32> = requests.iter().map(|&size| tile_size(size, atlas_size)).collect();
+    let capacity = (atlas_size as u64).pow(2);
+    let used = |sizes: &[u32]| sizes.iter().map(|&size| (size as u64).pow(2) * count as u64).sum::<u64>();
+
+    while used(&sizes) > capacity {
+        match sizes.iter().rposition(|&size| size > MIN_TILE_SIZE) {
+            Some(i) => sizes[i] /= 2,
+            None => break,
+        }
+    }
+    let mut fitting = sizes.len();
+    while used(&sizes[..fitting]) > capacity {
+        fitting -= 1;
+    }
+
+    let mut order: Vec<usize> = (0..fitting).collect();
+    order.sort_by(|a, b| sizes[*b].cmp(&sizes[*a]));
+
+    let mut tiles = vec![None; requests.len()];
+    let mut cursor: u64 = 0;
+    for i in order {
+        let size = sizes[i];
+        tiles[i] = Some((0..count).map(|_| {
+            let (x, y) = morton_decode(cursor);
+            cursor += (size as u64).pow(2);
+            Tile { x, y, size }
+        }).collect());
+    }
+    tiles
+}
+
+fn tile_size(requested: u32, atlas_size: u32) -> u32 {
+    requested.max(MIN_TILE_SIZE).next_power_of_two().min(atlas_size)
+}
+
+// Splits a Z-order index into the x and y held in its even and odd bits.
+fn morton_decode(index: u64) -> (u32, u32) {
+    let compact = |mut value: u64| {
+        value &= 0x5555_5555_5555_5555;
+        value = (value | (value >> 1)) & 0x3333_3333_3333_3333;
+        value = (value | (value >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+        value = (value | (value >> 4)) & 0x00ff_00ff_00ff_00ff;
+        value = (value | (value >> 8)) & 0x0000_ffff_0000_ffff;
+        value = (value | (value >> 16)) & 0x0000_0000_ffff_ffff;
+        value as u32
+    };
+    (compact(index), compact(index >> 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: &Tile, b: &Tile) -> bool {
+        a.x < b.x + b.size && b.x < a.x + a.size && a.y < b.y + b.size && b.y < a.y + a.size
+    }
+
+    fn assert_packed(atlas_size: u32, tiles: &[Option<Vec<Tile>>]) {
+        let tiles: Vec<&Tile> = tiles.iter().flatten().flatten().collect();
+        for (i, a) in tiles.iter().enumerate() {
+            assert!(a.x + a.size <= atlas_size && a.y + a.size <= atlas_size, "{:?} outside the atlas", a);
+            assert!(a.x % a.size == 0 && a.y % a.size == 0, "{:?} not aligned", a);
+            for b in &tiles[i + 1..] {
+                assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    fn sizes(tiles: &[Option<Vec<Tile>>]) -> Vec<Option<u32>> {
+        tiles.iter().map(|tiles| tiles.as_ref().map(|tiles| tiles[0].size)).collect()
+    }
+
+    #[test]
+    fn mixed_sizes_do_not_overlap() {
+        // Smaller requests come first so placing them in order would misalign the larger ones.
+        let requests = [64, 256, 128, 1024, 64, 512, 128];
+        let tiles = allocate(4096, &requests, 3);
+
+        assert_eq!(sizes(&tiles), requests.iter().map(|&size| Some(size)).collect::<Vec<_>>());
+        assert!(tiles.iter().all(|tiles| tiles.as_ref().unwrap().len() == 3));
+        assert_packed(4096, &tiles);
+    }
+
+    #[test]
+    fn requests_round_up_to_powers_of_two() {
+        let tiles = allocate(4096, &[1, 100, 300, 1500], 1);
+        assert_eq!(sizes(&tiles), vec![Some(64), Some(128), Some(512), Some(2048)]);
+
+        // Never bigger than the atlas itself.
+        assert_eq!(sizes(&allocate(1024, &[8192], 1)), vec![Some(1024)]);
+    }
+
+    #[test]
+    fn least_important_are_halved_first() {
+        // Four 512 tiles fill a 1024 atlas exactly, so the fifth shrinks to the minimum before the
+        // fourth gives up just enough room for it.
+        let tiles = allocate(1024, &[512, 512, 512, 512, 512], 1);
+        assert_eq!(sizes(&tiles), vec![Some(512), Some(512), Some(512), Some(256), Some(64)]);
+        assert_packed(1024, &tiles);
+
+        let tiles = allocate(512, &[512, 512], 1);
+        assert_eq!(sizes(&tiles), vec![Some(256), Some(64)]);
+        assert_packed(512, &tiles);
+    }
+
+    #[test]
+    fn whatever_does_not_fit_gets_none() {
+        // A 128 atlas holds four 64 tiles, with six faces a single light doesn't.
+        let tiles = allocate(128, &[64, 64, 64, 64, 64], 1);
+        assert_eq!(sizes(&tiles), vec![Some(64), Some(64), Some(64), Some(64), None]);
+        assert_packed(128, &tiles);
+
+        let tiles = allocate(128, &[64], 6);
+        assert_eq!(sizes(&tiles), vec![None]);
+    }
+
+    #[test]
+    fn uv_covers_the_tile() {
+        let tile = Tile { x: 512, y: 256, size: 256 };
+        assert_eq!(tile.uv(1024), [0.5, 0.25, 0.25, 0.25]);
+    }
+}
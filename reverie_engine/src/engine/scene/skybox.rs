@@ -1,114 +1,48 @@
-use std::{sync::mpsc::channel, path::PathBuf};
+use std::{sync::{mpsc::channel, Arc}, path::PathBuf};
 
+use anyhow::Result;
 use image::GenericImageView;
 use wgpu::util::DeviceExt;
 
 use crate::util::cast_slice;
 
-use super::super::{camera::Camera, renderer::Renderer};
+use super::{super::{camera::Camera, layouts::Layouts}, environment::Environment};
 use crate::engine::asset::texture::Texture;
 
+pub const DEFAULT_SKYBOX: &str = "textures/skyboxes/starfield/";
+const DIRECTIONS: [&str; 6] = ["left", "right", "up", "down", "front", "back"];
+const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const MAX_FACE_SIZE: u32 = 2048;
+
 pub struct Skybox {
     pub texture: Texture,
     pub vertex_buffer: wgpu::Buffer,
     proj_buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup
+    pub bind_group: wgpu::BindGroup,
+    pub environment: Arc<Environment>,
 }
 
 impl Skybox {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera, dir: &PathBuf) -> Self {
-        let directions = ["left", "right", "up", "down", "front", "back"];
-
-        let (tx, rx) = channel();
-
-        for i in &directions {
-            let path = dir.join(format!("{}.png", i));
-            let tx = tx.clone();
-            let path_clone = path.clone();
-            std::thread::spawn(move || {
-                let bytes = std::fs::read(&path_clone).expect(path_clone.to_str().unwrap());
-                let img = image::load_from_memory(bytes.as_slice()).unwrap();
-                
-                tx.send((path_clone, img)).unwrap();
-            });
-        }
-
-        let mut textures: [Option<image::DynamicImage>; 6] = [None, None, None, None, None, None];
-
-        for _ in 0..6 {
-            let (path, img) = rx.recv().unwrap();
-            let dir = path.file_stem().unwrap().to_str().unwrap();
-            match directions.iter().position(|d| d == &dir) {
-                Some(idx) => {
-                    textures[idx] = Some(img);
-                }
-                None => {
-                    println!("Directory not found: {}", dir);
-                }
-            }
-        }
-
-        let textures: [image::DynamicImage; 6] = [
-            textures[0].take().unwrap(),
-            textures[1].take().unwrap(),
-            textures[2].take().unwrap(),
-            textures[3].take().unwrap(),
-            textures[4].take().unwrap(),
-            textures[5].take().unwrap(),
-        ];
-
-        let dimensions = textures[0].dimensions();
-
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 6
+    // `path` is either a directory of six png faces or a single equirectangular panorama,
+    // usually an .hdr or .exr file.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, layouts: &Layouts, camera: &Camera, path: &PathBuf) -> Result<Self> {
+        let (skybox_texture, files, cache) = if path.is_dir() {
+            let files: Vec<PathBuf> = DIRECTIONS.iter().map(|direction| path.join(format!("{}.png", direction))).collect();
+            (load_faces(device, queue, &files), files, path.join("environment.cache"))
+        } else {
+            let mut cache = path.clone().into_os_string();
+            cache.push(".environment.cache");
+            (load_equirect(device, queue, path)?, vec![path.clone()], PathBuf::from(cache))
         };
 
-        let skybox_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("shadow"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-
-        for i in 0..6 {
-            queue.write_texture(
-                wgpu::ImageCopyTexture {
-                    aspect: wgpu::TextureAspect::All,
-                    texture: &skybox_texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d { x: 0, y: 0, z: i },
-                },
-                &textures[i as usize].to_rgba8(),
-                wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                    rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-                },
-                wgpu::Extent3d {
-                    width: dimensions.0,
-                    height: dimensions.1,
-                    depth_or_array_layers: 1,
-                },
-            );
-        }
-
         let skybox_view = skybox_texture.create_view(&wgpu::TextureViewDescriptor {
             label: Some("skybox view"),
-            format: Some(wgpu::TextureFormat::Rgba8UnormSrgb),
             dimension: Some(wgpu::TextureViewDimension::Cube),
-            aspect: wgpu::TextureAspect::All,
-            base_mip_level: 0,
-            mip_level_count: None,
-            base_array_layer: 0,
-            array_layer_count: None,
+            ..Default::default()
         });
 
+        let environment = Arc::new(Environment::new(device, queue, &skybox_texture, &files, &cache));
+
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("cubemap_sampler"),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -181,7 +115,7 @@ impl Skybox {
             camera_view.z.x, camera_view.z.y, camera_view.z.z,
         );
         let view_mat4 = cg::Matrix4::from(rotation);
-        let proj = camera.projection.calc_matrix();
+        let proj = camera.projection.perspective_matrix();
         let proj_view = proj * view_mat4;
 
         let proj_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -191,7 +125,7 @@ impl Skybox {
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &Renderer::get_skybox_layout(),
+            layout: &layouts.skybox,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -209,7 +143,7 @@ impl Skybox {
             label: Some("skybox_bind_group"),
         });
 
-        Self {
+        Ok(Self {
             texture: Texture {
                 texture: skybox_texture,
                 view: skybox_view,
@@ -218,7 +152,8 @@ impl Skybox {
             vertex_buffer,
             proj_buffer,
             bind_group,
-        }
+            environment,
+        })
     }
 
     pub fn update_projection(&self, camera: &Camera, queue: &wgpu::Queue) {
@@ -229,9 +164,206 @@ impl Skybox {
             camera_view.z.x, camera_view.z.y, camera_view.z.z,
         );
         let view_mat4 = cg::Matrix4::from(rotation);
-        let proj = camera.projection.calc_matrix();
+        let proj = camera.projection.perspective_matrix();
         let proj_view = proj * view_mat4;
 
         queue.write_buffer(&self.proj_buffer, 0, cast_slice(&[proj_view]));
     }
 }
+
+fn load_faces(device: &wgpu::Device, queue: &wgpu::Queue, files: &[PathBuf]) -> wgpu::Texture {
+    let (tx, rx) = channel();
+
+    for path in files {
+        let tx = tx.clone();
+        let path_clone = path.clone();
+        std::thread::spawn(move || {
+            let bytes = std::fs::read(&path_clone).expect(path_clone.to_str().unwrap());
+            let img = image::load_from_memory(bytes.as_slice()).unwrap();
+            
+            tx.send((path_clone, img)).unwrap();
+        });
+    }
+
+    let mut textures: [Option<image::DynamicImage>; 6] = [None, None, None, None, None, None];
+
+    for _ in 0..6 {
+        let (path, img) = rx.recv().unwrap();
+        let dir = path.file_stem().unwrap().to_str().unwrap();
+        match DIRECTIONS.iter().position(|d| d == &dir) {
+            Some(idx) => {
+                textures[idx] = Some(img);
+            }
+            None => {
+                println!("Directory not found: {}", dir);
+            }
+        }
+    }
+
+    let textures: [image::DynamicImage; 6] = [
+        textures[0].take().unwrap(),
+        textures[1].take().unwrap(),
+        textures[2].take().unwrap(),
+        textures[3].take().unwrap(),
+        textures[4].take().unwrap(),
+        textures[5].take().unwrap(),
+    ];
+
+    let dimensions = textures[0].dimensions();
+
+    let size = wgpu::Extent3d {
+        width: dimensions.0,
+        height: dimensions.1,
+        depth_or_array_layers: 6
+    };
+
+    let skybox_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("skybox"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    for i in 0..6 {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &skybox_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: i },
+            },
+            &textures[i as usize].to_rgba8(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
+                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
+            },
+            wgpu::Extent3d {
+                width: dimensions.0,
+                height: dimensions.1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    skybox_texture
+}
+
+// Uploads the panorama as full floats and resamples it into the six faces of a half float cube map.
+fn load_equirect(device: &wgpu::Device, queue: &wgpu::Queue, path: &PathBuf) -> Result<wgpu::Texture> {
+    let mut img = image::open(path)?;
+    let limit = device.limits().max_texture_dimension_2d;
+    if img.width() > limit || img.height() > limit {
+        img = img.resize(limit, limit, image::imageops::FilterType::Triangle);
+    }
+    let img = img.to_rgba32f();
+    let (width, height) = img.dimensions();
+
+    let source = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+        label: Some("equirect"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    }, cast_slice(img.as_raw()));
+    let source_view = source.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let face_size = (width / 4).clamp(1, MAX_FACE_SIZE);
+    let skybox_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("skybox"),
+        size: wgpu::Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let output_view = skybox_texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: HDR_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                },
+                count: None,
+            },
+        ],
+        label: Some("equirect_bind_group_layout"),
+    });
+
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("equirect_pipeline"),
+        layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("equirect_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        })),
+        module: &device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("equirect_shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../../shaders/equirect.wgsl").into()),
+        }),
+        entry_point: "main",
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&output_view),
+            },
+        ],
+        label: Some("equirect_bind_group"),
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("equirect_encoder"),
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("equirect_pass") });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups((face_size + 7) / 8, (face_size + 7) / 8, 6);
+    }
+    queue.submit([encoder.finish()]);
+
+    Ok(skybox_texture)
+}
@@ -0,0 +1,58 @@
+use std::collections::{HashMap, HashSet};
+
+use specs::{Entities, Entity, Join, System, WriteStorage};
+
+use crate::engine::components::transform::TransformComponent;
+
+// Recomputes world matrices top-down from each transform's parent. Only nodes that are
+// dirty, or sit below one that changed, are recomputed and uploaded to the GPU.
+pub struct TransformSystem<'q> {
+    queue: &'q wgpu::Queue,
+    pub changed: Vec<Entity>,
+}
+
+impl<'q> TransformSystem<'q> {
+    pub fn new(queue: &'q wgpu::Queue) -> Self {
+        Self {
+            queue,
+            changed: Vec::new(),
+        }
+    }
+}
+
+impl<'a, 'q> System<'a> for TransformSystem<'q> {
+    type SystemData = (Entities<'a>, WriteStorage<'a, TransformComponent>);
+
+    fn run(&mut self, (entities, mut transforms): Self::SystemData) {
+        let mut roots = Vec::new();
+        let mut children: HashMap<Entity, Vec<Entity>> = HashMap::new();
+        for (entity, transform) in (&entities, &transforms).join() {
+            match transform.data.parent.filter(|parent| transforms.contains(*parent)) {
+                Some(parent) => children.entry(parent).or_default().push(entity),
+                None => roots.push(entity),
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let mut stack: Vec<(Entity, Option<cg::Matrix4<f32>>, bool)> = roots.into_iter().rev().map(|root| (root, None, false)).collect();
+        while let Some((entity, parent_matrix, parent_changed)) = stack.pop() {
+            if !visited.insert(entity) {
+                continue;
+            }
+
+            let transform = transforms.get_mut(entity).unwrap();
+            let changed = parent_changed || transform.data.dirty;
+            if changed {
+                transform.data.update_matrix(parent_matrix);
+                transform.data.dirty = false;
+                transform.update_buffers(self.queue);
+                self.changed.push(entity);
+            }
+
+            let matrix = transform.get_matrix();
+            if let Some(children) = children.get(&entity) {
+                stack.extend(children.iter().rev().map(|child| (*child, Some(matrix), changed)));
+            }
+        }
+    }
+}
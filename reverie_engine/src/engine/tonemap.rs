@@ -0,0 +1,352 @@
+use serde::{Serialize, Deserialize};
+use wgpu::util::DeviceExt;
+
+use crate::util::cast_slice;
+
+// The scene is lit and shaded into this before being tone mapped, and post-processing works in it after.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const HISTOGRAM_BINS: u64 = 256;
+const TONEMAPPERS: [&str; 3] = ["ACES", "Reinhard", "AgX"];
+const EXPOSURE_MODES: [&str; 2] = ["Manual", "Automatic"];
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Tonemapper {
+    Aces,
+    Reinhard,
+    AgX,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ExposureMode {
+    Manual,
+    Automatic,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub tonemapper: Tonemapper,
+    pub exposure_mode: ExposureMode,
+    // In stops: the exposure itself when manual, a compensation on top of the metered one when automatic.
+    pub exposure: f32,
+    // Log2 luminance range the automatic exposure meters over.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    // Fraction of the remaining way to the metered luminance covered per second is 1 - e^-rate.
+    pub adaptation_rate: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::Aces,
+            exposure_mode: ExposureMode::Manual,
+            exposure: 0.0,
+            min_log_luminance: -8.0,
+            max_log_luminance: 8.0,
+            adaptation_rate: 1.5,
+        }
+    }
+}
+
+impl ToneMapping {
+    pub fn inspect(&mut self, ui: &imgui::Ui) -> bool {
+        let mut changed = false;
+
+        let mut tonemapper = self.tonemapper as usize;
+        if ui.combo_simple_string("Tonemapper", &mut tonemapper, &TONEMAPPERS) {
+            self.tonemapper = match tonemapper {
+                0 => Tonemapper::Aces,
+                1 => Tonemapper::Reinhard,
+                _ => Tonemapper::AgX,
+            };
+            changed = true;
+        }
+
+        let mut exposure_mode = self.exposure_mode as usize;
+        if ui.combo_simple_string("Exposure Mode", &mut exposure_mode, &EXPOSURE_MODES) {
+            self.exposure_mode = if exposure_mode == 0 { ExposureMode::Manual } else { ExposureMode::Automatic };
+            changed = true;
+        }
+
+        match self.exposure_mode {
+            ExposureMode::Manual => changed |= imgui::Drag::new("Exposure (EV)").speed(0.05).range(-16.0, 16.0).build(ui, &mut self.exposure),
+            ExposureMode::Automatic => {
+                changed |= imgui::Drag::new("Compensation (EV)").speed(0.05).range(-16.0, 16.0).build(ui, &mut self.exposure);
+                changed |= imgui::Drag::new("Min Log Luminance").speed(0.1).range(-20.0, self.max_log_luminance - 1.0).build(ui, &mut self.min_log_luminance);
+                changed |= imgui::Drag::new("Max Log Luminance").speed(0.1).range(self.min_log_luminance + 1.0, 20.0).build(ui, &mut self.max_log_luminance);
+                changed |= imgui::Drag::new("Adaptation Rate").speed(0.05).range(0.01, 100.0).build(ui, &mut self.adaptation_rate);
+            }
+        }
+        changed
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ToneMappingUniform {
+    exposure: f32,
+    tonemapper: u32,
+    automatic: u32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    pixel_count: u32,
+}
+
+// Exposes the HDR color target and maps it into the 0 to 1 range the post-process stack works in.
+// Automatic exposure builds a histogram of the frame's log luminance and eases towards its average over time.
+pub struct ToneMappingPass {
+    layout: wgpu::BindGroupLayout,
+    exposure_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    luminance_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    exposure_bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+    automatic: bool,
+}
+
+impl ToneMappingPass {
+    pub fn new(device: &wgpu::Device, hdr: &wgpu::Texture) -> Self {
+        let texture = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let uniform = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = |binding, visibility, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // The compute passes write the histogram and luminance, tone mapping only reads the luminance.
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(wgpu::ShaderStages::FRAGMENT),
+                uniform(wgpu::ShaderStages::FRAGMENT),
+                storage(4, wgpu::ShaderStages::FRAGMENT, true),
+            ],
+            label: Some("tone_mapping_layout"),
+        });
+        let exposure_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(wgpu::ShaderStages::COMPUTE),
+                uniform(wgpu::ShaderStages::COMPUTE),
+                storage(2, wgpu::ShaderStages::COMPUTE, false),
+                storage(3, wgpu::ShaderStages::COMPUTE, false),
+            ],
+            label: Some("exposure_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("tone_mapping_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let exposure_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("exposure_pipeline_layout"),
+            bind_group_layouts: &[&exposure_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("tone_mapping_shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/tonemap.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("tone mapping pass"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let compute_pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&exposure_pipeline_layout),
+            module: &shader,
+            entry_point,
+        });
+        let histogram_pipeline = compute_pipeline("build_histogram");
+        let average_pipeline = compute_pipeline("average_histogram");
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("tone_mapping_buffer"),
+            size: std::mem::size_of::<ToneMappingUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let histogram_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("luminance_histogram_buffer"),
+            contents: cast_slice(&[0u32; HISTOGRAM_BINS as usize]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let luminance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("average_luminance_buffer"),
+            contents: cast_slice(&[1.0f32]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let (bind_group, exposure_bind_group) = create_bind_groups(device, &layout, &exposure_layout, hdr, &uniform_buffer, &histogram_buffer, &luminance_buffer);
+
+        Self {
+            layout,
+            exposure_layout,
+            pipeline,
+            histogram_pipeline,
+            average_pipeline,
+            uniform_buffer,
+            histogram_buffer,
+            luminance_buffer,
+            bind_group,
+            exposure_bind_group,
+            size: (hdr.width(), hdr.height()),
+            automatic: false,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &wgpu::Texture) {
+        (self.bind_group, self.exposure_bind_group) = create_bind_groups(device, &self.layout, &self.exposure_layout, hdr, &self.uniform_buffer, &self.histogram_buffer, &self.luminance_buffer);
+        self.size = (hdr.width(), hdr.height());
+    }
+
+    // `dt` is the time since the last frame, None jumps straight to the metered exposure.
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &ToneMapping, dt: Option<f32>) {
+        self.automatic = settings.exposure_mode == ExposureMode::Automatic;
+        let uniform = ToneMappingUniform {
+            exposure: settings.exposure,
+            tonemapper: settings.tonemapper as u32,
+            automatic: self.automatic as u32,
+            min_log_luminance: settings.min_log_luminance,
+            log_luminance_range: (settings.max_log_luminance - settings.min_log_luminance).max(0.001),
+            adaptation: dt.map_or(1.0, |dt| 1.0 - (-dt * settings.adaptation_rate).exp()),
+            pixel_count: self.size.0 * self.size.1,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniform]));
+    }
+
+    pub fn draw(&self, view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder) {
+        if self.automatic {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("exposure_pass") });
+            pass.set_bind_group(0, &self.exposure_bind_group, &[]);
+            pass.set_pipeline(&self.histogram_pipeline);
+            pass.dispatch_workgroups((self.size.0 + 15) / 16, (self.size.1 + 15) / 16, 1);
+            pass.set_pipeline(&self.average_pipeline);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("tone_mapping_pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    }
+                }),
+            ],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+pub fn create_hdr_texture(device: &wgpu::Device, extent: &wgpu::Extent3d) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("hdr_texture"),
+        size: *extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn create_bind_groups(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, exposure_layout: &wgpu::BindGroupLayout, hdr: &wgpu::Texture, uniform_buffer: &wgpu::Buffer, histogram_buffer: &wgpu::Buffer, luminance_buffer: &wgpu::Buffer) -> (wgpu::BindGroup, wgpu::BindGroup) {
+    let view = hdr.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: luminance_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("tone_mapping_bind_group"),
+    });
+    let exposure_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: exposure_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: histogram_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: luminance_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("exposure_bind_group"),
+    });
+    (bind_group, exposure_bind_group)
+}
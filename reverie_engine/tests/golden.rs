@@ -0,0 +1,162 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use reverie_engine::engine::{
+    asset::texture::Texture,
+    camera::{Camera, Projection},
+    components::registry::ComponentRegistry,
+    context::Context,
+    registry::Registry,
+    renderer::Renderer,
+    scene::Scene,
+};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+// Per-pixel CIE76 distance above which a pixel counts as different, and the
+// fraction of differing pixels a render may have before the comparison fails.
+const MAX_DELTA_E: f32 = 5.0;
+const MAX_DIFFERING_PIXELS: f32 = 0.005;
+
+// A small project of its own, scenes covering meshes, shadowed point, directional and spot lights
+// and an HDR skybox, so editing the editor's scenes doesn't change the references.
+fn project_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn reference_dir() -> PathBuf {
+    project_dir().join("references")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+fn bless() -> bool {
+    std::env::var("REVERIE_BLESS").map(|value| value == "1").unwrap_or(false)
+}
+
+#[test]
+fn scenes_match_reference_images() {
+    let context = pollster::block_on(Context::new_headless(WIDTH, HEIGHT, true));
+    let extent = wgpu::Extent3d {
+        width: WIDTH,
+        height: HEIGHT,
+        depth_or_array_layers: 1,
+    };
+
+    let mut registry = Registry::new_headless(context.device.clone(), context.queue.clone(), project_dir());
+    let mut renderer = Renderer::new(&context.device, &registry.layouts, &context.config, &extent);
+    Texture::load_defaults(&context.device, &context.queue);
+
+    let mut camera = Camera::new(&context.device, &registry.layouts.camera, (0.0, 5.0, 10.0), cg::Deg(-90.0), cg::Deg(-20.0),
+        Projection::new(WIDTH, HEIGHT, cg::Deg(45.0), 0.1, 100.0));
+    camera.update_uniform();
+
+    let mut scenes: Vec<PathBuf> = std::fs::read_dir(project_dir().join("res").join("scenes")).unwrap()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |extension| extension == "revscene"))
+        .collect();
+    scenes.sort();
+    assert!(!scenes.is_empty(), "no scenes found in res/scenes");
+
+    std::fs::create_dir_all(output_dir()).unwrap();
+    if bless() {
+        std::fs::create_dir_all(reference_dir()).unwrap();
+    }
+
+    let components = Arc::new(ComponentRegistry::new());
+    let mut failures = Vec::new();
+    for path in scenes {
+        let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+        let mut scene = Scene::new(path.clone(), components.clone(), &mut registry, &context.device, &context.queue, &camera).unwrap();
+        let actual = renderer.render_to_image(&context.device, &context.queue, &mut scene, &camera, &extent).unwrap();
+
+        let reference_path = reference_dir().join(format!("{}.png", name));
+        if bless() {
+            actual.save(&reference_path).unwrap();
+            eprintln!("wrote reference image {}", reference_path.display());
+            continue;
+        }
+        if !reference_path.exists() {
+            let actual_path = output_dir().join(format!("{}_actual.png", name));
+            actual.save(&actual_path).unwrap();
+            failures.push(format!("{}: no reference image at {}, run with REVERIE_BLESS=1 to write it from {}", name, reference_path.display(), actual_path.display()));
+            continue;
+        }
+
+        let reference = image::open(&reference_path).unwrap().to_rgba8();
+        if let Some(failure) = compare(&name, &actual, &reference) {
+            failures.push(failure);
+        }
+    }
+
+    assert!(failures.is_empty(), "golden image mismatches:\n{}", failures.join("\n"));
+}
+
+fn compare(name: &str, actual: &image::RgbaImage, reference: &image::RgbaImage) -> Option<String> {
+    let actual_path = output_dir().join(format!("{}_actual.png", name));
+
+    if actual.dimensions() != reference.dimensions() {
+        actual.save(&actual_path).unwrap();
+        return Some(format!("{}: size {:?} does not match reference {:?}", name, actual.dimensions(), reference.dimensions()));
+    }
+
+    let mut diff = image::RgbaImage::new(actual.width(), actual.height());
+    let mut differing = 0;
+    let mut max_delta = 0.0f32;
+
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let expected = reference.get_pixel(x, y);
+        let delta = delta_e(pixel, expected);
+        max_delta = max_delta.max(delta);
+
+        if delta > MAX_DELTA_E {
+            differing += 1;
+            diff.put_pixel(x, y, image::Rgba([255, 0, 0, 255]));
+        } else {
+            let luma = ((pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 12) as u8;
+            diff.put_pixel(x, y, image::Rgba([luma, luma, luma, 255]));
+        }
+    }
+
+    let fraction = differing as f32 / (actual.width() * actual.height()) as f32;
+    if fraction <= MAX_DIFFERING_PIXELS {
+        return None;
+    }
+
+    let diff_path = output_dir().join(format!("{}_diff.png", name));
+    actual.save(&actual_path).unwrap();
+    diff.save(&diff_path).unwrap();
+
+    Some(format!(
+        "{}: {:.2}% of pixels differ (max delta E {:.1}), see {} and {}",
+        name, fraction * 100.0, max_delta, actual_path.display(), diff_path.display(),
+    ))
+}
+
+fn delta_e(a: &image::Rgba<u8>, b: &image::Rgba<u8>) -> f32 {
+    let a = to_lab(a);
+    let b = to_lab(b);
+
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn to_lab(pixel: &image::Rgba<u8>) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}